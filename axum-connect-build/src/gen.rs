@@ -1,55 +1,289 @@
-use proc_macro2::TokenStream;
+use std::{collections::HashMap, fmt, rc::Rc};
+
+use convert_case::{Case, Casing};
+use proc_macro2::{Ident, TokenStream};
 use prost_build::{Method, Service, ServiceGenerator};
 use quote::{format_ident, quote};
 use syn::parse_str;
 
+/// Extra code generated with each service, for build scripts that want more than the generator
+/// does (tracing wrappers, OpenAPI annotations, ...) without forking it. Both are handed the
+/// buffer the service was just written to, anything written to it lands in the same module.
+pub trait ServiceGenHook: fmt::Debug {
+    /// Called once per service, after its code is generated.
+    fn service(&self, _service: &Service, _buf: &mut String) {}
+
+    /// Called once per method of the service, after `service`.
+    fn method(&self, _service: &Service, _method: &Method, _buf: &mut String) {}
+}
+
 #[derive(Default)]
-pub struct AxumConnectServiceGenerator {}
+pub struct AxumConnectServiceGenerator {
+    sunsets: HashMap<String, String>,
+    scopes: HashMap<String, Vec<String>>,
+    service_features: bool,
+    hooks: Vec<Rc<dyn ServiceGenHook>>,
+}
 
 impl AxumConnectServiceGenerator {
     pub fn new() -> Self {
         Default::default()
     }
 
+    /// Sunset dates (HTTP-dates) keyed by method path, ex. `/hello.HelloWorldService/SayHello`.
+    pub fn with_sunsets(mut self, sunsets: HashMap<String, String>) -> Self {
+        self.sunsets = sunsets;
+        self
+    }
+
+    /// The `(axum_connect.scopes)` option of each method, keyed by method path.
+    pub fn with_scopes(mut self, scopes: HashMap<String, Vec<String>>) -> Self {
+        self.scopes = scopes;
+        self
+    }
+
+    /// Gate each service behind a `svc-<service-name>` cargo feature.
+    pub fn with_service_features(mut self, enabled: bool) -> Self {
+        self.service_features = enabled;
+        self
+    }
+
+    /// Hooks run for every service, in order.
+    pub fn with_hooks(mut self, hooks: Vec<Rc<dyn ServiceGenHook>>) -> Self {
+        self.hooks = hooks;
+        self
+    }
+
     fn generate_service(&mut self, service: Service, buf: &mut String) {
+        // Generating takes the service apart, hooks get a copy.
+        let hooked = (!self.hooks.is_empty()).then(|| service.clone());
+
         // Service struct
         let service_name = format_ident!("{}", service.name);
-        let methods =
-            service.methods.into_iter().filter_map(|m| {
-                // Don't currently support client streaming. Will-do soon.
-                if m.client_streaming {
-                    return None;
-                }
-
-                Some(self.generate_service_method(
-                    m,
-                    &format!("{}.{}", service.package, service.proto_name),
-                ))
-            });
+        let builder_name = format_ident!("{}Builder", service.name);
+        let path_root = format!("{}.{}", service.package, service.proto_name);
+        let (methods, builder_methods): (Vec<_>, Vec<_>) = service
+            .methods
+            .into_iter()
+            .map(|m| self.generate_service_method(m, &service_name, &path_root))
+            .unzip();
+        let gate = if self.service_features {
+            let feature = format!("svc-{}", service.proto_name.to_case(Case::Kebab));
+            quote! { #[cfg(feature = #feature)] }
+        } else {
+            quote! {}
+        };
 
         buf.push_str(
             quote! {
+                #gate
                 pub struct #service_name;
 
-                #[allow(dead_code)]
+                #gate
+                #[allow(dead_code, deprecated)]
                 impl #service_name {
+                    /// Register several methods of this service at once, wrapped in layers that
+                    /// apply to this service only. Pass the result of `build()` to `.rpc(...)`.
+                    pub fn builder<S>() -> #builder_name<S>
+                    where
+                        S: Clone + Send + Sync + 'static,
+                    {
+                        #builder_name {
+                            inner: axum_connect::router::RpcServiceBuilder::new(),
+                        }
+                    }
+
+                    /// Answers calls to methods of this service that aren't registered with an
+                    /// `unimplemented` Connect error instead of a 404.
+                    pub fn unimplemented_fallback<S>(
+                    ) -> impl FnOnce(axum::Router<S>) -> axum_connect::router::RpcRouter<S>
+                    where
+                        S: Clone + Send + Sync + 'static,
+                    {
+                        axum_connect::router::unimplemented_service(#path_root)
+                    }
+
                     #(#methods)*
                 }
+
+                #gate
+                pub struct #builder_name<S> {
+                    inner: axum_connect::router::RpcServiceBuilder<S>,
+                }
+
+                #gate
+                #[allow(dead_code, deprecated)]
+                impl<S> #builder_name<S>
+                where
+                    S: Clone + Send + Sync + 'static,
+                {
+                    pub fn layer<L>(self, layer: L) -> Self
+                    where
+                        axum_connect::router::RpcServiceBuilder<S>:
+                            axum_connect::router::RpcServiceLayer<L>,
+                    {
+                        Self {
+                            inner: self.inner.layer(layer),
+                        }
+                    }
+
+                    pub fn build(
+                        self,
+                    ) -> impl FnOnce(axum::Router<S>) -> axum_connect::router::RpcRouter<S> {
+                        self.inner.build()
+                    }
+
+                    pub fn unimplemented_fallback(self) -> Self {
+                        Self {
+                            inner: self.inner.rpc(#service_name::unimplemented_fallback()),
+                        }
+                    }
+
+                    #(#builder_methods)*
+                }
             }
             .to_string()
             .as_str(),
         );
+
+        if let Some(service) = hooked {
+            for hook in &self.hooks {
+                hook.service(&service, buf);
+                for method in &service.methods {
+                    hook.method(&service, method, buf);
+                }
+            }
+        }
     }
 
-    fn generate_service_method(&mut self, method: Method, path_root: &str) -> TokenStream {
+    fn generate_service_method(
+        &mut self,
+        method: Method,
+        service_name: &Ident,
+        path_root: &str,
+    ) -> (TokenStream, TokenStream) {
         let method_name = format_ident!("{}", method.name);
         let method_name_unary_get = format_ident!("{}_unary_get", method.name);
         let input_type: syn::Type = parse_str(&method.input_type).unwrap();
         let output_type: syn::Type = parse_str(&method.output_type).unwrap();
         let path = format!("/{}/{}", path_root, method.proto_name);
+        let proto_name = &method.proto_name;
+        let descriptor_name =
+            format_ident!("{}", method.name.trim_start_matches("r#").to_uppercase());
+        let kind = match (method.client_streaming, method.server_streaming) {
+            (false, false) => quote! { axum_connect::descriptor::MethodKind::Unary },
+            (false, true) => quote! { axum_connect::descriptor::MethodKind::ServerStreaming },
+            (true, false) => quote! { axum_connect::descriptor::MethodKind::ClientStreaming },
+            (true, true) => quote! { axum_connect::descriptor::MethodKind::BidiStreaming },
+        };
+        let deprecated = method.options.deprecated.unwrap_or(false);
+        // Registering a deprecated method warns, like calling one would.
+        let deprecation = if deprecated {
+            quote! { #[deprecated] }
+        } else {
+            quote! {}
+        };
+        // NO_SIDE_EFFECTS or IDEMPOTENT.
+        let idempotent = method.options.idempotency_level.unwrap_or(0) != 0;
+        let sunset = match self.sunsets.get(&path) {
+            Some(sunset) => quote! { Some(#sunset) },
+            None => quote! { None },
+        };
+        let scopes = self.scopes.get(&path).cloned().unwrap_or_default();
+        let descriptor = quote! {
+            pub const #descriptor_name: axum_connect::descriptor::MethodDescriptor =
+                axum_connect::descriptor::MethodDescriptor {
+                    service: #path_root,
+                    method: #proto_name,
+                    path: #path,
+                    kind: #kind,
+                    deprecated: #deprecated,
+                    idempotent: #idempotent,
+                    sunset: #sunset,
+                    scopes: &[#(#scopes),*],
+                };
+        };
 
-        if method.server_streaming {
+        // Client and bidi streaming aren't supported over HTTP yet (only over WebSockets, see
+        // `axum_connect::websocket`). Registering these at least answers calls with a proper
+        // Connect error instead of a 404.
+        if method.client_streaming {
+            let builder_methods = quote! {
+                #deprecation
+                pub fn #method_name(self) -> Self {
+                    Self {
+                        inner: self.inner.rpc(#service_name::#method_name()),
+                    }
+                }
+            };
+
+            let methods = quote! {
+                #descriptor
+
+                #deprecation
+                pub fn #method_name<S>(
+                ) -> impl FnOnce(axum::Router<S>) -> axum_connect::router::RpcRouter<S>
+                where
+                    S: Clone + Send + Sync + 'static,
+                {
+                    move |router: axum::Router<S>| {
+                        axum_connect::descriptor::record_registration(Self::#descriptor_name);
+                        router.route(
+                            #path,
+                            axum::routing::post(|
+                                mut request: axum::http::Request<axum::body::Body>
+                            | async move {
+                                request.extensions_mut().insert(Self::#descriptor_name);
+                                let origin = axum_connect::cors::allowed_origin(&request);
+                                axum_connect::cors::allow_origin(
+                                    origin,
+                                    axum_connect::handler::unsupported_streaming(request).await,
+                                )
+                            })
+                            .options(|request: axum::http::Request<axum::body::Body>| async move {
+                                axum_connect::cors::preflight("POST, OPTIONS", &request)
+                            })
+                            .fallback(|request: axum::http::Request<axum::body::Body>| {
+                                axum_connect::handler::method_not_allowed("POST, OPTIONS", request)
+                            }),
+                        )
+                    }
+                }
+            };
+
+            return (methods, builder_methods);
+        }
+
+        // Preflights and other methods are answered with the methods the registration actually
+        // serves. axum panics on overlapping method routes (and on merging two fallbacks), so the
+        // GET variant serves POST too, rather than being registered next to the POST one.
+        let allow_methods = "POST, OPTIONS";
+        let allow_methods_unary_get = "POST, GET, OPTIONS";
+
+        let builder_method = |name: &Ident, handler_trait: TokenStream| {
             quote! {
+                #deprecation
+                pub fn #name<T, H>(self, handler: H) -> Self
+                where
+                    H: #handler_trait<#input_type, #output_type, T, S>,
+                    T: 'static,
+                {
+                    Self {
+                        inner: self.inner.rpc(#service_name::#name(handler)),
+                    }
+                }
+            }
+        };
+        let unary = quote! { axum_connect::handler::RpcHandlerUnary };
+        let stream = quote! { axum_connect::handler::RpcHandlerStream };
+
+        if method.server_streaming {
+            let builder_methods = builder_method(&method_name, stream);
+
+            let methods = quote! {
+                #descriptor
+
+                #deprecation
                 pub fn #method_name<T, H, S>(
                     handler: H
                 ) -> impl FnOnce(axum::Router<S>) -> axum_connect::router::RpcRouter<S>
@@ -59,20 +293,42 @@ impl AxumConnectServiceGenerator {
                     S: Clone + Send + Sync + 'static,
                 {
                     move |router: axum::Router<S>| {
+                        axum_connect::descriptor::record_registration(Self::#descriptor_name);
                         router.route(
                             #path,
                             axum::routing::post(|
                                 axum::extract::State(state): axum::extract::State<S>,
-                                request: axum::http::Request<axum::body::Body>
+                                mut request: axum::http::Request<axum::body::Body>
                             | async move {
-                                handler.call(request, state).await
+                                request.extensions_mut().insert(Self::#descriptor_name);
+                                request.extensions_mut().insert(
+                                    axum_connect::redact::RpcMessageLog::of::<#input_type, #output_type>(),
+                                );
+                                let origin = axum_connect::cors::allowed_origin(&request);
+                                axum_connect::cors::allow_origin(origin, handler.call(request, state).await)
+                            })
+                            .options(|request: axum::http::Request<axum::body::Body>| async move {
+                                axum_connect::cors::preflight(#allow_methods, &request)
+                            })
+                            .fallback(|request: axum::http::Request<axum::body::Body>| {
+                                axum_connect::handler::method_not_allowed(#allow_methods, request)
                             }),
                         )
                     }
                 }
-            }
+            };
+
+            (methods, builder_methods)
         } else {
-            quote! {
+            let builder_methods = [
+                builder_method(&method_name, unary.clone()),
+                builder_method(&method_name_unary_get, unary),
+            ];
+
+            let methods = quote! {
+                #descriptor
+
+                #deprecation
                 pub fn #method_name<T, H, S>(
                     handler: H
                 ) -> impl FnOnce(axum::Router<S>) -> axum_connect::router::RpcRouter<S>
@@ -82,18 +338,31 @@ impl AxumConnectServiceGenerator {
                     S: Clone + Send + Sync + 'static,
                 {
                     move |router: axum::Router<S>| {
+                        axum_connect::descriptor::record_registration(Self::#descriptor_name);
                         router.route(
                             #path,
                             axum::routing::post(|
                                 axum::extract::State(state): axum::extract::State<S>,
-                                request: axum::http::Request<axum::body::Body>
+                                mut request: axum::http::Request<axum::body::Body>
                             | async move {
-                                handler.call(request, state).await
+                                request.extensions_mut().insert(Self::#descriptor_name);
+                                request.extensions_mut().insert(
+                                    axum_connect::redact::RpcMessageLog::of::<#input_type, #output_type>(),
+                                );
+                                let origin = axum_connect::cors::allowed_origin(&request);
+                                axum_connect::cors::allow_origin(origin, handler.call(request, state).await)
+                            })
+                            .options(|request: axum::http::Request<axum::body::Body>| async move {
+                                axum_connect::cors::preflight(#allow_methods, &request)
+                            })
+                            .fallback(|request: axum::http::Request<axum::body::Body>| {
+                                axum_connect::handler::method_not_allowed(#allow_methods, request)
                             }),
                         )
                     }
                 }
 
+                #deprecation
                 pub fn #method_name_unary_get<T, H, S>(
                     handler: H
                 ) -> impl FnOnce(axum::Router<S>) -> axum_connect::router::RpcRouter<S>
@@ -103,18 +372,38 @@ impl AxumConnectServiceGenerator {
                     S: Clone + Send + Sync + 'static,
                 {
                     move |router: axum::Router<S>| {
+                        axum_connect::descriptor::record_registration(Self::#descriptor_name);
+                        let call = |
+                            axum::extract::State(state): axum::extract::State<S>,
+                            mut request: axum::http::Request<axum::body::Body>
+                        | async move {
+                            request.extensions_mut().insert(Self::#descriptor_name);
+                            request.extensions_mut().insert(
+                                axum_connect::redact::RpcMessageLog::of::<#input_type, #output_type>(),
+                            );
+                            let origin = axum_connect::cors::allowed_origin(&request);
+                            axum_connect::cors::allow_origin(origin, handler.call(request, state).await)
+                        };
+
                         router.route(
                             #path,
-                            axum::routing::get(|
-                                axum::extract::State(state): axum::extract::State<S>,
-                                request: axum::http::Request<axum::body::Body>
-                            | async move {
-                                handler.call(request, state).await
-                            }),
+                            axum::routing::post(call.clone())
+                                .get(call)
+                                .options(|request: axum::http::Request<axum::body::Body>| async move {
+                                    axum_connect::cors::preflight(#allow_methods_unary_get, &request)
+                                })
+                                .fallback(|request: axum::http::Request<axum::body::Body>| {
+                                    axum_connect::handler::method_not_allowed(
+                                        #allow_methods_unary_get,
+                                        request,
+                                    )
+                                }),
                         )
                     }
                 }
-            }
+            };
+
+            (methods, quote! { #(#builder_methods)* })
         }
     }
 }
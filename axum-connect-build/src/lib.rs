@@ -1,15 +1,39 @@
 use std::{
     cell::RefCell,
+    collections::HashMap,
     env,
     io::{BufWriter, Write},
     ops::Deref,
     path::{Path, PathBuf},
+    process::Command,
     rc::Rc,
 };
 
 use gen::AxumConnectServiceGenerator;
 
+pub use gen::ServiceGenHook;
+pub use prost_build::{Method, Service};
+
+// Code generators matching the prost version axum-connect was built with, see Cargo.toml.
+#[cfg(all(feature = "prost-0-12", feature = "prost-0-13"))]
+compile_error!(
+    "the `prost-0-12` and `prost-0-13` features are mutually exclusive, disable default features \
+    to use `prost-0-13`"
+);
+#[cfg(not(any(feature = "prost-0-12", feature = "prost-0-13")))]
+compile_error!("enable one of the `prost-0-12` or `prost-0-13` features");
+#[cfg(all(feature = "prost-0-13", not(feature = "prost-0-12")))]
+extern crate pbjson_build_0_7 as pbjson_build;
+#[cfg(all(feature = "prost-0-13", not(feature = "prost-0-12")))]
+extern crate prost_build_0_13 as prost_build;
+
 mod gen;
+mod manifest;
+mod names;
+mod options;
+mod redact;
+mod schema;
+mod setters;
 
 #[derive(Clone, Debug)]
 pub struct AxumConnectGenSettings {
@@ -17,6 +41,49 @@ pub struct AxumConnectGenSettings {
     pub inputs: Vec<PathBuf>,
     pub protoc_args: Vec<String>,
     pub protoc_version: Option<String>,
+    /// Sunset dates for methods that are going away, keyed by method path (ex.
+    /// `/hello.HelloWorldService/SayHello`). Values are HTTP-dates (ex.
+    /// `Sat, 01 Mar 2025 00:00:00 GMT`) and are sent as-is in the `Sunset` response header.
+    pub sunsets: HashMap<String, String>,
+    /// Protobuf paths (ex. `.upload.Chunk.data`, or `.` for everything) whose `bytes` fields are
+    /// generated as `Bytes` instead of `Vec<u8>`. Binary requests are decoded straight out of the
+    /// request body, so these fields borrow from it rather than being copied. Worth it for
+    /// messages that carry large blobs (uploads), see prost's `Config::bytes`.
+    pub bytes: Vec<String>,
+    /// Fail JSON requests that contain fields the message doesn't define with `invalid_argument`,
+    /// instead of ignoring them (which is what the proto3 JSON mapping asks of parsers, so that
+    /// older servers accept requests from newer clients). For strict API deployments.
+    pub reject_unknown_fields: bool,
+    /// Name fields in JSON responses as they are in the proto file (`user_id`) instead of
+    /// lowerCamelCase (`userId`). Requests are accepted with either.
+    pub json_proto_field_names: bool,
+    /// Include fields that are at their default value (zero, empty, ...) in JSON responses,
+    /// instead of leaving them out.
+    pub json_emit_defaults: bool,
+    /// Encode enums in JSON responses as their number instead of their name. Requests are accepted
+    /// with either.
+    pub json_enums_as_integers: bool,
+    /// Gate each generated service behind a cargo feature of the crate running codegen, named
+    /// `svc-` and the service's name in kebab-case (ex. `svc-hello-world-service`). Lets binaries
+    /// that only serve some services of a large proto tree skip compiling the rest. Messages are
+    /// generated either way. Declare the features in your `Cargo.toml`.
+    pub service_features: bool,
+    /// Write a JSON manifest of the services (methods, paths, streaming kinds, request and
+    /// response types, ...) to `axum_connect_manifest.json` in `OUT_DIR`, for deploy tooling,
+    /// gateways and client generators.
+    pub manifest: bool,
+    /// Write a JSON Schema for the request and response message of every method to
+    /// `json_schema/<message>.json` in `OUT_DIR` (ex. `json_schema/hello.HelloRequest.json`), for
+    /// non-protobuf consumers and validating gateways. They follow the same JSON mapping as the
+    /// generated serde impls, `json_proto_field_names` included.
+    pub json_schemas: bool,
+    /// Generate a `with_*` setter for every field of every message, so messages with many
+    /// (optional) fields can be built in one expression:
+    /// `HelloRequest::default().with_name("x").with_tags(["a", "b"])`. Setters take enums as the
+    /// enum rather than an `i32`, and messages and optional scalars without the `Some`.
+    pub message_setters: bool,
+    /// Extra code to generate with each service, see `ServiceGenHook`.
+    pub hooks: Vec<Rc<dyn ServiceGenHook>>,
 }
 
 impl Default for AxumConnectGenSettings {
@@ -26,18 +93,42 @@ impl Default for AxumConnectGenSettings {
             inputs: Default::default(),
             protoc_args: Default::default(),
             protoc_version: Some("22.3".to_string()),
+            sunsets: Default::default(),
+            bytes: Default::default(),
+            reject_unknown_fields: false,
+            json_proto_field_names: false,
+            json_emit_defaults: false,
+            json_enums_as_integers: false,
+            service_features: false,
+            manifest: false,
+            json_schemas: false,
+            message_setters: false,
+            hooks: Default::default(),
         }
     }
 }
 
 impl AxumConnectGenSettings {
+    /// Settings for the protos under `path`, see `add_directory_recursive`.
     pub fn from_directory_recursive<P>(path: P) -> anyhow::Result<Self>
     where
         P: Into<PathBuf>,
     {
-        let path = path.into();
         let mut settings = Self::default();
-        settings.includes.push(path.clone());
+        settings.add_directory_recursive(path)?;
+
+        Ok(settings)
+    }
+
+    /// Adds a proto root: `path` is used as an import path, and every `.proto` file under it is
+    /// generated. Call it once per root to merge several (ex. shared protos and the crate's own).
+    /// Relative paths are relative to the crate being built, not the working directory.
+    pub fn add_directory_recursive<P>(&mut self, path: P) -> anyhow::Result<&mut Self>
+    where
+        P: Into<PathBuf>,
+    {
+        let path = manifest_relative(path);
+        self.add_include(path.clone());
 
         // Recursively add all files that end in ".proto" to the inputs.
         let mut dirs = vec![path];
@@ -48,13 +139,61 @@ impl AxumConnectGenSettings {
                 if path.is_dir() {
                     dirs.push(path.clone());
                 } else if path.extension().map(|ext| ext == "proto").unwrap_or(false) {
-                    settings.inputs.push(path);
+                    self.inputs.push(path);
                 }
             }
         }
 
-        Ok(settings)
+        Ok(self)
     }
+
+    /// Adds an import path only, for protos the inputs import but that aren't generated
+    /// themselves (ex. a vendored copy of googleapis). Relative paths are relative to the crate
+    /// being built.
+    pub fn add_include<P>(&mut self, path: P) -> &mut Self
+    where
+        P: Into<PathBuf>,
+    {
+        let path = manifest_relative(path);
+        if !self.includes.contains(&path) {
+            self.includes.push(path);
+        }
+
+        self
+    }
+}
+
+/// `path` relative to the directory of the crate being built (`CARGO_MANIFEST_DIR`) rather than
+/// the working directory. Absolute paths are returned as-is.
+pub fn manifest_relative<P>(path: P) -> PathBuf
+where
+    P: Into<PathBuf>,
+{
+    let path = path.into();
+    match env::var_os("CARGO_MANIFEST_DIR") {
+        Some(dir) if path.is_relative() => PathBuf::from(dir).join(path),
+        _ => path,
+    }
+}
+
+/// `path` relative to the root of the workspace the crate being built is in (the closest
+/// directory above it with a `Cargo.toml` that has a `[workspace]`), for protos shared by the
+/// crates of a workspace. Crates outside of a workspace are their own root.
+pub fn workspace_relative<P>(path: P) -> PathBuf
+where
+    P: Into<PathBuf>,
+{
+    let path = path.into();
+    let manifest_dir = manifest_relative("");
+    let root = manifest_dir
+        .ancestors()
+        .find(|dir| {
+            std::fs::read_to_string(dir.join("Cargo.toml"))
+                .is_ok_and(|manifest| manifest.lines().any(|line| line.trim() == "[workspace]"))
+        })
+        .unwrap_or(manifest_dir.as_path());
+
+    root.join(path)
 }
 
 pub fn axum_connect_codegen(settings: AxumConnectGenSettings) -> anyhow::Result<()> {
@@ -72,13 +211,60 @@ pub fn axum_connect_codegen(settings: AxumConnectGenSettings) -> anyhow::Result<
 
     let descriptor_path = PathBuf::from(env::var("OUT_DIR").unwrap()).join("proto_descriptor.bin");
 
+    // Custom options (ex. `(axum_connect.redact)`), for protos to import.
+    let mut includes = settings.includes.clone();
+    includes.push(options::write_options_proto(
+        PathBuf::from(env::var("OUT_DIR").unwrap()).join("axum_connect_include"),
+    )?);
+
+    // prost hands service generators the standard method options only, custom ones are read off
+    // of a descriptor set of our own.
+    let descriptor_set = compile_descriptor_set(&settings, &includes, &descriptor_path)?;
+    let scopes = options::method_scopes(&descriptor_set)?;
+    rerun_if_imports_changed(&descriptor_set, &settings.includes)?;
+
+    if settings.manifest {
+        manifest::write_manifest(
+            &descriptor_set,
+            &scopes,
+            &PathBuf::from(env::var("OUT_DIR").unwrap()).join("axum_connect_manifest.json"),
+        )?;
+    }
+
+    if settings.json_schemas {
+        schema::write_schemas(
+            &descriptor_set,
+            settings.json_proto_field_names,
+            &PathBuf::from(env::var("OUT_DIR").unwrap()).join("json_schema"),
+        )?;
+    }
+
     let mut conf = prost_build::Config::new();
 
     // Standard prost configuration
     conf.compile_well_known_types();
     conf.file_descriptor_set_path(&descriptor_path);
     conf.extern_path(".google.protobuf", "::axum_connect::pbjson_types");
-    conf.service_generator(Box::new(AxumConnectServiceGenerator::new()));
+    // `prost::Name` impls, for packing messages into `Any`s (and error details).
+    conf.enable_type_names();
+    conf.service_generator(Box::new(
+        AxumConnectServiceGenerator::new()
+            .with_sunsets(settings.sunsets.clone())
+            .with_scopes(scopes)
+            .with_service_features(settings.service_features)
+            .with_hooks(settings.hooks.clone()),
+    ));
+
+    conf.bytes(&settings.bytes);
+
+    // prost marks deprecated fields (and enum values) only.
+    let (deprecated_messages, deprecated_enums) = options::deprecated_types(&descriptor_set)?;
+    for message in deprecated_messages {
+        conf.message_attribute(message, "#[deprecated]");
+    }
+    for e in deprecated_enums {
+        conf.enum_attribute(e, "#[deprecated]");
+    }
 
     // Arg configuration
     for arg in settings.protoc_args {
@@ -86,11 +272,16 @@ pub fn axum_connect_codegen(settings: AxumConnectGenSettings) -> anyhow::Result<
     }
 
     // File configuration
-    conf.compile_protos(&settings.inputs, &settings.includes)
-        .unwrap();
+    conf.compile_protos(&settings.inputs, &includes).unwrap();
 
     // Use pbjson to generate the Serde impls, and inline them with the Prost files.
     let descriptor_set = std::fs::read(descriptor_path)?;
+    let redactions = redact::generate(&descriptor_set)?;
+    let setters = if settings.message_setters {
+        setters::generate(&descriptor_set, &settings.bytes)?
+    } else {
+        Default::default()
+    };
     let mut output: PathBuf = PathBuf::from(env::var("OUT_DIR").unwrap());
     output.push("FILENAME");
 
@@ -98,17 +289,32 @@ pub fn axum_connect_codegen(settings: AxumConnectGenSettings) -> anyhow::Result<
     let files = Rc::new(RefCell::new(vec![]));
 
     let files_c = files.clone();
-    let writers = pbjson_build::Builder::new()
+    let mut builder = pbjson_build::Builder::new();
+    builder
         .register_descriptors(&descriptor_set)?
         .extern_path(".google.protobuf", "::axum_connect::pbjson_types")
-        .generate(&["."], move |package| {
-            output.set_file_name(format!("{}.rs", package));
-            files_c.deref().borrow_mut().push(output.clone());
+        .bytes(&settings.bytes);
+    if !settings.reject_unknown_fields {
+        builder.ignore_unknown_fields();
+    }
+    if settings.json_proto_field_names {
+        builder.preserve_proto_field_names();
+    }
+    if settings.json_emit_defaults {
+        builder.emit_fields();
+    }
+    if settings.json_enums_as_integers {
+        builder.use_integers_for_enums();
+    }
+
+    let writers = builder.generate(&["."], move |package| {
+        output.set_file_name(format!("{}.rs", package));
+        files_c.deref().borrow_mut().push(output.clone());
 
-            let file = std::fs::OpenOptions::new().append(true).create(true).open(&output)?;
+        let file = std::fs::OpenOptions::new().append(true).create(true).open(&output)?;
 
-            Ok(BufWriter::new(file))
-        })?;
+        Ok(BufWriter::new(file))
+    })?;
 
     for (_, mut writer) in writers {
         writer.flush()?;
@@ -120,8 +326,72 @@ pub fn axum_connect_codegen(settings: AxumConnectGenSettings) -> anyhow::Result<
         let contents = contents.replace("pbjson::", "axum_connect::pbjson::");
         let contents = contents.replace("prost::", "axum_connect::prost::");
         let contents = contents.replace("serde::", "axum_connect::serde::");
+        // Generated impls for deprecated messages (and with deprecated fields) aren't uses.
+        let contents = allow_deprecated_impls(&contents);
         std::fs::write(&file, contents)?;
     }
 
+    // `RpcRedact` impls and setters go after everything else (they're already written with full
+    // paths).
+    names::append(&PathBuf::from(env::var("OUT_DIR").unwrap()), redactions)?;
+    names::append(&PathBuf::from(env::var("OUT_DIR").unwrap()), setters)?;
+
+    Ok(())
+}
+
+/// Instructs cargo to re-run if any proto the inputs import (transitively) changes, not just the
+/// inputs themselves. Imports are found on the include paths the way protoc finds them, those
+/// that aren't (protoc's own well-known types) don't change.
+fn rerun_if_imports_changed(descriptor_set: &[u8], includes: &[PathBuf]) -> anyhow::Result<()> {
+    let pool = prost_reflect::DescriptorPool::decode(descriptor_set)?;
+    for file in pool.files() {
+        let path = includes
+            .iter()
+            .map(|include| include.join(file.name()))
+            .find(|path| path.is_file());
+
+        if let Some(path) = path {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+    }
+
     Ok(())
 }
+
+/// Puts `#[allow(deprecated)]` on every `impl` block of a generated file.
+fn allow_deprecated_impls(contents: &str) -> String {
+    let mut out = String::with_capacity(contents.len());
+    for line in contents.lines() {
+        let code = line.trim_start();
+        if code.starts_with("impl ") || code.starts_with("impl<") {
+            out.push_str(&line[..line.len() - code.len()]);
+            out.push_str("#[allow(deprecated)]\n");
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+
+    out
+}
+
+/// Runs protoc on the inputs, writing their descriptor set (imports included) to `path`. prost
+/// writes the same file again later on.
+fn compile_descriptor_set(
+    settings: &AxumConnectGenSettings,
+    includes: &[PathBuf],
+    path: &Path,
+) -> anyhow::Result<Vec<u8>> {
+    let mut protoc = Command::new(prost_build::protoc_from_env());
+    protoc.arg("--include_imports").arg("-o").arg(path);
+    for include in includes {
+        protoc.arg("-I").arg(include);
+    }
+    protoc.args(&settings.protoc_args).args(&settings.inputs);
+
+    let output = protoc.output()?;
+    if !output.status.success() {
+        anyhow::bail!("protoc failed: {}", String::from_utf8_lossy(&output.stderr));
+    }
+
+    Ok(std::fs::read(path)?)
+}
@@ -0,0 +1,75 @@
+//! A JSON manifest of the generated services, for deploy tooling, gateways and client generators.
+
+use std::{collections::HashMap, path::Path};
+
+use prost_reflect::{DescriptorPool, MethodDescriptor};
+use serde_json::{json, Value};
+
+/// Writes the manifest of every service in the descriptor set to `path`:
+///
+/// ```json
+/// {
+///   "services": [{
+///     "name": "hello.HelloWorldService",
+///     "methods": [{
+///       "name": "SayHello",
+///       "path": "/hello.HelloWorldService/SayHello",
+///       "kind": "unary",
+///       "request_type": "hello.HelloRequest",
+///       "response_type": "hello.HelloResponse",
+///       "deprecated": false,
+///       "idempotent": false,
+///       "scopes": []
+///     }]
+///   }]
+/// }
+/// ```
+pub fn write_manifest(
+    descriptor_set: &[u8],
+    scopes: &HashMap<String, Vec<String>>,
+    path: &Path,
+) -> anyhow::Result<()> {
+    let pool = DescriptorPool::decode(descriptor_set)?;
+
+    let services: Vec<_> = pool
+        .services()
+        .map(|service| {
+            json!({
+                "name": service.full_name(),
+                "methods": service
+                    .methods()
+                    .map(|method| method_manifest(&method, scopes))
+                    .collect::<Vec<_>>(),
+            })
+        })
+        .collect();
+
+    std::fs::write(
+        path,
+        serde_json::to_string_pretty(&json!({ "services": services }))?,
+    )?;
+    Ok(())
+}
+
+fn method_manifest(method: &MethodDescriptor, scopes: &HashMap<String, Vec<String>>) -> Value {
+    let path = format!("/{}/{}", method.parent_service().full_name(), method.name());
+    let options = method.method_descriptor_proto().options.as_ref();
+
+    json!({
+        "name": method.name(),
+        "kind": match (method.is_client_streaming(), method.is_server_streaming()) {
+            (false, false) => "unary",
+            (false, true) => "server_streaming",
+            (true, false) => "client_streaming",
+            (true, true) => "bidi_streaming",
+        },
+        "request_type": method.input().full_name(),
+        "response_type": method.output().full_name(),
+        "deprecated": options.and_then(|options| options.deprecated).unwrap_or(false),
+        "idempotent": options
+            .and_then(|options| options.idempotency_level)
+            .is_some_and(|level| level != 0),
+        "scopes": scopes.get(&path).cloned().unwrap_or_default(),
+        "path": path,
+    })
+}
@@ -0,0 +1,168 @@
+//! How prost names the Rust items it generates, for code generated alongside them. The case
+//! conversions (and keyword escaping) are prost's own, `prost_build::ident`, which keeps digits
+//! attached to the word before them: `address_line1` stays `address_line1`.
+
+use std::{collections::HashMap, path::Path};
+
+use heck::{ToSnakeCase, ToUpperCamelCase};
+use prost_reflect::MessageDescriptor;
+
+/// The path of the Rust type prost generates for `message`, from its package's module.
+pub fn rust_path(message: &MessageDescriptor) -> String {
+    match message.parent_message() {
+        Some(parent) => format!(
+            "{}::{}",
+            module_path(&parent),
+            to_upper_camel(message.name())
+        ),
+        None => to_upper_camel(message.name()),
+    }
+}
+
+/// The path of the module prost generates for the nested types (and oneofs) of `message`.
+pub fn module_path(message: &MessageDescriptor) -> String {
+    let module = rust_ident(message.name());
+    match message.parent_message() {
+        Some(parent) => format!("{}::{}", module_path(&parent), module),
+        None => module,
+    }
+}
+
+/// The path of a message or enum type (named `name`, in `package` and nested in `parent`) from
+/// the module of `from_package`, the way prost writes it: relative, with `super::`s.
+pub fn type_path(
+    from_package: &str,
+    package: &str,
+    parent: Option<&MessageDescriptor>,
+    name: &str,
+) -> String {
+    let name = to_upper_camel(name);
+    // See the `extern_path` codegen sets up.
+    if package == "google.protobuf" {
+        return format!("::axum_connect::pbjson_types::{}", name);
+    }
+
+    let from: Vec<_> = from_package.split('.').filter(|c| !c.is_empty()).collect();
+    let to: Vec<_> = package.split('.').filter(|c| !c.is_empty()).collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+
+    let mut path = vec!["super".to_string(); from.len() - common];
+    path.extend(to[common..].iter().map(|c| rust_ident(c)));
+    if let Some(parent) = parent {
+        path.push(module_path(parent));
+    }
+    path.push(name);
+
+    path.join("::")
+}
+
+/// A snake_case identifier, escaped the way prost escapes keywords.
+pub fn rust_ident(name: &str) -> String {
+    let mut ident = name.to_snake_case();
+    match ident.as_str() {
+        // Keywords that can't be raw identifiers.
+        "self" | "super" | "crate" => ident.push('_'),
+        "as" | "async" | "await" | "break" | "const" | "continue" | "dyn" | "else" | "enum"
+        | "extern" | "false" | "fn" | "for" | "if" | "impl" | "in" | "let" | "loop" | "match"
+        | "mod" | "move" | "mut" | "pub" | "ref" | "return" | "static" | "struct" | "trait"
+        | "true" | "type" | "unsafe" | "use" | "where" | "while" | "abstract" | "become"
+        | "box" | "do" | "final" | "macro" | "override" | "priv" | "typeof" | "unsized"
+        | "virtual" | "yield" | "try" => ident.insert_str(0, "r#"),
+        _ => {}
+    }
+
+    ident
+}
+
+/// An UpperCamelCase type (or enum variant) name, escaped the way prost escapes `Self`.
+pub fn to_upper_camel(name: &str) -> String {
+    let mut ident = name.to_upper_camel_case();
+    if ident == "Self" {
+        ident.push('_');
+    }
+
+    ident
+}
+
+/// Appends generated impls (keyed by package) to the files prost generated in `out_dir`.
+pub fn append(out_dir: &Path, packages: HashMap<String, String>) -> anyhow::Result<()> {
+    use std::io::Write;
+
+    for (package, impls) in packages {
+        // prost's name for the file of protos without a package.
+        let package = if package.is_empty() { "_" } else { &package };
+        let path = out_dir.join(format!("{}.rs", package));
+        let mut file = std::fs::OpenOptions::new().append(true).open(path)?;
+        file.write_all(impls.as_bytes())?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use prost_reflect::DescriptorPool;
+    use prost_types::{
+        field_descriptor_proto::{Label, Type},
+        DescriptorProto, FieldDescriptorProto, FileDescriptorProto, FileDescriptorSet,
+    };
+
+    use super::*;
+
+    /// `address.v1.Person1.Address2`, with an `address_line1` field.
+    fn address() -> MessageDescriptor {
+        let address = DescriptorProto {
+            name: Some("Address2".to_string()),
+            field: vec![FieldDescriptorProto {
+                name: Some("address_line1".to_string()),
+                number: Some(1),
+                label: Some(Label::Optional as i32),
+                r#type: Some(Type::String as i32),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let file = FileDescriptorProto {
+            name: Some("address.proto".to_string()),
+            package: Some("address.v1".to_string()),
+            message_type: vec![DescriptorProto {
+                name: Some("Person1".to_string()),
+                nested_type: vec![address],
+                ..Default::default()
+            }],
+            syntax: Some("proto3".to_string()),
+            ..Default::default()
+        };
+
+        DescriptorPool::from_file_descriptor_set(FileDescriptorSet { file: vec![file] })
+            .unwrap()
+            .get_message_by_name("address.v1.Person1.Address2")
+            .unwrap()
+    }
+
+    #[test]
+    fn digits_stay_attached() {
+        let address = address();
+        let field = address.get_field_by_name("address_line1").unwrap();
+
+        assert_eq!(rust_ident(field.name()), "address_line1");
+        assert_eq!(rust_path(&address), "person1::Address2");
+        assert_eq!(module_path(&address), "person1::address2");
+        assert_eq!(
+            type_path(
+                "other",
+                "address.v1",
+                address.parent_message().as_ref(),
+                address.name()
+            ),
+            "super::address::v1::person1::Address2"
+        );
+    }
+
+    #[test]
+    fn keywords_are_escaped() {
+        assert_eq!(rust_ident("type"), "r#type");
+        assert_eq!(rust_ident("self"), "self_");
+        assert_eq!(to_upper_camel("self"), "Self_");
+    }
+}
@@ -0,0 +1,93 @@
+//! `axum_connect/options.proto`, the custom options codegen understands.
+
+use std::{collections::HashMap, path::PathBuf};
+
+use prost_reflect::DescriptorPool;
+
+const OPTIONS_PROTO: &str = r#"syntax = "proto3";
+
+package axum_connect;
+
+import "google/protobuf/descriptor.proto";
+
+extend google.protobuf.FieldOptions {
+  // Masks the field out of logged messages, see `axum_connect::redact`.
+  bool redact = 50621;
+}
+
+extend google.protobuf.MethodOptions {
+  // Scopes (or roles) callers must hold, see `axum_connect::auth::RpcScopesLayer`.
+  repeated string scopes = 50622;
+}
+"#;
+
+/// Writes `axum_connect/options.proto` under `include` (a directory), returning `include`.
+pub fn write_options_proto(include: PathBuf) -> anyhow::Result<PathBuf> {
+    let dir = include.join("axum_connect");
+    std::fs::create_dir_all(&dir)?;
+    std::fs::write(dir.join("options.proto"), OPTIONS_PROTO)?;
+    Ok(include)
+}
+
+/// The `(axum_connect.scopes)` of every method that has any, keyed by method path (ex.
+/// `/hello.HelloWorldService/SayHello`).
+pub fn method_scopes(descriptor_set: &[u8]) -> anyhow::Result<HashMap<String, Vec<String>>> {
+    let pool = DescriptorPool::decode(descriptor_set)?;
+    // Only there if one of the protos imports `axum_connect/options.proto`.
+    let Some(option) = pool.get_extension_by_name("axum_connect.scopes") else {
+        return Ok(HashMap::new());
+    };
+
+    let mut scopes = HashMap::new();
+    for method in pool.services().flat_map(|service| service.methods()) {
+        let options = method.options();
+        let method_scopes: Vec<_> = options
+            .get_extension(&option)
+            .as_list()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|scope| scope.as_str().map(str::to_string))
+            .collect();
+
+        if !method_scopes.is_empty() {
+            scopes.insert(
+                format!("/{}/{}", method.parent_service().full_name(), method.name()),
+                method_scopes,
+            );
+        }
+    }
+
+    Ok(scopes)
+}
+
+/// Messages and enums marked `deprecated = true`, as prost paths (ex. `.hello.OldRequest`).
+/// Well-known types aside, axum-connect has those.
+pub fn deprecated_types(descriptor_set: &[u8]) -> anyhow::Result<(Vec<String>, Vec<String>)> {
+    let pool = DescriptorPool::decode(descriptor_set)?;
+
+    let messages = pool
+        .all_messages()
+        .filter(|message| message.package_name() != "google.protobuf")
+        .filter(|message| {
+            message
+                .descriptor_proto()
+                .options
+                .as_ref()
+                .is_some_and(|options| options.deprecated())
+        })
+        .map(|message| format!(".{}", message.full_name()))
+        .collect();
+    let enums = pool
+        .all_enums()
+        .filter(|e| e.package_name() != "google.protobuf")
+        .filter(|e| {
+            e.enum_descriptor_proto()
+                .options
+                .as_ref()
+                .is_some_and(|options| options.deprecated())
+        })
+        .map(|e| format!(".{}", e.full_name()))
+        .collect();
+
+    Ok((messages, enums))
+}
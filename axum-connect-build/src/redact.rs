@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+
+use prost_reflect::{
+    DescriptorPool, ExtensionDescriptor, FieldDescriptor, Kind, MessageDescriptor,
+};
+
+use crate::names::{module_path, rust_ident, rust_path, to_upper_camel};
+
+/// `RpcRedact` impls for every message in the descriptor set (well-known types aside, axum-connect
+/// has those), keyed by the package whose generated file they belong in.
+pub fn generate(descriptor_set: &[u8]) -> anyhow::Result<HashMap<String, String>> {
+    let pool = DescriptorPool::decode(descriptor_set)?;
+    // Only there if one of the protos imports `axum_connect/options.proto`.
+    let option = pool.get_extension_by_name("axum_connect.redact");
+    // Whether a message (by full name) has anything to redact, itself or in nested messages.
+    let mut redacting = HashMap::new();
+
+    let mut packages = HashMap::<String, String>::new();
+    for message in pool.all_messages() {
+        let package = message.package_name();
+        if message.is_map_entry() || package == "google.protobuf" || package == "axum_connect" {
+            continue;
+        }
+
+        let body = if redacts(&message, option.as_ref(), &mut redacting) {
+            redact_body(&message, option.as_ref(), &mut redacting)
+        } else {
+            String::new()
+        };
+
+        packages
+            .entry(package.to_string())
+            // Generated files don't import anything themselves.
+            .or_insert_with(|| "use axum_connect::redact::RpcRedact as _;\n".to_string())
+            .push_str(&format!(
+                "#[allow(deprecated)]\nimpl axum_connect::redact::RpcRedact for {} {{\n    fn redact(&mut self) {{\n{}    }}\n}}\n",
+                rust_path(&message),
+                body
+            ));
+    }
+
+    Ok(packages)
+}
+
+fn is_redacted(field: &FieldDescriptor, option: Option<&ExtensionDescriptor>) -> bool {
+    option.is_some_and(|option| {
+        field
+            .options()
+            .get_extension(option)
+            .as_bool()
+            .unwrap_or(false)
+    })
+}
+
+/// The message a field holds (the value, for maps), unless it's a well-known type.
+fn field_message(field: &FieldDescriptor) -> Option<MessageDescriptor> {
+    let kind = if field.is_map() {
+        match field.kind() {
+            Kind::Message(entry) => entry.map_entry_value_field().kind(),
+            _ => return None,
+        }
+    } else {
+        field.kind()
+    };
+
+    match kind {
+        Kind::Message(message) if message.package_name() != "google.protobuf" => Some(message),
+        _ => None,
+    }
+}
+
+fn redacts(
+    message: &MessageDescriptor,
+    option: Option<&ExtensionDescriptor>,
+    redacting: &mut HashMap<String, bool>,
+) -> bool {
+    if let Some(redacts) = redacting.get(message.full_name()) {
+        return *redacts;
+    }
+
+    // Recursive messages only redact if something along the way does.
+    redacting.insert(message.full_name().to_string(), false);
+    let redacts = message.fields().any(|field| {
+        is_redacted(&field, option)
+            || field_message(&field).is_some_and(|nested| redacts(&nested, option, redacting))
+    });
+    redacting.insert(message.full_name().to_string(), redacts);
+
+    redacts
+}
+
+fn redact_body(
+    message: &MessageDescriptor,
+    option: Option<&ExtensionDescriptor>,
+    redacting: &mut HashMap<String, bool>,
+) -> String {
+    let mut body = String::new();
+    let mut oneofs = HashMap::<String, Vec<String>>::new();
+
+    for field in message.fields() {
+        let redacted = is_redacted(&field, option);
+        let nested =
+            field_message(&field).is_some_and(|nested| redacts(&nested, option, redacting));
+        if !redacted && !nested {
+            continue;
+        }
+
+        // prost generates proto3 `optional` fields as plain `Option`s, not oneofs.
+        if let Some(oneof) = field
+            .containing_oneof()
+            .filter(|oneof| !oneof.is_synthetic())
+        {
+            let variant = format!(
+                "{}::{}::{}",
+                module_path(message),
+                to_upper_camel(oneof.name()),
+                to_upper_camel(field.name())
+            );
+            let arm = if redacted {
+                format!(
+                    "            Some({}(value)) => *value = Default::default(),\n",
+                    variant
+                )
+            } else {
+                format!("            Some({}(value)) => value.redact(),\n", variant)
+            };
+            oneofs
+                .entry(rust_ident(oneof.name()))
+                .or_default()
+                .push(arm);
+            continue;
+        }
+
+        let ident = rust_ident(field.name());
+        body.push_str(&if redacted {
+            format!("        self.{} = Default::default();\n", ident)
+        } else if field.is_map() {
+            format!(
+                "        for value in self.{}.values_mut() {{\n            value.redact();\n        }}\n",
+                ident
+            )
+        } else if field.is_list() {
+            format!(
+                "        for value in &mut self.{} {{\n            value.redact();\n        }}\n",
+                ident
+            )
+        } else {
+            format!(
+                "        if let Some(value) = &mut self.{} {{\n            value.redact();\n        }}\n",
+                ident
+            )
+        });
+    }
+
+    for (ident, arms) in oneofs {
+        body.push_str(&format!(
+            "        match &mut self.{} {{\n{}            _ => {{}}\n        }}\n",
+            ident,
+            arms.concat()
+        ));
+    }
+
+    body
+}
@@ -0,0 +1,37 @@
+//! JSON Schema documents for the messages services use, the same ones `axum_connect::schema`
+//! serves at runtime (see `axum_connect_schema`).
+
+use std::{collections::BTreeMap, path::Path};
+
+use axum_connect_schema::message_schema;
+use prost_reflect::DescriptorPool;
+use serde_json::json;
+
+/// Writes `<message>.json` (ex. `hello.HelloRequest.json`) to `dir` for the request and response
+/// message of every method in the descriptor set. Fields are named as they are in the proto file
+/// if `proto_field_names`, see `AxumConnectGenSettings::json_proto_field_names`.
+pub fn write_schemas(
+    descriptor_set: &[u8],
+    proto_field_names: bool,
+    dir: &Path,
+) -> anyhow::Result<()> {
+    let pool = DescriptorPool::decode(descriptor_set)?;
+    let messages: BTreeMap<_, _> = pool
+        .services()
+        .flat_map(|service| service.methods())
+        .flat_map(|method| [method.input(), method.output()])
+        .map(|message| (message.full_name().to_string(), message))
+        .collect();
+
+    std::fs::create_dir_all(dir)?;
+    for (name, message) in messages {
+        let mut schema = message_schema(&message, proto_field_names);
+        schema["$id"] = json!(format!("{}.json", name));
+        std::fs::write(
+            dir.join(format!("{}.json", name)),
+            serde_json::to_string_pretty(&schema)?,
+        )?;
+    }
+
+    Ok(())
+}
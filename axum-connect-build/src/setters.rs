@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+
+use prost_reflect::{Cardinality, DescriptorPool, FieldDescriptor, Kind};
+
+use crate::names::{module_path, rust_ident, rust_path, to_upper_camel, type_path};
+
+/// `with_*` setters for every field of every message in the descriptor set (well-known types
+/// aside), keyed by the package whose generated file they belong in. `bytes` is
+/// `AxumConnectGenSettings::bytes`, the setters take whatever prost made the field.
+pub fn generate(
+    descriptor_set: &[u8],
+    bytes: &[String],
+) -> anyhow::Result<HashMap<String, String>> {
+    let pool = DescriptorPool::decode(descriptor_set)?;
+
+    let mut packages = HashMap::<String, String>::new();
+    for message in pool.all_messages() {
+        let package = message.package_name();
+        if message.is_map_entry() || package == "google.protobuf" || package == "axum_connect" {
+            continue;
+        }
+
+        let setters: String = message
+            .fields()
+            .map(|field| setter(&field, package, bytes))
+            .collect();
+
+        packages
+            .entry(package.to_string())
+            .or_default()
+            .push_str(&format!(
+                "#[allow(deprecated)]\nimpl {} {{\n{}}}\n",
+                rust_path(&message),
+                setters
+            ));
+    }
+
+    Ok(packages)
+}
+
+fn setter(field: &FieldDescriptor, package: &str, bytes: &[String]) -> String {
+    let ident = rust_ident(field.name());
+    let name = ident.trim_start_matches("r#");
+    let message = field.parent_message();
+
+    let (param, assign) = if field.is_map() {
+        let Kind::Message(entry) = field.kind() else {
+            unreachable!("map fields are entry messages");
+        };
+        let key = value_type(&entry.map_entry_key_field(), package, bytes, false);
+        let value = value_type(&entry.map_entry_value_field(), package, bytes, false);
+        (
+            format!("impl IntoIterator<Item = ({}, {})>", key, value),
+            format!(
+                "self.{} = value.into_iter().map(|(k, v)| (k, v.into())).collect();",
+                ident
+            ),
+        )
+    } else if field.is_list() {
+        (
+            format!(
+                "impl IntoIterator<Item = {}>",
+                value_type(field, package, bytes, false)
+            ),
+            format!(
+                "self.{} = value.into_iter().map(Into::into).collect();",
+                ident
+            ),
+        )
+    } else if let Some(oneof) = field
+        .containing_oneof()
+        .filter(|oneof| !oneof.is_synthetic())
+    {
+        (
+            value_type(field, package, bytes, true),
+            format!(
+                "self.{} = Some({}::{}::{}(value.into()));",
+                rust_ident(oneof.name()),
+                module_path(message),
+                to_upper_camel(oneof.name()),
+                to_upper_camel(field.name())
+            ),
+        )
+    } else if field.supports_presence()
+        // prost only makes required messages `Option`s.
+        && (field.cardinality() != Cardinality::Required || field.kind().as_message().is_some())
+    {
+        (
+            value_type(field, package, bytes, true),
+            format!("self.{} = Some(value.into());", ident),
+        )
+    } else {
+        (
+            value_type(field, package, bytes, true),
+            format!("self.{} = value.into();", ident),
+        )
+    };
+
+    let deprecation = if field
+        .field_descriptor_proto()
+        .options
+        .as_ref()
+        .is_some_and(|o| o.deprecated())
+    {
+        "    #[deprecated]\n"
+    } else {
+        ""
+    };
+
+    format!(
+        "    /// Sets `{}`.\n{}    pub fn with_{}(mut self, value: {}) -> Self {{\n        {}\n        self\n    }}\n",
+        field.name(),
+        deprecation,
+        name,
+        param,
+        assign
+    )
+}
+
+/// The type a setter takes for (one value of) `field`. Enums are taken as the enum rather than
+/// the `i32` prost stores, strings and bytes as anything that converts if `convert`.
+fn value_type(field: &FieldDescriptor, package: &str, bytes: &[String], convert: bool) -> String {
+    let ty = match field.kind() {
+        Kind::Double => "f64",
+        Kind::Float => "f32",
+        Kind::Int32 | Kind::Sint32 | Kind::Sfixed32 => "i32",
+        Kind::Int64 | Kind::Sint64 | Kind::Sfixed64 => "i64",
+        Kind::Uint32 | Kind::Fixed32 => "u32",
+        Kind::Uint64 | Kind::Fixed64 => "u64",
+        Kind::Bool => "bool",
+        Kind::String => "String",
+        Kind::Bytes if is_bytes(field, bytes) => "::axum_connect::prost::bytes::Bytes",
+        Kind::Bytes => "Vec<u8>",
+        Kind::Enum(e) => {
+            return type_path(
+                package,
+                e.package_name(),
+                e.parent_message().as_ref(),
+                e.name(),
+            );
+        }
+        Kind::Message(m) => {
+            return type_path(
+                package,
+                m.package_name(),
+                m.parent_message().as_ref(),
+                m.name(),
+            );
+        }
+    };
+
+    match field.kind() {
+        Kind::String | Kind::Bytes if convert => format!("impl Into<{}>", ty),
+        _ => ty.to_string(),
+    }
+}
+
+/// Whether prost generates `field` as `Bytes`, matching paths the way `Config::bytes` does.
+fn is_bytes(field: &FieldDescriptor, bytes: &[String]) -> bool {
+    let path = format!(".{}", field.full_name());
+    bytes.iter().any(|prefix| {
+        prefix == "."
+            || path == *prefix
+            || path
+                .strip_prefix(prefix.as_str())
+                .is_some_and(|rest| rest.starts_with('.'))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+    use prost_types::{
+        field_descriptor_proto::{Label, Type},
+        DescriptorProto, FieldDescriptorProto, FileDescriptorProto, FileDescriptorSet,
+        OneofDescriptorProto,
+    };
+
+    use super::*;
+
+    fn string_field(name: &str, number: i32, oneof: Option<i32>) -> FieldDescriptorProto {
+        FieldDescriptorProto {
+            name: Some(name.to_string()),
+            number: Some(number),
+            label: Some(Label::Optional as i32),
+            r#type: Some(Type::String as i32),
+            oneof_index: oneof,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn digit_bearing_names() {
+        let file = FileDescriptorProto {
+            name: Some("address.proto".to_string()),
+            package: Some("address.v1".to_string()),
+            message_type: vec![DescriptorProto {
+                name: Some("Address".to_string()),
+                field: vec![
+                    string_field("address_line1", 1, None),
+                    string_field("po_box2", 2, Some(0)),
+                ],
+                oneof_decl: vec![OneofDescriptorProto {
+                    name: Some("kind1".to_string()),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            syntax: Some("proto3".to_string()),
+            ..Default::default()
+        };
+        let descriptor_set = FileDescriptorSet { file: vec![file] }.encode_to_vec();
+
+        let generated = generate(&descriptor_set, &[]).unwrap();
+        let setters = &generated["address.v1"];
+
+        assert!(setters.contains("pub fn with_address_line1(mut self, value: impl Into<String>)"));
+        assert!(setters.contains("self.address_line1 = value.into();"));
+        assert!(setters.contains("pub fn with_po_box2("));
+        assert!(setters.contains("self.kind1 = Some(address::Kind1::PoBox2(value.into()));"));
+    }
+}
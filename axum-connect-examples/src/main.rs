@@ -6,11 +6,10 @@
 
 use async_stream::stream;
 use axum::Router;
-use axum_connect::{futures::Stream, prelude::*};
+use axum_connect::{cors::connect_cors, futures::Stream, prelude::*};
 use axum_extra::extract::Host;
 use error::Error;
 use proto::hello::*;
-use tower_http::cors::CorsLayer;
 
 // Take a peak at error.rs to see how errors work in axum-connect.
 mod error;
@@ -33,9 +32,9 @@ async fn main() {
     // It expect a service method handler, wrapped in it's respective type. The handler (below) is
     // just a normal Rust function. Just like Axum, it also supports extractors!
     let app = Router::new()
-        // A standard unary (POST based) Connect-Web request handler.
-        .rpc(HelloWorldService::say_hello(say_hello_unary))
-        // A GET version of the same thing, which has well-defined semantics for caching.
+        // A standard unary (POST based) Connect-Web request handler, which also answers GETs
+        // (which have well-defined semantics for caching). Use `HelloWorldService::say_hello` for
+        // POST only.
         .rpc(HelloWorldService::say_hello_unary_get(say_hello_unary))
         // A server-streaming request handler. Very useful when you need them!
         .rpc(HelloWorldService::say_hello_stream(stream_three_reponses));
@@ -44,7 +43,7 @@ async fn main() {
         .await
         .unwrap();
     println!("listening on http://{:?}", listener.local_addr().unwrap());
-    axum::serve(listener, app.layer(CorsLayer::very_permissive()))
+    axum::serve(listener, app.layer(connect_cors()))
         .await
         .unwrap();
 }
@@ -70,11 +69,9 @@ async fn say_hello_unary(Host(host): Host, request: HelloRequest) -> Result<Hell
 }
 
 /// This is a server-streaming request handler. Much more rare to see one in the wild, but they
-/// sure are useful when you need them! axum-connect has only partial support for everything
-/// connect-web defines in server-streaming requests. For example, it doesn't define a way to
-/// return trailers. I've never once actually needed them, so it feels weird to muddy the API just
-/// to support such a niche use. Trailers are IMO the worst single decision gRPC made, locking them
-/// into HTTP/2 forever. I'm not a fan -.-
+/// sure are useful when you need them! Trailers are IMO the worst single decision gRPC made,
+/// locking them into HTTP/2 forever. I'm not a fan -.- But if you must, yield a `StreamEnd` (or a
+/// `Result<_, StreamEnd>`) to end the stream with trailers and/or an error, details included.
 ///
 /// You can however return a stream of anything that converts `RpcIntoResponse`, just like the
 /// unary handlers. Again, very flexible. In this case I'm using the amazing `async-stream` crate
@@ -0,0 +1,145 @@
+//! JSON Schemas for protobuf messages, following the proto3 JSON mapping (the one pbjson
+//! implements). `axum_connect::schema` serves them at runtime, and `axum-connect-build` writes them
+//! out at build time.
+
+use prost_reflect::{FieldDescriptor, Kind, MessageDescriptor};
+use serde_json::{json, Map, Value};
+
+/// Builds a standalone JSON Schema for `message`, with every message it references in `$defs`.
+/// Fields are named as they are in the proto file if `proto_field_names` (see
+/// `AxumConnectGenSettings::json_proto_field_names`), in lowerCamelCase otherwise.
+pub fn message_schema(message: &MessageDescriptor, proto_field_names: bool) -> Value {
+    let mut defs = Map::new();
+    let mut pending = vec![message.clone()];
+
+    while let Some(message) = pending.pop() {
+        if defs.contains_key(message.full_name()) {
+            continue;
+        }
+
+        let def = message_def(&message, proto_field_names, &mut pending);
+        defs.insert(message.full_name().to_string(), def);
+    }
+
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "$ref": format!("#/$defs/{}", message.full_name()),
+        "$defs": defs,
+    })
+}
+
+fn message_def(
+    message: &MessageDescriptor,
+    proto_field_names: bool,
+    pending: &mut Vec<MessageDescriptor>,
+) -> Value {
+    if let Some(def) = well_known_def(message, pending) {
+        return def;
+    }
+
+    let properties: Map<String, Value> = message
+        .fields()
+        .map(|field| {
+            let name = if proto_field_names {
+                field.name()
+            } else {
+                field.json_name()
+            };
+            (name.to_string(), field_schema(&field, pending))
+        })
+        .collect();
+
+    json!({
+        "type": "object",
+        "title": message.name(),
+        "properties": properties,
+    })
+}
+
+fn field_schema(field: &FieldDescriptor, pending: &mut Vec<MessageDescriptor>) -> Value {
+    if field.is_map() {
+        let value = match field.kind() {
+            Kind::Message(entry) => kind_schema(&entry.map_entry_value_field().kind(), pending),
+            _ => json!({}),
+        };
+
+        return json!({ "type": "object", "additionalProperties": value });
+    }
+
+    let schema = kind_schema(&field.kind(), pending);
+    if field.is_list() {
+        json!({ "type": "array", "items": schema })
+    } else {
+        schema
+    }
+}
+
+fn kind_schema(kind: &Kind, pending: &mut Vec<MessageDescriptor>) -> Value {
+    match kind {
+        // Floats may also be "NaN", "Infinity" or "-Infinity".
+        Kind::Double | Kind::Float => json!({ "type": ["number", "string"] }),
+        Kind::Int32 | Kind::Sint32 | Kind::Sfixed32 | Kind::Uint32 | Kind::Fixed32 => {
+            json!({ "type": "integer" })
+        }
+        // 64 bit integers are written as strings, but parsers accept both.
+        Kind::Int64 | Kind::Sint64 | Kind::Sfixed64 | Kind::Uint64 | Kind::Fixed64 => {
+            json!({ "type": ["integer", "string"] })
+        }
+        Kind::Bool => json!({ "type": "boolean" }),
+        Kind::String => json!({ "type": "string" }),
+        Kind::Bytes => json!({ "type": "string", "contentEncoding": "base64" }),
+        Kind::Enum(e) if e.full_name() == "google.protobuf.NullValue" => json!({ "type": "null" }),
+        Kind::Enum(e) => {
+            let values: Vec<Value> = e
+                .values()
+                .map(|v| Value::from(v.name()))
+                .chain(e.values().map(|v| Value::from(v.number())))
+                .collect();
+
+            json!({ "enum": values })
+        }
+        Kind::Message(message) => {
+            pending.push(message.clone());
+            json!({ "$ref": format!("#/$defs/{}", message.full_name()) })
+        }
+    }
+}
+
+/// Well-known types have their own special JSON mapping.
+fn well_known_def(
+    message: &MessageDescriptor,
+    pending: &mut Vec<MessageDescriptor>,
+) -> Option<Value> {
+    let def = match message.full_name() {
+        "google.protobuf.Timestamp" => json!({ "type": "string", "format": "date-time" }),
+        "google.protobuf.Duration" => {
+            json!({ "type": "string", "pattern": "^-?[0-9]+(\\.[0-9]+)?s$" })
+        }
+        "google.protobuf.FieldMask" => json!({ "type": "string" }),
+        "google.protobuf.Struct" => json!({ "type": "object" }),
+        "google.protobuf.ListValue" => json!({ "type": "array" }),
+        "google.protobuf.Value" => json!({}),
+        "google.protobuf.Empty" => json!({ "type": "object" }),
+        "google.protobuf.Any" => json!({
+            "type": "object",
+            "properties": { "@type": { "type": "string" } },
+            "required": ["@type"],
+        }),
+        "google.protobuf.DoubleValue"
+        | "google.protobuf.FloatValue"
+        | "google.protobuf.Int64Value"
+        | "google.protobuf.UInt64Value"
+        | "google.protobuf.Int32Value"
+        | "google.protobuf.UInt32Value"
+        | "google.protobuf.BoolValue"
+        | "google.protobuf.StringValue"
+        | "google.protobuf.BytesValue" => {
+            // Wrappers are written as their bare value.
+            let value = message.get_field_by_name("value")?;
+            json!({ "anyOf": [kind_schema(&value.kind(), pending), { "type": "null" }] })
+        }
+        _ => return None,
+    };
+
+    Some(def)
+}
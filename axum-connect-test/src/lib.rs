@@ -0,0 +1,242 @@
+//! An in-process client for testing axum-connect services without binding a port.
+//!
+//! Requests are sent straight into a `Router` (with `tower::ServiceExt::oneshot`), encoded the same
+//! way a Connect client would, and responses are decoded back into messages or `RpcError`s.
+//!
+//! ```ignore
+//! let client = TestClient::new(
+//!     Router::new().rpc(HelloWorldService::say_hello(say_hello_unary)),
+//! );
+//!
+//! let res: HelloResponse = client
+//!     .unary(HelloWorldService::SAY_HELLO, HelloRequest { name: Some("Alec".into()) })
+//!     .await?;
+//! ```
+
+use std::str::FromStr;
+
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, Request},
+    response::Response,
+    Router,
+};
+use axum_connect::{
+    descriptor::MethodDescriptor,
+    error::{RpcError, RpcErrorCode, RpcErrorDetail},
+    response::RpcResult,
+};
+use prost::Message;
+use serde::{de::DeserializeOwned, Serialize};
+use tower::ServiceExt;
+
+#[derive(Clone)]
+pub struct TestClient {
+    router: Router,
+    headers: HeaderMap,
+    json: bool,
+}
+
+impl TestClient {
+    pub fn new(router: Router) -> Self {
+        Self {
+            router,
+            headers: HeaderMap::new(),
+            json: false,
+        }
+    }
+
+    /// Use the JSON codec instead of binary protobuf.
+    pub fn json(mut self) -> Self {
+        self.json = true;
+        self
+    }
+
+    /// Send `name: value` with every request (ex. an `authorization` header).
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Sends a raw request through the router, for anything the typed helpers don't cover.
+    pub async fn call(&self, req: Request<Body>) -> Response {
+        // Routers are infallible.
+        self.router.clone().oneshot(req).await.unwrap()
+    }
+
+    pub async fn unary<TMReq, TMRes>(
+        &self,
+        method: MethodDescriptor,
+        message: TMReq,
+    ) -> RpcResult<TMRes>
+    where
+        TMReq: Message + Serialize,
+        TMRes: Message + DeserializeOwned + Default,
+    {
+        let content_type = if self.json {
+            "application/json"
+        } else {
+            "application/proto"
+        };
+        let body = self.encode(&message)?;
+        let res = self.call(self.request(method, content_type, body)).await;
+
+        let status = res.status();
+        let body = read_body(res).await?;
+        if !status.is_success() {
+            return Err(decode_error(&body).unwrap_or_else(|| {
+                RpcError::unknown(format!("HTTP {} without a Connect error body", status))
+            }));
+        }
+
+        self.decode(body)
+    }
+
+    /// Calls a server-streaming method and collects everything it sent. If the stream ended in an
+    /// error, that error is the last item.
+    pub async fn server_streaming<TMReq, TMRes>(
+        &self,
+        method: MethodDescriptor,
+        message: TMReq,
+    ) -> Vec<RpcResult<TMRes>>
+    where
+        TMReq: Message + Serialize,
+        TMRes: Message + DeserializeOwned + Default,
+    {
+        let content_type = if self.json {
+            "application/connect+json"
+        } else {
+            "application/connect+proto"
+        };
+        let body = match self.encode(&message) {
+            Ok(body) => envelope(0, &body),
+            Err(e) => return vec![Err(e)],
+        };
+        let res = self.call(self.request(method, content_type, body)).await;
+
+        let mut body = match read_body(res).await {
+            Ok(body) => body,
+            Err(e) => return vec![Err(e)],
+        };
+
+        let mut items = vec![];
+        while !body.is_empty() {
+            if body.len() < 5 {
+                items.push(Err(RpcError::data_loss("Truncated envelope")));
+                break;
+            }
+
+            let flags = body[0];
+            let size = u32::from_be_bytes([body[1], body[2], body[3], body[4]]) as usize;
+            if body.len() - 5 < size {
+                items.push(Err(RpcError::data_loss("Truncated envelope")));
+                break;
+            }
+            let _ = body.split_to(5);
+            let payload = body.split_to(size);
+
+            // EndStreamResponse, the error (if any) is always JSON.
+            if flags & 0x2 != 0 {
+                if let Some(e) = decode_end_stream(&payload) {
+                    items.push(Err(e));
+                }
+                break;
+            }
+
+            items.push(self.decode(payload));
+        }
+
+        items
+    }
+
+    fn request(
+        &self,
+        method: MethodDescriptor,
+        content_type: &str,
+        body: Vec<u8>,
+    ) -> Request<Body> {
+        let mut req = Request::builder()
+            .method(Method::POST)
+            .uri(method.path)
+            .header(header::CONTENT_TYPE, content_type)
+            .header("connect-protocol-version", "1")
+            .body(Body::from(body))
+            .unwrap();
+        req.headers_mut().extend(self.headers.clone());
+        req
+    }
+
+    fn encode<M>(&self, message: &M) -> RpcResult<Vec<u8>>
+    where
+        M: Message + Serialize,
+    {
+        if self.json {
+            serde_json::to_vec(message).map_err(|e| RpcError::internal(e.to_string()))
+        } else {
+            Ok(message.encode_to_vec())
+        }
+    }
+
+    fn decode<M>(&self, body: Bytes) -> RpcResult<M>
+    where
+        M: Message + DeserializeOwned + Default,
+    {
+        if self.json {
+            serde_json::from_slice(&body).map_err(|e| RpcError::data_loss(e.to_string()))
+        } else {
+            M::decode(body).map_err(|e| RpcError::data_loss(e.to_string()))
+        }
+    }
+}
+
+async fn read_body(res: Response) -> RpcResult<Bytes> {
+    to_bytes(res.into_body(), usize::MAX)
+        .await
+        .map_err(|e| RpcError::unavailable(e.to_string()))
+}
+
+fn envelope(flags: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 5);
+    frame.push(flags);
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// The error of an EndStreamResponse, if it carries one. Accepts the error both under `error` (as
+/// the spec says) and inlined at the top level.
+fn decode_end_stream(payload: &[u8]) -> Option<RpcError> {
+    let value: serde_json::Value = serde_json::from_slice(payload).ok()?;
+    let error = value.get("error").unwrap_or(&value);
+    error_from_json(error)
+}
+
+fn decode_error(body: &[u8]) -> Option<RpcError> {
+    let value: serde_json::Value = serde_json::from_slice(body).ok()?;
+    error_from_json(&value)
+}
+
+fn error_from_json(value: &serde_json::Value) -> Option<RpcError> {
+    let code = RpcErrorCode::from_str(value.get("code")?.as_str()?).ok()?;
+    let message = value
+        .get("message")
+        .and_then(|message| message.as_str())
+        .unwrap_or_default();
+
+    let mut e = RpcError::new(code, message.to_string());
+    if let Some(details) = value.get("details").and_then(|details| details.as_array()) {
+        for detail in details {
+            if let (Some(proto_type), Some(value)) = (
+                detail.get("type").and_then(|t| t.as_str()),
+                detail.get("value").and_then(|v| v.as_str()),
+            ) {
+                e.details.push(RpcErrorDetail {
+                    proto_type: proto_type.to_string(),
+                    proto_b62_value: value.to_string(),
+                });
+            }
+        }
+    }
+
+    Some(e)
+}
@@ -0,0 +1,58 @@
+//! Round-trips a server-streaming call through `TestClient`, which envelopes the request the way
+//! Connect clients do.
+
+use axum::{body::Body, http::Request, response::Response, routing::post, Router};
+use axum_connect::{
+    descriptor::{MethodDescriptor, MethodKind},
+    futures::{executor::block_on, stream, Stream},
+    handler::RpcHandlerStream,
+    pbjson_types::StringValue,
+};
+use axum_connect_test::TestClient;
+
+const ECHO: MethodDescriptor = MethodDescriptor {
+    service: "test.EchoService",
+    method: "Echo",
+    path: "/test.EchoService/Echo",
+    kind: MethodKind::ServerStreaming,
+    deprecated: false,
+    idempotent: false,
+    sunset: None,
+    scopes: &[],
+};
+
+fn router() -> Router {
+    Router::new().route(ECHO.path, post(echo))
+}
+
+async fn echo(req: Request<Body>) -> Response {
+    RpcHandlerStream::<StringValue, StringValue, _, ()>::call(echo_stream, req, ()).await
+}
+
+async fn echo_stream(message: StringValue) -> impl Stream<Item = StringValue> {
+    stream::iter([message.clone(), message])
+}
+
+fn hello() -> StringValue {
+    StringValue {
+        value: "hello".to_string(),
+    }
+}
+
+#[test]
+fn server_streaming_round_trips() {
+    let client = TestClient::new(router());
+    let items = block_on(client.server_streaming::<_, StringValue>(ECHO, hello()));
+
+    let items: Vec<_> = items.into_iter().map(|item| item.unwrap()).collect();
+    assert_eq!(items, vec![hello(), hello()]);
+}
+
+#[test]
+fn server_streaming_round_trips_json() {
+    let client = TestClient::new(router()).json();
+    let items = block_on(client.server_streaming::<_, StringValue>(ECHO, hello()));
+
+    let items: Vec<_> = items.into_iter().map(|item| item.unwrap()).collect();
+    assert_eq!(items, vec![hello(), hello()]);
+}
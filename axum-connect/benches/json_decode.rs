@@ -0,0 +1,46 @@
+//! Compares the two JSON decoders on the kind of payloads browser clients tend to send: large,
+//! nested, string-heavy objects. Run with `cargo bench -p axum-connect --features simd-json`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use pbjson_types::Struct;
+use serde_json::json;
+
+fn payload(rows: usize) -> Vec<u8> {
+    let rows: Vec<_> = (0..rows)
+        .map(|i| {
+            json!({
+                "id": format!("row-{}", i),
+                "name": "Lorem ipsum dolor sit amet, consectetur adipiscing elit",
+                "score": i as f64 * 1.5,
+                "active": i % 2 == 0,
+                "tags": ["alpha", "beta", "gamma", "delta"],
+                "meta": { "created": "2024-01-01T00:00:00Z", "owner": { "id": i, "kind": "user" } },
+            })
+        })
+        .collect();
+
+    serde_json::to_vec(&json!({ "rows": rows })).unwrap()
+}
+
+fn json_decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("json_decode");
+
+    for rows in [10, 1_000, 10_000] {
+        let bytes = payload(rows);
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+
+        group.bench_with_input(BenchmarkId::new("serde_json", rows), &bytes, |b, bytes| {
+            b.iter(|| serde_json::from_slice::<Struct>(bytes).unwrap())
+        });
+
+        // Includes the copy the decode path has to make, simd-json parses in place.
+        group.bench_with_input(BenchmarkId::new("simd_json", rows), &bytes, |b, bytes| {
+            b.iter(|| simd_json::serde::from_slice::<Struct>(&mut bytes.clone()).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, json_decode);
+criterion_main!(benches);
@@ -0,0 +1,28 @@
+//! Arbitrary bodies for a server-streaming call. The transport unwraps the request envelope (flags,
+//! size and compression, in `decode_envelope`) before the message in it is decoded, so both get
+//! fuzzed.
+
+#![no_main]
+
+use axum::{body::Body, http::Request};
+use axum_connect_fuzz::{call, STREAM};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((codec, body)) = data.split_first() else {
+        return;
+    };
+    let content_type = if codec & 1 == 0 {
+        "application/connect+proto"
+    } else {
+        "application/connect+json"
+    };
+
+    call(
+        Request::post(STREAM)
+            .header("content-type", content_type)
+            .header("connect-protocol-version", "1")
+            .body(Body::from(body.to_vec()))
+            .unwrap(),
+    );
+});
@@ -0,0 +1,16 @@
+//! Arbitrary query strings for a unary GET call (`encoding`, `base64`, `compression`, `message`).
+
+#![no_main]
+
+use axum::{body::Body, http::Request};
+use axum_connect_fuzz::{call, UNARY};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|query: &str| {
+    // Only queries that make it into a URI at all can reach the server.
+    let Ok(req) = Request::get(format!("{}?{}", UNARY, query)).body(Body::empty()) else {
+        return;
+    };
+
+    call(req);
+});
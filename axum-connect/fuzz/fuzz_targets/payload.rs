@@ -0,0 +1,26 @@
+//! Arbitrary bodies for a unary call, decoded as binary protobuf or JSON.
+
+#![no_main]
+
+use axum::{body::Body, http::Request};
+use axum_connect_fuzz::{call, UNARY};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((codec, body)) = data.split_first() else {
+        return;
+    };
+    let content_type = if codec & 1 == 0 {
+        "application/proto"
+    } else {
+        "application/json"
+    };
+
+    call(
+        Request::post(UNARY)
+            .header("content-type", content_type)
+            .header("connect-protocol-version", "1")
+            .body(Body::from(body.to_vec()))
+            .unwrap(),
+    );
+});
@@ -0,0 +1,51 @@
+//! What the fuzz targets share: a router serving a unary and a server-streaming method, and a way
+//! to call it without an async runtime. Both methods echo a `google.protobuf.Struct`, which covers
+//! about every JSON shape (and nested message) there is.
+
+use axum::{
+    body::{to_bytes, Body},
+    http::Request,
+    response::Response,
+    routing::post,
+    Router,
+};
+use axum_connect::{
+    handler::{RpcHandlerStream, RpcHandlerUnary},
+    pbjson_types::Struct,
+};
+use futures::{stream, Stream};
+use tower::ServiceExt;
+
+pub const UNARY: &str = "/fuzz.FuzzService/Unary";
+pub const STREAM: &str = "/fuzz.FuzzService/Stream";
+
+/// Sends `req` through the router and reads the whole response, so streams are encoded too.
+pub fn call(req: Request<Body>) {
+    futures::executor::block_on(async {
+        // Routers are infallible.
+        let res = router().oneshot(req).await.unwrap();
+        let _ = to_bytes(res.into_body(), usize::MAX).await;
+    });
+}
+
+fn router() -> Router {
+    Router::new()
+        .route(UNARY, post(unary).get(unary))
+        .route(STREAM, post(server_streaming))
+}
+
+async fn unary(req: Request<Body>) -> Response {
+    RpcHandlerUnary::<Struct, Struct, _, ()>::call(echo, req, ()).await
+}
+
+async fn server_streaming(req: Request<Body>) -> Response {
+    RpcHandlerStream::<Struct, Struct, _, ()>::call(echo_stream, req, ()).await
+}
+
+async fn echo(message: Struct) -> Struct {
+    message
+}
+
+async fn echo_stream(message: Struct) -> impl Stream<Item = Struct> {
+    stream::iter([message])
+}
@@ -0,0 +1,71 @@
+//! One structured record per RPC, for access logs. Attach a sink with `RpcConfig::access_log`:
+//!
+//! ```ignore
+//! let app = Router::new()
+//!     .rpc(HelloWorldService::say_hello(say_hello))
+//!     .rpc_config(RpcConfig::new().access_log(access_log::trace));
+//! ```
+//!
+//! Records are made once the call is done, which for server streams is once the last message (and
+//! the EndStreamResponse) is encoded. They carry the Connect error code of the call, so streams
+//! that fail show up as failures even though their HTTP status is always 200.
+
+use std::{sync::Arc, time::Duration};
+
+use crate::{descriptor::MethodDescriptor, error::RpcErrorCode};
+
+pub type RpcAccessLog = Arc<dyn Fn(&RpcAccessRecord) + Send + Sync>;
+
+/// A finished RPC.
+#[derive(Clone, Debug)]
+pub struct RpcAccessRecord {
+    /// The method called. `None` for calls that never made it to a generated route.
+    pub method: Option<MethodDescriptor>,
+    /// Whether the call used the binary protobuf codec (as opposed to JSON).
+    pub binary: bool,
+    /// Whether the response was a stream.
+    pub streaming: bool,
+    /// From when the call was received until its response was encoded.
+    pub duration: Duration,
+    /// The size of the request message, as received.
+    pub request_size: usize,
+    /// The size of the response message (or, for streams, of all envelopes before the end of the
+    /// stream). Error bodies aren't counted.
+    pub response_size: usize,
+    /// The Connect error code the call failed with, `None` if it succeeded.
+    pub code: Option<RpcErrorCode>,
+}
+
+impl RpcAccessRecord {
+    /// The codec, as in the content type: `proto` or `json`.
+    pub fn codec(&self) -> &'static str {
+        if self.binary {
+            "proto"
+        } else {
+            "json"
+        }
+    }
+
+    /// The Connect error code of the call, or `ok`.
+    pub fn code_str(&self) -> &'static str {
+        self.code.as_ref().map_or("ok", RpcErrorCode::as_str)
+    }
+}
+
+/// A sink for `RpcConfig::access_log` that logs each record at `INFO` under the
+/// `axum_connect::access` target.
+#[cfg(feature = "tracing")]
+pub fn trace(record: &RpcAccessRecord) {
+    tracing::info!(
+        target: "axum_connect::access",
+        rpc.service = record.method.map(|m| m.service).unwrap_or_default(),
+        rpc.method = record.method.map(|m| m.method).unwrap_or_default(),
+        rpc.codec = record.codec(),
+        rpc.streaming = record.streaming,
+        rpc.connect_code = record.code_str(),
+        duration_ms = record.duration.as_secs_f64() * 1000.0,
+        request_size = record.request_size,
+        response_size = record.response_size,
+        "RPC finished"
+    );
+}
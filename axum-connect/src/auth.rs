@@ -0,0 +1,358 @@
+//! Bearer-token authentication for RPC handlers.
+//!
+//! `RpcBearer` just pulls the token out of the `Authorization` header. For anything more, implement
+//! `RpcBearerValidator` for whatever a validated token turns into (a user, a set of claims, ...)
+//! and extract `RpcAuthenticated` of it:
+//!
+//! ```ignore
+//! #[async_trait]
+//! impl RpcBearerValidator<AppState> for User {
+//!     async fn validate(token: &str, state: &AppState) -> Result<Self, RpcError> {
+//!         state.sessions.user_for(token).await.ok_or_else(|| RpcBearer::invalid("Unknown session"))
+//!     }
+//! }
+//!
+//! async fn say_hello(RpcAuthenticated(user): RpcAuthenticated<User>, request: HelloRequest) -> ...
+//! ```
+//!
+//! Failures are `Unauthenticated` Connect errors carrying a `google.rpc.ErrorInfo` detail, with
+//! the `WWW-Authenticate` challenge a plain HTTP API would have answered with in its metadata.
+//!
+//! Methods can also declare the scopes (or roles) callers need in the proto file, and have them
+//! enforced in one place by `RpcScopesLayer`:
+//!
+//! ```protobuf
+//! import "axum_connect/options.proto";
+//!
+//! service AdminService {
+//!   rpc DeleteUser(DeleteUserRequest) returns (DeleteUserResponse) {
+//!     option (axum_connect.scopes) = "users:write";
+//!   }
+//! }
+//! ```
+//!
+//! ```ignore
+//! impl RpcScoped for User {
+//!     fn has_scope(&self, scope: &str) -> bool {
+//!         self.scopes.iter().any(|held| held == scope)
+//!     }
+//! }
+//!
+//! let app = Router::new()
+//!     .rpc(AdminService::delete_user(delete_user))
+//!     .layer(RpcScopesLayer::<User, _>::new(state.clone()))
+//!     .with_state(state);
+//! ```
+
+use std::{
+    collections::HashMap,
+    marker::PhantomData,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use async_trait::async_trait;
+use axum::http::{header, request, Request};
+use futures::future::BoxFuture;
+use prost::Message;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{
+    descriptor::MethodDescriptor,
+    error::{RpcError, RpcErrorCode, RpcErrorDetail},
+    parts::RpcFromRequestParts,
+};
+
+/// The domain of the `google.rpc.ErrorInfo` details of authentication errors.
+const ERROR_DOMAIN: &str = "axum-connect";
+
+/// The bearer token of the call, from `Authorization: Bearer <token>`. Rejects calls without one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RpcBearer(pub String);
+
+impl RpcBearer {
+    /// The error for a token that is present, but no good (unknown, expired, revoked, ...).
+    pub fn invalid(message: impl Into<String>) -> RpcError {
+        unauthenticated(message.into(), "INVALID_TOKEN", "invalid_token")
+    }
+}
+
+#[async_trait]
+impl<M, S> RpcFromRequestParts<M, S> for RpcBearer
+where
+    M: Message,
+    S: Send + Sync,
+{
+    type Rejection = RpcError;
+
+    async fn rpc_from_request_parts(
+        parts: &mut request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let Some(authorization) = parts.headers.get(header::AUTHORIZATION) else {
+            return Err(unauthenticated(
+                "Missing bearer token".to_string(),
+                "MISSING_TOKEN",
+                "",
+            ));
+        };
+
+        let token = authorization
+            .to_str()
+            .ok()
+            .and_then(|value| value.split_once(' '))
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+            .map(|(_, token)| token.trim())
+            .filter(|token| !token.is_empty());
+
+        match token {
+            Some(token) => Ok(Self(token.to_string())),
+            None => Err(unauthenticated(
+                "Malformed Authorization header, expected a bearer token".to_string(),
+                "INVALID_REQUEST",
+                "invalid_request",
+            )),
+        }
+    }
+}
+
+/// Turns a bearer token into something handlers can use, see `RpcAuthenticated`.
+#[async_trait]
+pub trait RpcBearerValidator<S>: Sized {
+    /// Validates `token`. Reject bad tokens with `RpcBearer::invalid` (or any other error).
+    async fn validate(token: &str, state: &S) -> Result<Self, RpcError>;
+}
+
+/// The result of validating the call's bearer token with `T`'s `RpcBearerValidator` impl.
+#[derive(Clone, Debug)]
+pub struct RpcAuthenticated<T>(pub T);
+
+#[async_trait]
+impl<M, S, T> RpcFromRequestParts<M, S> for RpcAuthenticated<T>
+where
+    M: Message,
+    S: Send + Sync,
+    T: RpcBearerValidator<S>,
+{
+    type Rejection = RpcError;
+
+    async fn rpc_from_request_parts(
+        parts: &mut request::Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let RpcBearer(token) =
+            <RpcBearer as RpcFromRequestParts<M, S>>::rpc_from_request_parts(parts, state).await?;
+        T::validate(&token, state).await.map(Self)
+    }
+}
+
+/// Implemented by validated credentials (see `RpcBearerValidator`) that carry scopes or roles.
+pub trait RpcScoped {
+    fn has_scope(&self, scope: &str) -> bool;
+}
+
+/// Enforces the `(axum_connect.scopes)` of every method (see `MethodDescriptor::scopes`): calls to
+/// methods that declare scopes must carry a bearer token that `T` validates, and that holds every
+/// one of them. Calls that don't are rejected with `Unauthenticated` and `PermissionDenied`
+/// respectively. The validated `T` is added to the request extensions, for handlers to extract
+/// with `Extension<T>`.
+///
+/// The layer only hands the check to the RPC routes under it, which run it against their own
+/// method descriptor, so it can go anywhere (ex. around a merged or nested router) and other
+/// routes are unaffected. Calls to methods without scopes pass through untouched, but RPC routes
+/// without a descriptor (hand-written ones, ex. with `RpcHandlerUnary` directly) are rejected with
+/// `PermissionDenied`: there's no telling which scopes they need.
+pub struct RpcScopesLayer<T, S> {
+    state: S,
+    _validator: PhantomData<fn() -> T>,
+}
+
+impl<T, S> RpcScopesLayer<T, S> {
+    /// `state` is handed to `T`'s `RpcBearerValidator` impl.
+    pub fn new(state: S) -> Self {
+        Self {
+            state,
+            _validator: PhantomData,
+        }
+    }
+}
+
+impl<T, S: Clone> Clone for RpcScopesLayer<T, S> {
+    fn clone(&self) -> Self {
+        Self::new(self.state.clone())
+    }
+}
+
+impl<I, T, S: Clone> Layer<I> for RpcScopesLayer<T, S> {
+    type Service = RpcScopes<I, T, S>;
+
+    fn layer(&self, inner: I) -> Self::Service {
+        RpcScopes {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+pub struct RpcScopes<I, T, S> {
+    inner: I,
+    layer: RpcScopesLayer<T, S>,
+}
+
+impl<I: Clone, T, S: Clone> Clone for RpcScopes<I, T, S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            layer: self.layer.clone(),
+        }
+    }
+}
+
+impl<I, T, S, B> Service<Request<B>> for RpcScopes<I, T, S>
+where
+    I: Service<Request<B>>,
+    T: RpcBearerValidator<S> + RpcScoped + Clone + Send + Sync + 'static,
+    S: Clone + Send + Sync + 'static,
+{
+    type Response = I::Response;
+    type Error = I::Error;
+    type Future = I::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        req.extensions_mut()
+            .insert(ScopeCheck(Arc::new(Authorizer::<T, S> {
+                state: self.layer.state.clone(),
+                _validator: PhantomData,
+            })));
+        self.inner.call(req)
+    }
+}
+
+/// What `RpcScopesLayer` leaves in the request extensions, for the route to run once it knows
+/// which method is being called.
+#[derive(Clone)]
+struct ScopeCheck(Arc<dyn Authorize>);
+
+trait Authorize: Send + Sync {
+    fn authorize<'a>(
+        &'a self,
+        parts: &'a mut request::Parts,
+        scopes: &'static [&'static str],
+    ) -> BoxFuture<'a, Result<(), RpcError>>;
+}
+
+struct Authorizer<T, S> {
+    state: S,
+    _validator: PhantomData<fn() -> T>,
+}
+
+impl<T, S> Authorize for Authorizer<T, S>
+where
+    T: RpcBearerValidator<S> + RpcScoped + Clone + Send + Sync + 'static,
+    S: Send + Sync,
+{
+    fn authorize<'a>(
+        &'a self,
+        parts: &'a mut request::Parts,
+        scopes: &'static [&'static str],
+    ) -> BoxFuture<'a, Result<(), RpcError>> {
+        Box::pin(async move {
+            let validated = authorize::<T, S>(parts, &self.state, scopes).await?;
+            parts.extensions.insert(validated);
+            Ok(())
+        })
+    }
+}
+
+/// Runs the check of the `RpcScopesLayer` the call went through (if any) against the scopes of
+/// the method descriptor the route attached.
+pub(crate) async fn check_scopes(parts: &mut request::Parts) -> Result<(), RpcError> {
+    let Some(ScopeCheck(check)) = parts.extensions.get::<ScopeCheck>().cloned() else {
+        return Ok(());
+    };
+
+    let scopes = match parts.extensions.get::<MethodDescriptor>() {
+        Some(method) if method.scopes.is_empty() => return Ok(()),
+        Some(method) => method.scopes,
+        None => {
+            return Err(RpcError::new(
+                RpcErrorCode::PermissionDenied,
+                "Unknown method, can't tell which scopes it requires".to_string(),
+            ))
+        }
+    };
+
+    check.authorize(parts, scopes).await
+}
+
+/// Validates the call's bearer token with `T`, and checks it holds every one of `scopes`.
+async fn authorize<T, S>(
+    parts: &mut request::Parts,
+    state: &S,
+    scopes: &[&str],
+) -> Result<T, RpcError>
+where
+    T: RpcBearerValidator<S> + RpcScoped,
+    S: Send + Sync,
+{
+    // Any message type does, `RpcBearer` doesn't look at it.
+    let RpcBearer(token) =
+        <RpcBearer as RpcFromRequestParts<(), S>>::rpc_from_request_parts(parts, state).await?;
+    let validated = T::validate(&token, state).await?;
+
+    let missing: Vec<_> = scopes
+        .iter()
+        .copied()
+        .filter(|scope| !validated.has_scope(scope))
+        .collect();
+    if missing.is_empty() {
+        Ok(validated)
+    } else {
+        Err(insufficient_scope(scopes, &missing))
+    }
+}
+
+/// A `PermissionDenied` error for a token lacking the `missing` ones of the `required` scopes.
+fn insufficient_scope(required: &[&str], missing: &[&str]) -> RpcError {
+    let mut e = RpcError::new(
+        RpcErrorCode::PermissionDenied,
+        format!("Missing required scopes: {}", missing.join(", ")),
+    );
+    e.details.push(RpcErrorDetail::error_info(
+        "INSUFFICIENT_SCOPE",
+        ERROR_DOMAIN,
+        HashMap::from([
+            ("scopes".to_string(), missing.join(" ")),
+            (
+                "www-authenticate".to_string(),
+                format!(
+                    "Bearer error=\"insufficient_scope\", scope=\"{}\"",
+                    required.join(" ")
+                ),
+            ),
+        ]),
+    ));
+    e
+}
+
+/// An `Unauthenticated` error. `challenge_error` is the `error` of the `WWW-Authenticate`
+/// challenge (RFC 6750), if any.
+fn unauthenticated(message: String, reason: &str, challenge_error: &str) -> RpcError {
+    let challenge = match challenge_error {
+        "" => "Bearer".to_string(),
+        error => format!("Bearer error=\"{}\"", error),
+    };
+
+    let mut e = RpcError::new(RpcErrorCode::Unauthenticated, message);
+    e.details.push(RpcErrorDetail::error_info(
+        reason,
+        ERROR_DOMAIN,
+        HashMap::from([("www-authenticate".to_string(), challenge)]),
+    ));
+    e
+}
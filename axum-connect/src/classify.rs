@@ -0,0 +1,110 @@
+//! A `tower-http` response classifier that understands Connect, enabled by the `tower-http`
+//! feature.
+//!
+//! tower-http's default classifiers only look at the HTTP status, which misses every error of a
+//! server-streaming RPC (those are always sent with a 200, the error lives in the last frame of
+//! the body). `ConnectClassifier` instead classifies by Connect error code, waiting for the end of
+//! the body for streams:
+//!
+//! ```ignore
+//! let app = Router::new()
+//!     .rpc(HelloWorldService::say_hello(say_hello))
+//!     .layer(TraceLayer::new(ConnectClassifier));
+//! ```
+//!
+//! Responses that don't come from an RPC handler are classified like `ServerErrorsAsFailures`
+//! does: 5xx statuses are failures.
+
+use std::fmt;
+
+use axum::http::{HeaderMap, Request, Response, StatusCode};
+use tower_http::classify::{ClassifiedResponse, ClassifyEos, ClassifyResponse, MakeClassifier};
+
+use crate::{error::RpcErrorCode, handler::codec::StreamOutcome};
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ConnectClassifier;
+
+/// Why a response was classified as a failure.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConnectFailureClass {
+    /// The RPC failed with this Connect error code.
+    Rpc(RpcErrorCode),
+    /// A non-RPC response with a 5xx status.
+    StatusCode(StatusCode),
+    /// The response (or its body) errored before it was complete.
+    Error(String),
+}
+
+impl fmt::Display for ConnectFailureClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectFailureClass::Rpc(code) => write!(f, "RPC error: {}", code),
+            ConnectFailureClass::StatusCode(status) => write!(f, "Status code: {}", status),
+            ConnectFailureClass::Error(error) => write!(f, "Error: {}", error),
+        }
+    }
+}
+
+impl MakeClassifier for ConnectClassifier {
+    type Classifier = Self;
+    type FailureClass = ConnectFailureClass;
+    type ClassifyEos = ConnectClassifyEos;
+
+    fn make_classifier<B>(&self, _req: &Request<B>) -> Self::Classifier {
+        *self
+    }
+}
+
+impl ClassifyResponse for ConnectClassifier {
+    type FailureClass = ConnectFailureClass;
+    type ClassifyEos = ConnectClassifyEos;
+
+    fn classify_response<B>(
+        self,
+        res: &Response<B>,
+    ) -> ClassifiedResponse<Self::FailureClass, Self::ClassifyEos> {
+        if let Some(code) = res.extensions().get::<RpcErrorCode>() {
+            return ClassifiedResponse::Ready(Err(ConnectFailureClass::Rpc(code.clone())));
+        }
+
+        if let Some(outcome) = res.extensions().get::<StreamOutcome>() {
+            return ClassifiedResponse::RequiresEos(ConnectClassifyEos(outcome.clone()));
+        }
+
+        if res.status().is_server_error() {
+            ClassifiedResponse::Ready(Err(ConnectFailureClass::StatusCode(res.status())))
+        } else {
+            ClassifiedResponse::Ready(Ok(()))
+        }
+    }
+
+    fn classify_error<E>(self, error: &E) -> Self::FailureClass
+    where
+        E: fmt::Display + 'static,
+    {
+        ConnectFailureClass::Error(error.to_string())
+    }
+}
+
+/// Classifies a streaming RPC once its body is done.
+#[derive(Clone)]
+pub struct ConnectClassifyEos(StreamOutcome);
+
+impl ClassifyEos for ConnectClassifyEos {
+    type FailureClass = ConnectFailureClass;
+
+    fn classify_eos(self, _trailers: Option<&HeaderMap>) -> Result<(), Self::FailureClass> {
+        match self.0 .0.get() {
+            Some(code) => Err(ConnectFailureClass::Rpc(code.clone())),
+            None => Ok(()),
+        }
+    }
+
+    fn classify_error<E>(self, error: &E) -> Self::FailureClass
+    where
+        E: fmt::Display + 'static,
+    {
+        ConnectFailureClass::Error(error.to_string())
+    }
+}
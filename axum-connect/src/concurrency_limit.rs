@@ -0,0 +1,190 @@
+//! Per-method concurrency limits that answer in Connect. Calls over a method's limit are rejected
+//! right away with an `Unavailable` Connect error (with a `google.rpc.RetryInfo` detail) rather than
+//! queued, so expensive methods can't pile up work the server can't keep up with:
+//!
+//! ```ignore
+//! let app = Router::new()
+//!     .rpc(ReportService::generate(generate))
+//!     .layer(RpcConcurrencyLimitLayer::new().method(ReportService::GENERATE, 4));
+//! ```
+//!
+//! A call is in flight until its response body is done (or dropped), so open streams count too.
+//! Methods are matched by path, under any prefix they're nested at. Methods without a limit, and
+//! preflights, are never limited. Rejections go through the error hook and redaction of the
+//! `RpcConfig`, if the layer is applied before `rpc_config`.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use axum::{
+    body::Body,
+    http::{Method, Request},
+    response::Response,
+};
+use futures::{
+    future::{self, BoxFuture},
+    FutureExt, StreamExt,
+};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{
+    descriptor::{is_rpc_path, MethodDescriptor},
+    error::{RpcError, RpcErrorCode, RpcErrorDetail},
+    handler::codec::encode_rejection,
+};
+
+#[derive(Clone, Debug)]
+pub struct RpcConcurrencyLimitLayer {
+    limits: HashMap<&'static str, (MethodDescriptor, Arc<Limit>)>,
+    retry_after: Duration,
+}
+
+impl Default for RpcConcurrencyLimitLayer {
+    fn default() -> Self {
+        Self {
+            limits: Default::default(),
+            retry_after: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RpcConcurrencyLimitLayer {
+    /// No limits until some are set with `method`.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Allows at most `max` calls to `method` in flight at once.
+    ///
+    /// Panics if `max` is zero.
+    pub fn method(mut self, method: MethodDescriptor, max: usize) -> Self {
+        assert!(max > 0, "concurrency limit must be positive");
+
+        self.limits.insert(
+            method.path,
+            (
+                method,
+                Arc::new(Limit {
+                    max,
+                    in_flight: AtomicUsize::new(0),
+                }),
+            ),
+        );
+        self
+    }
+
+    /// How long rejected clients are told to wait before retrying. Defaults to a second.
+    pub fn retry_after(mut self, retry_after: Duration) -> Self {
+        self.retry_after = retry_after;
+        self
+    }
+}
+
+impl<S> Layer<S> for RpcConcurrencyLimitLayer {
+    type Service = RpcConcurrencyLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RpcConcurrencyLimit {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RpcConcurrencyLimit<S> {
+    inner: S,
+    layer: RpcConcurrencyLimitLayer,
+}
+
+impl<S, B> Service<Request<B>> for RpcConcurrencyLimit<S>
+where
+    S: Service<Request<B>, Response = Response>,
+    S::Future: Send + 'static,
+    S::Error: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let path = req.uri().path();
+        let (method, limit) = match self
+            .layer
+            .limits
+            .iter()
+            .find(|(rpc_path, _)| is_rpc_path(path, rpc_path))
+        {
+            Some((_, (method, limit))) if req.method() != Method::OPTIONS => (*method, limit),
+            _ => return self.inner.call(req).boxed(),
+        };
+
+        match limit.acquire() {
+            Some(permit) => self
+                .inner
+                .call(req)
+                .map(|res| res.map(|res| hold_until_done(res, permit)))
+                .boxed(),
+            None => future::ready(Ok(reject(&req, method, self.layer.retry_after))).boxed(),
+        }
+    }
+}
+
+/// Keeps `permit` until the body of `res` is done with.
+fn hold_until_done(res: Response, permit: Permit) -> Response {
+    res.map(|body| {
+        Body::from_stream(body.into_data_stream().map(move |chunk| {
+            let _permit = &permit;
+            chunk
+        }))
+    })
+}
+
+/// An `Unavailable` error, encoded for whatever kind of call `req` (to `method`) is.
+fn reject<B>(req: &Request<B>, method: MethodDescriptor, retry_after: Duration) -> Response {
+    let mut e = RpcError::new(
+        RpcErrorCode::Unavailable,
+        "Too many concurrent calls".to_string(),
+    );
+    e.details.push(RpcErrorDetail::retry_info(retry_after));
+
+    encode_rejection(req, e, Some(method))
+}
+
+#[derive(Debug)]
+struct Limit {
+    max: usize,
+    in_flight: AtomicUsize,
+}
+
+impl Limit {
+    fn acquire(self: &Arc<Self>) -> Option<Permit> {
+        self.in_flight
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < self.max).then_some(n + 1)
+            })
+            .ok()
+            .map(|_| Permit(self.clone()))
+    }
+}
+
+/// A call in flight, until dropped.
+struct Permit(Arc<Limit>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::AcqRel);
+    }
+}
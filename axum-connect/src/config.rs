@@ -0,0 +1,275 @@
+use std::sync::Arc;
+#[cfg(any(feature = "timeout", feature = "tracing"))]
+use std::{collections::HashMap, time::Duration};
+
+use axum::http::{request, HeaderName, HeaderValue};
+
+#[cfg(feature = "shutdown")]
+use crate::shutdown::RpcShutdown;
+use crate::{
+    access_log::{RpcAccessLog, RpcAccessRecord},
+    cors::CorsOrigins,
+    descriptor::{MethodDescriptor, RpcMethods},
+    error::{RpcError, RpcErrorCode},
+};
+
+const DEBUG_INFO_TYPE: &str = "google.rpc.DebugInfo";
+
+pub type RpcErrorHook = Arc<dyn Fn(RpcError, &MethodDescriptor) -> RpcError + Send + Sync>;
+
+/// Router-wide settings that every RPC handler consults.
+///
+/// Attach it with `RpcRouterExt::rpc_config`. Like any axum layer it only applies to routes that
+/// were registered *before* it, so call it after all your `.rpc(...)` registrations.
+#[derive(Clone)]
+pub struct RpcConfig {
+    pub(crate) access_log: Option<RpcAccessLog>,
+    pub(crate) catch_panics: bool,
+    pub(crate) cors_origins: CorsOrigins,
+    pub(crate) error_hook: Option<RpcErrorHook>,
+    pub(crate) redact_errors: bool,
+    pub(crate) propagate_headers: Arc<Vec<HeaderName>>,
+    #[cfg(feature = "tracing")]
+    pub(crate) log_messages: bool,
+    /// The methods of the router the config is applied to, when it's built inside
+    /// `RpcMethods::record`. Lets middleware find the method of calls it rejects.
+    pub(crate) methods: Option<RpcMethods>,
+    #[cfg(feature = "tracing")]
+    pub(crate) slow_threshold: Option<Duration>,
+    #[cfg(feature = "tracing")]
+    pub(crate) method_slow_thresholds: Arc<HashMap<&'static str, Duration>>,
+    #[cfg(feature = "shutdown")]
+    pub(crate) shutdown: Option<RpcShutdown>,
+    pub(crate) stream_buffer: usize,
+    #[cfg(feature = "timeout")]
+    pub(crate) timeout: Option<Duration>,
+    #[cfg(feature = "timeout")]
+    pub(crate) method_timeouts: Arc<HashMap<&'static str, Duration>>,
+}
+
+impl Default for RpcConfig {
+    fn default() -> Self {
+        Self {
+            access_log: None,
+            catch_panics: false,
+            cors_origins: Default::default(),
+            error_hook: None,
+            redact_errors: false,
+            propagate_headers: Default::default(),
+            #[cfg(feature = "tracing")]
+            log_messages: false,
+            methods: None,
+            #[cfg(feature = "tracing")]
+            slow_threshold: None,
+            #[cfg(feature = "tracing")]
+            method_slow_thresholds: Default::default(),
+            #[cfg(feature = "shutdown")]
+            shutdown: None,
+            stream_buffer: 1,
+            #[cfg(feature = "timeout")]
+            timeout: None,
+            #[cfg(feature = "timeout")]
+            method_timeouts: Default::default(),
+        }
+    }
+}
+
+impl RpcConfig {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Hand a record of every finished RPC (method, codec, duration, sizes, error code) to
+    /// `sink`, see `access_log`.
+    pub fn access_log<F>(mut self, sink: F) -> Self
+    where
+        F: Fn(&RpcAccessRecord) + Send + Sync + 'static,
+    {
+        self.access_log = Some(Arc::new(sink));
+        self
+    }
+
+    /// Catch panics raised by handlers (and by the streams they return) and answer with a
+    /// well-formed `Internal` Connect error instead of resetting the connection. Off by default.
+    /// Clients are only told the handler panicked, the panic message is the error's source (ex.
+    /// for an error hook to log).
+    pub fn catch_panics(mut self, enabled: bool) -> Self {
+        self.catch_panics = enabled;
+        self
+    }
+
+    /// Answer CORS requests (and preflights) from `origins` on RPC routes, ex.
+    /// `["https://app.example.com"]`, see `cors`. Origins are compared exactly, scheme and port
+    /// included. Off by default: without it, RPC routes send no CORS headers at all.
+    ///
+    /// Panics if an origin isn't a valid header value.
+    pub fn cors_origins<I, O>(mut self, origins: I) -> Self
+    where
+        I: IntoIterator<Item = O>,
+        O: AsRef<str>,
+    {
+        self.cors_origins = CorsOrigins::List(Arc::new(
+            origins
+                .into_iter()
+                .map(|origin| HeaderValue::from_str(origin.as_ref()).expect("valid origin"))
+                .collect(),
+        ));
+        self
+    }
+
+    /// Answer CORS requests from any origin, by mirroring it back. Any site can then call your
+    /// RPCs from its visitors' browsers, so only use it for APIs meant to be public.
+    pub fn cors_any_origin(mut self) -> Self {
+        self.cors_origins = CorsOrigins::Any;
+        self
+    }
+
+    /// Run `hook` on every error before it's sent to the client, be it from a handler, an
+    /// extractor or the protocol layer itself. Handy for redacting messages, normalizing codes
+    /// or logging in one place instead of in every handler.
+    pub fn error_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(RpcError, &MethodDescriptor) -> RpcError + Send + Sync + 'static,
+    {
+        self.error_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Production mode for errors: `Internal` and `Unknown` errors are sent with a generic message
+    /// and no details, and `google.rpc.DebugInfo` details are dropped from every error. The error
+    /// hook runs *before* redaction, so log from there if you want to keep the full error.
+    pub fn redact_errors(mut self, enabled: bool) -> Self {
+        self.redact_errors = enabled;
+        self
+    }
+
+    /// Request headers handlers should pass along to the calls they make themselves (ex.
+    /// `x-request-id`, `authorization`, `accept-language`). Handlers get them, and only them, with
+    /// the `RpcPropagatedHeaders` extractor. Header names are case-insensitive.
+    ///
+    /// Panics if a name isn't a valid header name.
+    pub fn propagate_headers<I, H>(mut self, headers: I) -> Self
+    where
+        I: IntoIterator<Item = H>,
+        H: AsRef<str>,
+    {
+        self.propagate_headers = Arc::new(
+            headers
+                .into_iter()
+                .map(|name| {
+                    HeaderName::from_bytes(name.as_ref().to_lowercase().as_bytes())
+                        .expect("valid header name")
+                })
+                .collect(),
+        );
+        self
+    }
+
+    /// Log the request and response messages of generated RPCs at `DEBUG`, in the RPC's span, with
+    /// sensitive fields masked (see `redact`). Off by default, messages can be large and chatty.
+    #[cfg(feature = "tracing")]
+    pub fn log_messages(mut self, enabled: bool) -> Self {
+        self.log_messages = enabled;
+        self
+    }
+
+    /// Log a warning (under the `axum_connect::slow` target) for every RPC that takes longer than
+    /// `threshold`, with its method and how long it took. Streams count until their last message
+    /// is encoded. Surfaces latency regressions without any tracing infrastructure beyond a log.
+    #[cfg(feature = "tracing")]
+    pub fn slow_threshold(mut self, threshold: Duration) -> Self {
+        self.slow_threshold = Some(threshold);
+        self
+    }
+
+    /// `slow_threshold`, for `method` only. Takes precedence over the router-wide threshold.
+    #[cfg(feature = "tracing")]
+    pub fn method_slow_threshold(mut self, method: MethodDescriptor, threshold: Duration) -> Self {
+        Arc::make_mut(&mut self.method_slow_thresholds).insert(method.path, threshold);
+        self
+    }
+
+    /// The slow threshold for `method`, if any.
+    #[cfg(feature = "tracing")]
+    pub(crate) fn slow_threshold_for(&self, method: Option<&MethodDescriptor>) -> Option<Duration> {
+        method
+            .and_then(|method| self.method_slow_thresholds.get(method.path).copied())
+            .or(self.slow_threshold)
+    }
+
+    /// How many encoded envelopes of a response stream may be buffered for the client at once.
+    /// Envelopes the handler's stream has ready together are written (and flushed) together, up to
+    /// this many at a time. The default, 1, flushes every message on its own, which is what you
+    /// want for live feeds; raise it for chatty streams where throughput matters more than
+    /// latency.
+    ///
+    /// Either way, the handler's stream is only polled as fast as the client reads, so a slow
+    /// client holds the stream back rather than piling up envelopes in memory.
+    pub fn stream_buffer(mut self, envelopes: usize) -> Self {
+        self.stream_buffer = envelopes.max(1);
+        self
+    }
+
+    /// End open response streams cleanly once `shutdown` begins and its drain period is over,
+    /// see `RpcShutdown`.
+    #[cfg(feature = "shutdown")]
+    pub fn shutdown(mut self, shutdown: RpcShutdown) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    /// The longest any RPC may run, enabled by the `timeout` feature. Unary handlers still running
+    /// after `timeout` are dropped and answered with `deadline_exceeded`; response streams still
+    /// open are ended with it. Clients asking for less (with `connect-timeout-ms`) get less.
+    #[cfg(feature = "timeout")]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// `timeout`, for `method` only. Takes precedence over the router-wide `timeout`.
+    #[cfg(feature = "timeout")]
+    pub fn method_timeout(mut self, method: MethodDescriptor, timeout: Duration) -> Self {
+        Arc::make_mut(&mut self.method_timeouts).insert(method.path, timeout);
+        self
+    }
+
+    /// The server's timeout for `method`, if any.
+    #[cfg(feature = "timeout")]
+    pub(crate) fn timeout_for(&self, method: Option<&MethodDescriptor>) -> Option<Duration> {
+        method
+            .and_then(|method| self.method_timeouts.get(method.path).copied())
+            .or(self.timeout)
+    }
+
+    pub(crate) fn from_parts(parts: &request::Parts) -> Self {
+        parts.extensions.get::<Self>().cloned().unwrap_or_default()
+    }
+
+    /// Applies the error hook (if both a hook and the method being called are known), then
+    /// redaction.
+    pub(crate) fn outgoing_error(
+        &self,
+        e: RpcError,
+        method: Option<&MethodDescriptor>,
+    ) -> RpcError {
+        let mut e = match (&self.error_hook, method) {
+            (Some(hook), Some(method)) => hook(e, method),
+            _ => e,
+        };
+
+        if self.redact_errors {
+            match e.code {
+                RpcErrorCode::Internal | RpcErrorCode::Unknown => {
+                    e.message = "Internal Server Error".to_string();
+                    e.details.clear();
+                }
+                _ => e
+                    .details
+                    .retain(|detail| detail.proto_type != DEBUG_INFO_TYPE),
+            }
+        }
+
+        e
+    }
+}
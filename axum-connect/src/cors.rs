@@ -0,0 +1,215 @@
+//! Just enough CORS for browsers to talk to RPC routes without any extra middleware. Generated
+//! routes answer `OPTIONS` preflights with `preflight` and stamp real responses with
+//! `allow_origin`. If you layer `tower_http::cors::CorsLayer` on top it wins: it answers
+//! preflights before they reach the route, and overwrites the headers set here.
+//!
+//! It's opt-in: only origins allowed with `RpcConfig::cors_origins` (or all of them, with
+//! `RpcConfig::cors_any_origin`) get CORS headers. Without either, RPC routes send none and
+//! browsers refuse cross-origin calls, as they would for any other route. Preflights from other
+//! origins get a 403 naming the rejected origin.
+//!
+//! With the `tower-http` feature, `connect_cors` is a `CorsLayer` preset allowing exactly what
+//! Connect needs, for when the whole router (not just RPC routes) needs CORS.
+
+use std::sync::Arc;
+
+use axum::{
+    http::{header, HeaderValue, Request, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+use crate::{
+    config::RpcConfig,
+    error::{RpcError, RpcErrorCode},
+    handler::codec::encode_error,
+};
+
+/// Request headers the Connect protocol (and connect-web) may send.
+pub const CONNECT_REQUEST_HEADERS: &str = "content-type, connect-protocol-version, \
+    connect-timeout-ms, connect-accept-encoding, connect-content-encoding, content-encoding, \
+    accept-encoding, x-user-agent, x-request-id";
+
+/// Response headers a Connect client needs to be able to read.
+pub const CONNECT_RESPONSE_HEADERS: &str =
+    "content-encoding, connect-content-encoding, x-request-id, deprecation, sunset";
+
+/// How long (in seconds) browsers may cache a preflight response.
+const MAX_AGE: &str = "7200";
+
+/// A `CorsLayer` for Connect clients in the browser: any origin, the methods RPCs are served on,
+/// the Connect request headers and `authorization`, with the Connect response headers exposed.
+/// Tighten it up like any other `CorsLayer`, ex. `connect_cors().allow_origin(origins)`.
+///
+/// Unlike `CorsLayer::very_permissive()`, it doesn't allow credentials, nor arbitrary headers.
+#[cfg(feature = "tower-http")]
+pub fn connect_cors() -> tower_http::cors::CorsLayer {
+    use std::time::Duration;
+
+    use axum::http::{HeaderName, Method};
+    use tower_http::cors::{AllowOrigin, CorsLayer};
+
+    fn header_names(list: &str) -> Vec<HeaderName> {
+        list.split(',')
+            .map(|name| HeaderName::from_bytes(name.trim().as_bytes()).unwrap())
+            .collect()
+    }
+
+    let mut allow_headers = header_names(CONNECT_REQUEST_HEADERS);
+    allow_headers.push(header::AUTHORIZATION);
+
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::mirror_request())
+        .allow_methods([Method::GET, Method::POST, Method::OPTIONS])
+        .allow_headers(allow_headers)
+        .expose_headers(header_names(CONNECT_RESPONSE_HEADERS))
+        .max_age(Duration::from_secs(MAX_AGE.parse().unwrap()))
+}
+
+/// Which origins RPC routes answer CORS requests from, see `RpcConfig::cors_origins`.
+#[derive(Clone, Debug, Default)]
+pub(crate) enum CorsOrigins {
+    #[default]
+    None,
+    Any,
+    List(Arc<Vec<HeaderValue>>),
+}
+
+impl CorsOrigins {
+    fn allows(&self, origin: &HeaderValue) -> bool {
+        match self {
+            CorsOrigins::None => false,
+            CorsOrigins::Any => true,
+            CorsOrigins::List(origins) => origins.contains(origin),
+        }
+    }
+}
+
+/// The `Origin` of `request`, if it has one and the `RpcConfig` of the route allows it.
+pub fn allowed_origin<B>(request: &Request<B>) -> Option<HeaderValue> {
+    let origin = request.headers().get(header::ORIGIN)?;
+    let config = request.extensions().get::<RpcConfig>()?;
+
+    config.cors_origins.allows(origin).then(|| origin.clone())
+}
+
+/// Answers an `OPTIONS` request for an RPC route that accepts `allow_methods` (a comma separated
+/// list, ex. "POST, GET, OPTIONS").
+///
+/// Preflights that can't succeed are rejected with a Connect error body that says why, so the
+/// reason shows up in curl and the browser's network tab instead of as an opaque CORS failure:
+/// a 403 for origins that aren't allowed (see `allowed_origin`), a 405 for methods the route
+/// doesn't accept.
+pub fn preflight<B>(allow_methods: &'static str, request: &Request<B>) -> Response {
+    let request_headers = request.headers();
+    let origin = request_headers.get(header::ORIGIN);
+    let requested_method = request_headers.get(header::ACCESS_CONTROL_REQUEST_METHOD);
+
+    let (Some(origin), Some(requested_method)) = (origin, requested_method) else {
+        // Not a CORS preflight, just someone asking what the route supports.
+        return (
+            StatusCode::NO_CONTENT,
+            [(header::ALLOW, HeaderValue::from_static(allow_methods))],
+        )
+            .into_response();
+    };
+
+    let Some(origin) = allowed_origin(request) else {
+        let e = RpcError::new(
+            RpcErrorCode::PermissionDenied,
+            format!(
+                "CORS preflight from origin {} rejected, it isn't allowed by \
+                RpcConfig::cors_origins",
+                origin.to_str().unwrap_or("(invalid)")
+            ),
+        );
+        return rejected_preflight(StatusCode::FORBIDDEN, allow_methods, &e);
+    };
+
+    let requested_method = requested_method.to_str().unwrap_or_default();
+    if !allow_methods
+        .split(',')
+        .any(|method| method.trim().eq_ignore_ascii_case(requested_method))
+    {
+        let e = RpcError::new(
+            RpcErrorCode::Unimplemented,
+            format!(
+                "CORS preflight asked for {}, but this RPC only accepts {}",
+                requested_method, allow_methods
+            ),
+        );
+        return rejected_preflight(StatusCode::METHOD_NOT_ALLOWED, allow_methods, &e);
+    }
+
+    // Allow every Connect header, plus whatever else the client asked for (auth and friends).
+    let allow_headers = match request_headers
+        .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+        .and_then(|h| h.to_str().ok())
+    {
+        Some(requested) if !requested.trim().is_empty() => {
+            format!("{}, {}", CONNECT_REQUEST_HEADERS, requested)
+        }
+        _ => CONNECT_REQUEST_HEADERS.to_string(),
+    };
+
+    let mut response = StatusCode::NO_CONTENT.into_response();
+    let headers = response.headers_mut();
+    headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+    headers.insert(
+        header::ACCESS_CONTROL_ALLOW_METHODS,
+        HeaderValue::from_static(allow_methods),
+    );
+    if let Ok(allow_headers) = HeaderValue::from_str(&allow_headers) {
+        headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, allow_headers);
+    }
+    headers.insert(
+        header::ACCESS_CONTROL_MAX_AGE,
+        HeaderValue::from_static(MAX_AGE),
+    );
+    headers.insert(
+        header::VARY,
+        HeaderValue::from_static(
+            "origin, access-control-request-method, access-control-request-headers",
+        ),
+    );
+
+    response
+}
+
+/// A preflight answered with `status` and `e` as a JSON Connect error body.
+fn rejected_preflight(status: StatusCode, allow_methods: &'static str, e: &RpcError) -> Response {
+    (
+        status,
+        [
+            (header::ALLOW, HeaderValue::from_static(allow_methods)),
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/json"),
+            ),
+        ],
+        encode_error(e, false),
+    )
+        .into_response()
+}
+
+/// Lets the browser hand `response` back to the page at `origin` (see `allowed_origin`) that made
+/// a cross-origin request. Does nothing without an origin (same-origin requests, or origins that
+/// aren't allowed), or if something else already set the CORS headers.
+pub fn allow_origin(origin: Option<HeaderValue>, mut response: Response) -> Response {
+    let Some(origin) = origin else {
+        return response;
+    };
+
+    let headers = response.headers_mut();
+    if headers.contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN) {
+        return response;
+    }
+
+    headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+    headers.insert(
+        header::ACCESS_CONTROL_EXPOSE_HEADERS,
+        HeaderValue::from_static(CONNECT_RESPONSE_HEADERS),
+    );
+    headers.append(header::VARY, HeaderValue::from_static("origin"));
+
+    response
+}
@@ -0,0 +1,138 @@
+use std::{
+    cell::RefCell,
+    sync::{Arc, Mutex},
+};
+
+/// Static description of a single RPC method. Generated services expose one associated const per
+/// method (ex. `HelloWorldService::SAY_HELLO`), and generated routes attach it to every request
+/// as an extension. Handlers copy it into the response extensions too, so middleware that wraps
+/// the whole router (ex. a metrics layer) can see which RPC it's looking at, even across
+/// `merge`/`nest`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MethodDescriptor {
+    /// Fully qualified service name, ex. `hello.HelloWorldService`.
+    pub service: &'static str,
+    /// Method name as written in the proto file, ex. `SayHello`.
+    pub method: &'static str,
+    /// The HTTP path the method is served on, ex. `/hello.HelloWorldService/SayHello`.
+    pub path: &'static str,
+    pub kind: MethodKind,
+    /// Set by `option deprecated = true;` on the method. Calls are answered with a `Deprecation`
+    /// header (and counted, with the `metrics` feature).
+    pub deprecated: bool,
+    /// Set by `option idempotency_level = NO_SIDE_EFFECTS;` (or `IDEMPOTENT`) on the method, which
+    /// makes it safe to retry.
+    pub idempotent: bool,
+    /// When the method goes away, as an HTTP-date (ex. `Sat, 01 Mar 2025 00:00:00 GMT`). Sent as
+    /// a `Sunset` header, see RFC 8594. Configured with `AxumConnectGenSettings::sunsets`.
+    pub sunset: Option<&'static str>,
+    /// Scopes (or roles) callers must hold, set by `option (axum_connect.scopes) = "...";` on the
+    /// method (see `axum_connect/options.proto`). Enforced by `auth::RpcScopesLayer`.
+    pub scopes: &'static [&'static str],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MethodKind {
+    Unary,
+    ServerStreaming,
+    /// Not supported yet, see `handler::unsupported_streaming`.
+    ClientStreaming,
+    /// Not supported yet, see `handler::unsupported_streaming`.
+    BidiStreaming,
+}
+
+thread_local! {
+    /// The registries of the `RpcMethods::record` calls in progress on this thread, innermost last.
+    static RECORDING: RefCell<Vec<RpcMethods>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The RPCs registered on a router, in registration order, for debug pages (see `rpcz`), metric
+/// label sets and the like. axum routers can't be inspected, so build the router inside `record`,
+/// which sees every RPC registered while it runs (on routers merged or nested into it too):
+///
+/// ```ignore
+/// let methods = RpcMethods::new();
+/// let app = methods.record(|| {
+///     Router::new()
+///         .rpc(HelloWorldService::say_hello(say_hello))
+///         .rpc(rpcz("/debug/rpcz"))
+/// });
+/// for method in methods.get() { /* ... */ }
+/// ```
+///
+/// Paths don't include any prefix they were nested under. `rpcz` and `rpc_config` called inside
+/// `record` use this registry too.
+#[derive(Clone, Debug, Default)]
+pub struct RpcMethods(Arc<Mutex<Vec<MethodDescriptor>>>);
+
+impl RpcMethods {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Runs `build`, recording every RPC it registers (on any router) into this registry.
+    pub fn record<F, R>(&self, build: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        /// Ends the recording even if `build` panics.
+        struct Recording;
+
+        impl Drop for Recording {
+            fn drop(&mut self) {
+                RECORDING.with(|recording| recording.borrow_mut().pop());
+            }
+        }
+
+        RECORDING.with(|recording| recording.borrow_mut().push(self.clone()));
+        let _recording = Recording;
+        build()
+    }
+
+    /// The RPCs recorded so far.
+    pub fn get(&self) -> Vec<MethodDescriptor> {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// The registry of the innermost `record` call in progress, if any.
+    pub(crate) fn current() -> Option<Self> {
+        RECORDING.with(|recording| recording.borrow().last().cloned())
+    }
+
+    /// The recorded method served on `path`, under any prefix it's nested at.
+    pub(crate) fn find(&self, path: &str) -> Option<MethodDescriptor> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|method| is_rpc_path(path, method.path))
+            .copied()
+    }
+
+    fn insert(&self, method: MethodDescriptor) {
+        let mut methods = self.0.lock().unwrap();
+        if !methods.iter().any(|existing| existing.path == method.path) {
+            methods.push(method);
+        }
+    }
+}
+
+/// Records that `method` was registered on a router, into the `RpcMethods` being recorded (if
+/// any). Generated routes (and `dynamic`/`raw` ones) call this as they're added, registering the
+/// same method again is a no-op.
+pub fn record_registration(method: MethodDescriptor) {
+    RECORDING.with(|recording| {
+        for methods in recording.borrow().iter() {
+            methods.insert(method);
+        }
+    });
+}
+
+/// Whether a request for `path` is a call to the RPC served on `rpc_path`, either as registered
+/// or nested under a prefix (ex. `/api/hello.HelloWorldService/SayHello`). Only whole path
+/// segments match.
+pub(crate) fn is_rpc_path(path: &str, rpc_path: &str) -> bool {
+    let rpc_path = rpc_path.trim_start_matches('/');
+    path.strip_suffix(rpc_path)
+        .is_some_and(|prefix| prefix.ends_with('/'))
+}
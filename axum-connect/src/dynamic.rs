@@ -0,0 +1,261 @@
+//! Handlers for methods picked at runtime instead of generated ahead of time, enabled by the
+//! `dynamic` feature. Requests and responses are `prost_reflect::DynamicMessage`s, decoded and
+//! encoded (binary or JSON) according to the method's descriptor. Handy for generic gateways, mock
+//! servers and admin tooling:
+//!
+//! ```ignore
+//! let pool = DescriptorPool::decode(DESCRIPTOR_SET)?;
+//! let method = pool
+//!     .get_service_by_name("hello.HelloWorldService")
+//!     .and_then(|service| service.methods().find(|method| method.name() == "SayHello"))
+//!     .unwrap();
+//!
+//! let app = Router::new().rpc(dynamic_unary(method, |_ctx, request: DynamicMessage| async move {
+//!     let mut response = DynamicMessage::new(request.descriptor().parent_pool()
+//!         .get_message_by_name("hello.HelloResponse").unwrap());
+//!     response.set_field_by_name("message", Value::String("Hello!".to_string()));
+//!     Ok(response)
+//! }));
+//! ```
+//!
+//! Or serve every method of a descriptor set loaded at runtime with one handler, for proxies and
+//! federation:
+//!
+//! ```ignore
+//! let app = Router::new().rpc(dynamic_service(&descriptor_set, |method, request| async move {
+//!     upstream.call(method, request).await
+//! })?);
+//! ```
+//!
+//! Only unary methods are supported. The handler gets an `RpcContext` rather than extractors, the
+//! request parts (headers, extensions, ...) are all in there.
+
+use axum::{
+    body::{Body, Bytes},
+    http::Request,
+    response::Response,
+    routing::post,
+    Router,
+};
+use futures::{Future, FutureExt};
+use prost_reflect::{DescriptorError, DescriptorPool, DynamicMessage, MessageDescriptor};
+
+use crate::{
+    cors::{allow_origin, allowed_origin, preflight},
+    descriptor::{record_registration, MethodDescriptor, MethodKind},
+    error::{RpcError, RpcErrorCode},
+    handler::{
+        call_handler,
+        codec::{read_request, ResponseEncoder},
+        method_not_allowed, ResponseTags,
+    },
+    response::RpcResult,
+    router::RpcRouter,
+    service::RpcContext,
+};
+
+/// Returns a registration (for `RpcRouterExt::rpc`) serving the unary `method` with `handler`.
+///
+/// # Panics
+///
+/// If `method` is a streaming method.
+pub fn dynamic_unary<S, H, F>(
+    method: prost_reflect::MethodDescriptor,
+    handler: H,
+) -> impl FnOnce(Router<S>) -> RpcRouter<S>
+where
+    S: Clone + Send + Sync + 'static,
+    H: FnOnce(RpcContext, DynamicMessage) -> F + Clone + Send + Sync + 'static,
+    F: Future<Output = RpcResult<DynamicMessage>> + Send + 'static,
+{
+    let descriptor = method_descriptor(&method);
+    assert!(
+        descriptor.kind == MethodKind::Unary,
+        "{} is not a unary method",
+        method.full_name()
+    );
+
+    move |router: Router<S>| {
+        record_registration(descriptor);
+        router.route(
+            descriptor.path,
+            post(move |mut request: Request<Body>| async move {
+                request.extensions_mut().insert(descriptor);
+                let origin = allowed_origin(&request);
+                allow_origin(origin, call_unary(method, handler, request).await)
+            })
+            .options(|request: Request<Body>| async move { preflight("POST, OPTIONS", &request) })
+            .fallback(|request: Request<Body>| method_not_allowed("POST, OPTIONS", request)),
+        )
+    }
+}
+
+/// Returns a registration (for `RpcRouterExt::rpc`) serving every unary method of every service in
+/// `descriptor_set` (an encoded `FileDescriptorSet`) with `handler`, which is told which method
+/// was called. Each method gets its own route, so the rest of the router is unaffected and paths
+/// outside the set still 404. Streaming methods are skipped.
+pub fn dynamic_service<S, H, F>(
+    descriptor_set: &[u8],
+    handler: H,
+) -> Result<impl FnOnce(Router<S>) -> RpcRouter<S>, DescriptorError>
+where
+    S: Clone + Send + Sync + 'static,
+    H: FnOnce(prost_reflect::MethodDescriptor, DynamicMessage) -> F + Clone + Send + Sync + 'static,
+    F: Future<Output = RpcResult<DynamicMessage>> + Send + 'static,
+{
+    let pool = DescriptorPool::decode(descriptor_set)?;
+    let methods: Vec<_> = pool
+        .services()
+        .flat_map(|service| service.methods())
+        .filter(|method| !method.is_client_streaming() && !method.is_server_streaming())
+        .collect();
+
+    Ok(move |mut router: Router<S>| {
+        for method in methods {
+            let handler = handler.clone();
+            let called = method.clone();
+            router = dynamic_unary(method, move |_ctx, message| handler(called, message))(router);
+        }
+        router
+    })
+}
+
+/// A `MethodDescriptor` for a method only known at runtime. The names are leaked, which is fine
+/// for something registered once at startup.
+pub(crate) fn method_descriptor(method: &prost_reflect::MethodDescriptor) -> MethodDescriptor {
+    fn leak(s: String) -> &'static str {
+        Box::leak(s.into_boxed_str())
+    }
+
+    let service = method.parent_service().full_name().to_string();
+    let path = format!("/{}/{}", service, method.name());
+
+    MethodDescriptor {
+        service: leak(service),
+        method: leak(method.name().to_string()),
+        path: leak(path),
+        kind: match (method.is_client_streaming(), method.is_server_streaming()) {
+            (false, false) => MethodKind::Unary,
+            (false, true) => MethodKind::ServerStreaming,
+            (true, false) => MethodKind::ClientStreaming,
+            (true, true) => MethodKind::BidiStreaming,
+        },
+        deprecated: method
+            .method_descriptor_proto()
+            .options
+            .as_ref()
+            .and_then(|options| options.deprecated)
+            .unwrap_or(false),
+        idempotent: method
+            .method_descriptor_proto()
+            .options
+            .as_ref()
+            .and_then(|options| options.idempotency_level)
+            .is_some_and(|level| level != 0),
+        sunset: None,
+        scopes: leak_scopes(method),
+    }
+}
+
+/// The `(axum_connect.scopes)` option of `method`, if its pool knows the option at all.
+fn leak_scopes(method: &prost_reflect::MethodDescriptor) -> &'static [&'static str] {
+    let Some(option) = method
+        .parent_pool()
+        .get_extension_by_name("axum_connect.scopes")
+    else {
+        return &[];
+    };
+
+    let scopes: Vec<&'static str> = method
+        .options()
+        .get_extension(&option)
+        .as_list()
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|scope| scope.as_str())
+        .map(|scope| &*Box::leak(scope.to_string().into_boxed_str()))
+        .collect();
+
+    Box::leak(scopes.into_boxed_slice())
+}
+
+/// The HTTP transport for dynamic unary calls, the `DynamicMessage` twin of `RpcHandlerUnary`.
+pub(crate) async fn call_unary<H, F>(
+    method: prost_reflect::MethodDescriptor,
+    handler: H,
+    req: Request<Body>,
+) -> Response
+where
+    H: FnOnce(RpcContext, DynamicMessage) -> F,
+    F: Future<Output = RpcResult<DynamicMessage>>,
+{
+    #[cfg(feature = "tracing")]
+    let span = crate::trace::request_span(&req);
+    let tags = ResponseTags::new(&req);
+
+    let fut = async move {
+        let (parts, encoder, payload) = match read_request(req, false).await {
+            Ok(read) => read,
+            Err(res) => return res,
+        };
+
+        let message = match payload.and_then(|bytes| decode(method.input(), bytes, &encoder)) {
+            Ok(message) => message,
+            Err(e) => return encoder.encode_error_response(e),
+        };
+
+        let ctx = RpcContext::from_parts(parts);
+        let config = ctx.config();
+        let handler = async move { handler(ctx, message).await };
+        let res = encoder
+            .before_deadline(call_handler(&config, handler))
+            .await
+            .and_then(|res| res)
+            .and_then(|res| res)
+            .and_then(|res| {
+                if res.descriptor() == method.output() {
+                    Ok(res)
+                } else {
+                    Err(RpcError::internal(format!(
+                        "Handler for {} answered with a {} instead of a {}",
+                        method.full_name(),
+                        res.descriptor().full_name(),
+                        method.output().full_name()
+                    )))
+                }
+            });
+
+        encoder.encode_response(res)
+    }
+    .map(move |res| tags.apply(res));
+
+    #[cfg(feature = "tracing")]
+    let fut = tracing::Instrument::instrument(fut, span);
+
+    fut.await
+}
+
+pub(crate) fn decode(
+    descriptor: MessageDescriptor,
+    bytes: Bytes,
+    encoder: &ResponseEncoder,
+) -> Result<DynamicMessage, RpcError> {
+    if encoder.binary {
+        DynamicMessage::decode(descriptor, bytes).map_err(|e| {
+            RpcError::new(
+                RpcErrorCode::InvalidArgument,
+                format!("Failed to decode binary protobuf. {}", e),
+            )
+        })
+    } else {
+        let mut deserializer = serde_json::Deserializer::from_slice(&bytes);
+        DynamicMessage::deserialize(descriptor, &mut deserializer)
+            .and_then(|message| deserializer.end().map(|_| message))
+            .map_err(|e| {
+                RpcError::new(
+                    RpcErrorCode::InvalidArgument,
+                    format!("Failed to decode JSON protobuf. {}", e),
+                )
+            })
+    }
+}
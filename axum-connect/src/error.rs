@@ -1,14 +1,20 @@
+use std::{collections::HashMap, error::Error, fmt, str::FromStr, sync::Arc};
+
 use axum::http::StatusCode;
 use prost::Message;
 use serde::Serialize;
 
 use crate::{prelude::RpcResult, response::RpcIntoResponse};
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Debug, Serialize)]
 pub struct RpcError {
     pub code: RpcErrorCode,
     pub message: String,
     pub details: Vec<RpcErrorDetail>,
+    /// The underlying error, if any (see `with_source`). Never sent to the client. `None` in
+    /// struct literals (`RpcError { code, message, details, source: None }`).
+    #[serde(skip)]
+    pub source: Option<Arc<dyn Error + Send + Sync>>,
 }
 
 pub trait RpcIntoError {
@@ -27,25 +33,107 @@ impl RpcError {
             code,
             message,
             details: vec![],
+            source: None,
+        }
+    }
+
+    /// Attaches the error that caused this one, so it shows up in `Error::source` chains (ex. when
+    /// logging from an error hook). The source is never sent to the client.
+    pub fn with_source<E>(mut self, source: E) -> Self
+    where
+        E: Error + Send + Sync + 'static,
+    {
+        self.source = Some(Arc::new(source));
+        self
+    }
+
+    /// The first detail of type `M`, decoded (ex. `e.detail::<RetryInfo>()` on an error a client
+    /// got back). `None` if there's no such detail, or it doesn't decode.
+    pub fn detail<M>(&self) -> Option<M>
+    where
+        M: Message + prost::Name + Default,
+    {
+        self.details.iter().find_map(RpcErrorDetail::unpack)
+    }
+}
+
+macro_rules! impl_code_constructors {
+    ($($name:ident => $code:ident),* $(,)?) => {
+        impl RpcError {
+            $(
+                #[doc = concat!(
+                    "Shorthand for `RpcError::new(RpcErrorCode::",
+                    stringify!($code),
+                    ", message.into())`."
+                )]
+                pub fn $name<M>(message: M) -> Self
+                where
+                    M: Into<String>,
+                {
+                    Self::new(RpcErrorCode::$code, message.into())
+                }
+            )*
+        }
+    };
+}
+
+impl_code_constructors!(
+    canceled => Canceled,
+    unknown => Unknown,
+    invalid_argument => InvalidArgument,
+    deadline_exceeded => DeadlineExceeded,
+    not_found => NotFound,
+    already_exists => AlreadyExists,
+    permission_denied => PermissionDenied,
+    resource_exhausted => ResourceExhausted,
+    failed_precondition => FailedPrecondition,
+    aborted => Aborted,
+    out_of_range => OutOfRange,
+    unimplemented => Unimplemented,
+    internal => Internal,
+    unavailable => Unavailable,
+    data_loss => DataLoss,
+    unauthenticated => Unauthenticated,
+);
+
+impl<M> From<(RpcErrorCode, M)> for RpcError
+where
+    M: Into<String>,
+{
+    fn from((code, message): (RpcErrorCode, M)) -> Self {
+        Self::new(code, message.into())
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.message.is_empty() {
+            write!(f, "{}", self.code)
+        } else {
+            write!(f, "{}: {}", self.code, self.message)
         }
     }
 }
 
+impl Error for RpcError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source
+            .as_deref()
+            .map(|source| source as &(dyn Error + 'static))
+    }
+}
+
 impl<C, M> RpcIntoError for (C, M)
 where
     C: Into<RpcErrorCode>,
     M: Into<String>,
 {
     fn rpc_into_error(self) -> RpcError {
-        RpcError {
-            code: self.0.into(),
-            message: self.1.into(),
-            details: vec![],
-        }
+        RpcError::new(self.0.into(), self.1.into())
     }
 }
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Debug, Serialize)]
 pub struct RpcErrorDetail {
     #[serde(rename = "type")]
     pub proto_type: String,
@@ -53,7 +141,256 @@ pub struct RpcErrorDetail {
     pub proto_b62_value: String,
 }
 
-#[derive(Clone, Serialize)]
+impl RpcErrorDetail {
+    /// Wraps `message` as a detail. `proto_type` is the fully qualified proto name of the message,
+    /// without the `type.googleapis.com/` prefix (ex. `google.rpc.RetryInfo`).
+    pub fn new<M>(proto_type: impl Into<String>, message: &M) -> Self
+    where
+        M: Message,
+    {
+        use base64::{engine::general_purpose, Engine as _};
+
+        Self {
+            proto_type: proto_type.into(),
+            // Spec: https://connect.build/docs/protocol/#error-end-stream (padding is omitted).
+            proto_b62_value: general_purpose::STANDARD_NO_PAD.encode(message.encode_to_vec()),
+        }
+    }
+
+    /// Wraps a generated message as a detail, under its own proto name.
+    pub fn from_message<M>(message: &M) -> Self
+    where
+        M: Message + prost::Name,
+    {
+        Self::new(M::full_name(), message)
+    }
+
+    /// Decodes the detail as `M`, if that's its type (going by `proto_type`, with or without the
+    /// `type.googleapis.com/` prefix). The value may be base64 with or without padding.
+    pub fn unpack<M>(&self) -> Option<M>
+    where
+        M: Message + prost::Name + Default,
+    {
+        use base64::{
+            alphabet,
+            engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+            Engine as _,
+        };
+
+        const ENGINE: GeneralPurpose = GeneralPurpose::new(
+            &alphabet::STANDARD,
+            GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+        );
+
+        let proto_type = self.proto_type.trim_start_matches("type.googleapis.com/");
+        if proto_type != M::full_name() {
+            return None;
+        }
+
+        let bytes = ENGINE.decode(&self.proto_b62_value).ok()?;
+        M::decode(bytes.as_slice()).ok()
+    }
+
+    /// A `google.rpc.DebugInfo` detail. These are dropped by `RpcConfig::redact_errors`.
+    pub fn debug_info(detail: impl Into<String>, stack_entries: Vec<String>) -> Self {
+        Self::new(
+            "google.rpc.DebugInfo",
+            &DebugInfo {
+                stack_entries,
+                detail: detail.into(),
+            },
+        )
+    }
+
+    /// A `google.rpc.RetryInfo` detail, telling the client how long to wait before retrying.
+    pub fn retry_info(delay: std::time::Duration) -> Self {
+        Self::new(
+            "google.rpc.RetryInfo",
+            &RetryInfo {
+                retry_delay: Some(pbjson_types::Duration {
+                    seconds: delay.as_secs() as i64,
+                    nanos: delay.subsec_nanos() as i32,
+                }),
+            },
+        )
+    }
+
+    /// A `google.rpc.ErrorInfo` detail: a machine-readable `reason` (ex. `TOKEN_EXPIRED`) within
+    /// `domain`, plus whatever context helps the client handle it.
+    pub fn error_info(
+        reason: impl Into<String>,
+        domain: impl Into<String>,
+        metadata: HashMap<String, String>,
+    ) -> Self {
+        Self::new(
+            "google.rpc.ErrorInfo",
+            &ErrorInfo {
+                reason: reason.into(),
+                domain: domain.into(),
+                metadata,
+            },
+        )
+    }
+}
+
+/// `google.rpc.ErrorInfo`, see:
+/// https://github.com/googleapis/googleapis/blob/master/google/rpc/error_details.proto
+#[derive(Clone, PartialEq, Message)]
+struct ErrorInfo {
+    #[prost(string, tag = "1")]
+    reason: String,
+    #[prost(string, tag = "2")]
+    domain: String,
+    #[prost(map = "string, string", tag = "3")]
+    metadata: HashMap<String, String>,
+}
+
+/// `google.rpc.RetryInfo`, see:
+/// https://github.com/googleapis/googleapis/blob/master/google/rpc/error_details.proto
+#[derive(Clone, PartialEq, Message)]
+struct RetryInfo {
+    #[prost(message, optional, tag = "1")]
+    retry_delay: Option<pbjson_types::Duration>,
+}
+
+/// `google.rpc.DebugInfo`, see:
+/// https://github.com/googleapis/googleapis/blob/master/google/rpc/error_details.proto
+#[derive(Clone, PartialEq, Message)]
+struct DebugInfo {
+    #[prost(string, repeated, tag = "1")]
+    stack_entries: Vec<String>,
+    #[prost(string, tag = "2")]
+    detail: String,
+}
+
+#[cfg(feature = "anyhow")]
+impl RpcIntoError for anyhow::Error {
+    /// Maps to `Internal`, with the full cause chain in a `google.rpc.DebugInfo` detail.
+    fn rpc_into_error(self) -> RpcError {
+        let chain = self
+            .chain()
+            .skip(1)
+            .map(|cause| cause.to_string())
+            .collect();
+        let mut e = RpcError::internal(self.to_string());
+        e.details
+            .push(RpcErrorDetail::debug_info(format!("{:?}", self), chain));
+
+        let source: Box<dyn Error + Send + Sync> = self.into();
+        e.source = Some(Arc::from(source));
+        e
+    }
+}
+
+impl RpcIntoError for std::io::Error {
+    fn rpc_into_error(self) -> RpcError {
+        use std::io::ErrorKind;
+
+        let code = match self.kind() {
+            ErrorKind::NotFound => RpcErrorCode::NotFound,
+            ErrorKind::PermissionDenied => RpcErrorCode::PermissionDenied,
+            ErrorKind::AlreadyExists => RpcErrorCode::AlreadyExists,
+            ErrorKind::InvalidInput | ErrorKind::InvalidData => RpcErrorCode::InvalidArgument,
+            ErrorKind::TimedOut => RpcErrorCode::DeadlineExceeded,
+            ErrorKind::Interrupted => RpcErrorCode::Canceled,
+            ErrorKind::Unsupported => RpcErrorCode::Unimplemented,
+            ErrorKind::OutOfMemory => RpcErrorCode::ResourceExhausted,
+            ErrorKind::ConnectionRefused
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::NotConnected
+            | ErrorKind::BrokenPipe => RpcErrorCode::Unavailable,
+            _ => RpcErrorCode::Internal,
+        };
+
+        RpcError::new(code, self.to_string()).with_source(self)
+    }
+}
+
+impl RpcIntoError for serde_json::Error {
+    fn rpc_into_error(self) -> RpcError {
+        let code = match self.classify() {
+            serde_json::error::Category::Io => RpcErrorCode::Internal,
+            _ => RpcErrorCode::InvalidArgument,
+        };
+
+        RpcError::new(code, self.to_string()).with_source(self)
+    }
+}
+
+/// `google.rpc.Status`, which is how gRPC carries error details (the `grpc-status-details-bin`
+/// trailer), see: https://github.com/googleapis/googleapis/blob/master/google/rpc/status.proto
+#[cfg(any(feature = "tonic", feature = "grpc-proxy"))]
+#[derive(Clone, PartialEq, Message)]
+struct GrpcStatus {
+    #[prost(int32, tag = "1")]
+    code: i32,
+    #[prost(string, tag = "2")]
+    message: String,
+    #[prost(message, repeated, tag = "3")]
+    details: Vec<pbjson_types::Any>,
+}
+
+#[cfg(any(feature = "tonic", feature = "grpc-proxy"))]
+impl RpcIntoError for tonic::Status {
+    /// Same code and message. Details are carried over if the status has any. gRPC's `OK` (which
+    /// has no business being an error) maps to `Unknown`.
+    fn rpc_into_error(self) -> RpcError {
+        use base64::{engine::general_purpose, Engine as _};
+
+        let code = RpcErrorCode::try_from(self.code() as i32).unwrap_or(RpcErrorCode::Unknown);
+        let mut e = RpcError::new(code, self.message().to_string());
+        if let Ok(status) = GrpcStatus::decode(self.details()) {
+            e.details = status
+                .details
+                .into_iter()
+                .map(|detail| RpcErrorDetail {
+                    proto_type: detail
+                        .type_url
+                        .trim_start_matches("type.googleapis.com/")
+                        .to_string(),
+                    proto_b62_value: general_purpose::STANDARD_NO_PAD.encode(detail.value),
+                })
+                .collect();
+        }
+
+        e.with_source(self)
+    }
+}
+
+#[cfg(any(feature = "tonic", feature = "grpc-proxy"))]
+impl From<RpcError> for tonic::Status {
+    fn from(e: RpcError) -> Self {
+        use base64::{engine::general_purpose, Engine as _};
+
+        let code = tonic::Code::from_i32(e.code.into());
+        if e.details.is_empty() {
+            return tonic::Status::new(code, e.message);
+        }
+
+        let details = GrpcStatus {
+            code: code as i32,
+            message: e.message.clone(),
+            details: e
+                .details
+                .iter()
+                .filter_map(|detail| {
+                    Some(pbjson_types::Any {
+                        type_url: format!("type.googleapis.com/{}", detail.proto_type),
+                        value: general_purpose::STANDARD_NO_PAD
+                            .decode(&detail.proto_b62_value)
+                            .ok()?
+                            .into(),
+                    })
+                })
+                .collect(),
+        };
+
+        tonic::Status::with_details(code, e.message, details.encode_to_vec().into())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum RpcErrorCode {
     Canceled,
@@ -74,22 +411,143 @@ pub enum RpcErrorCode {
     Unauthenticated,
 }
 
+impl RpcErrorCode {
+    /// The name used on the wire, see: https://connect.build/docs/protocol/#error-codes
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RpcErrorCode::Canceled => "canceled",
+            RpcErrorCode::Unknown => "unknown",
+            RpcErrorCode::InvalidArgument => "invalid_argument",
+            RpcErrorCode::DeadlineExceeded => "deadline_exceeded",
+            RpcErrorCode::NotFound => "not_found",
+            RpcErrorCode::AlreadyExists => "already_exists",
+            RpcErrorCode::PermissionDenied => "permission_denied",
+            RpcErrorCode::ResourceExhausted => "resource_exhausted",
+            RpcErrorCode::FailedPrecondition => "failed_precondition",
+            RpcErrorCode::Aborted => "aborted",
+            RpcErrorCode::OutOfRange => "out_of_range",
+            RpcErrorCode::Unimplemented => "unimplemented",
+            RpcErrorCode::Internal => "internal",
+            RpcErrorCode::Unavailable => "unavailable",
+            RpcErrorCode::DataLoss => "data_loss",
+            RpcErrorCode::Unauthenticated => "unauthenticated",
+        }
+    }
+}
+
+impl fmt::Display for RpcErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Returned when a number or name doesn't correspond to any `RpcErrorCode`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnknownRpcErrorCode(pub String);
+
+impl fmt::Display for UnknownRpcErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown RPC error code: {}", self.0)
+    }
+}
+
+impl Error for UnknownRpcErrorCode {}
+
+impl FromStr for RpcErrorCode {
+    type Err = UnknownRpcErrorCode;
+
+    /// Parses the wire name of a code (ex. `not_found`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "canceled" => RpcErrorCode::Canceled,
+            "unknown" => RpcErrorCode::Unknown,
+            "invalid_argument" => RpcErrorCode::InvalidArgument,
+            "deadline_exceeded" => RpcErrorCode::DeadlineExceeded,
+            "not_found" => RpcErrorCode::NotFound,
+            "already_exists" => RpcErrorCode::AlreadyExists,
+            "permission_denied" => RpcErrorCode::PermissionDenied,
+            "resource_exhausted" => RpcErrorCode::ResourceExhausted,
+            "failed_precondition" => RpcErrorCode::FailedPrecondition,
+            "aborted" => RpcErrorCode::Aborted,
+            "out_of_range" => RpcErrorCode::OutOfRange,
+            "unimplemented" => RpcErrorCode::Unimplemented,
+            "internal" => RpcErrorCode::Internal,
+            "unavailable" => RpcErrorCode::Unavailable,
+            "data_loss" => RpcErrorCode::DataLoss,
+            "unauthenticated" => RpcErrorCode::Unauthenticated,
+            _ => return Err(UnknownRpcErrorCode(s.to_string())),
+        })
+    }
+}
+
+impl From<RpcErrorCode> for i32 {
+    /// The gRPC status code number, see: https://grpc.github.io/grpc/core/md_doc_statuscodes.html
+    fn from(val: RpcErrorCode) -> Self {
+        match val {
+            RpcErrorCode::Canceled => 1,
+            RpcErrorCode::Unknown => 2,
+            RpcErrorCode::InvalidArgument => 3,
+            RpcErrorCode::DeadlineExceeded => 4,
+            RpcErrorCode::NotFound => 5,
+            RpcErrorCode::AlreadyExists => 6,
+            RpcErrorCode::PermissionDenied => 7,
+            RpcErrorCode::ResourceExhausted => 8,
+            RpcErrorCode::FailedPrecondition => 9,
+            RpcErrorCode::Aborted => 10,
+            RpcErrorCode::OutOfRange => 11,
+            RpcErrorCode::Unimplemented => 12,
+            RpcErrorCode::Internal => 13,
+            RpcErrorCode::Unavailable => 14,
+            RpcErrorCode::DataLoss => 15,
+            RpcErrorCode::Unauthenticated => 16,
+        }
+    }
+}
+
+impl TryFrom<i32> for RpcErrorCode {
+    type Error = UnknownRpcErrorCode;
+
+    /// The inverse of `From<RpcErrorCode> for i32`. Note that `0` (gRPC's `OK`) is not an error.
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        Ok(match value {
+            1 => RpcErrorCode::Canceled,
+            2 => RpcErrorCode::Unknown,
+            3 => RpcErrorCode::InvalidArgument,
+            4 => RpcErrorCode::DeadlineExceeded,
+            5 => RpcErrorCode::NotFound,
+            6 => RpcErrorCode::AlreadyExists,
+            7 => RpcErrorCode::PermissionDenied,
+            8 => RpcErrorCode::ResourceExhausted,
+            9 => RpcErrorCode::FailedPrecondition,
+            10 => RpcErrorCode::Aborted,
+            11 => RpcErrorCode::OutOfRange,
+            12 => RpcErrorCode::Unimplemented,
+            13 => RpcErrorCode::Internal,
+            14 => RpcErrorCode::Unavailable,
+            15 => RpcErrorCode::DataLoss,
+            16 => RpcErrorCode::Unauthenticated,
+            _ => return Err(UnknownRpcErrorCode(value.to_string())),
+        })
+    }
+}
+
 impl From<RpcErrorCode> for StatusCode {
     fn from(val: RpcErrorCode) -> Self {
         match val {
-            // Spec: https://connect.build/docs/protocol/#error-codes
-            RpcErrorCode::Canceled => StatusCode::REQUEST_TIMEOUT,
+            // Spec: https://connectrpc.com/docs/protocol/#error-codes
+            // 499 Client Closed Request, which has no constant.
+            RpcErrorCode::Canceled => StatusCode::from_u16(499).unwrap(),
             RpcErrorCode::Unknown => StatusCode::INTERNAL_SERVER_ERROR,
             RpcErrorCode::InvalidArgument => StatusCode::BAD_REQUEST,
-            RpcErrorCode::DeadlineExceeded => StatusCode::REQUEST_TIMEOUT,
+            RpcErrorCode::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
             RpcErrorCode::NotFound => StatusCode::NOT_FOUND,
             RpcErrorCode::AlreadyExists => StatusCode::CONFLICT,
             RpcErrorCode::PermissionDenied => StatusCode::FORBIDDEN,
             RpcErrorCode::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
-            RpcErrorCode::FailedPrecondition => StatusCode::PRECONDITION_FAILED,
+            RpcErrorCode::FailedPrecondition => StatusCode::BAD_REQUEST,
             RpcErrorCode::Aborted => StatusCode::CONFLICT,
             RpcErrorCode::OutOfRange => StatusCode::BAD_REQUEST,
-            RpcErrorCode::Unimplemented => StatusCode::NOT_FOUND,
+            RpcErrorCode::Unimplemented => StatusCode::NOT_IMPLEMENTED,
             RpcErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
             RpcErrorCode::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
             RpcErrorCode::DataLoss => StatusCode::INTERNAL_SERVER_ERROR,
@@ -0,0 +1,141 @@
+//! `google.protobuf.FieldMask` support for CRUD-style APIs, enabled by the `field-mask` feature.
+//! Masks are checked and applied with the message's descriptor (from the descriptor set the build
+//! writes next to the generated code), so it works for any generated message:
+//!
+//! ```ignore
+//! const DESCRIPTOR_SET: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/proto_descriptor.bin"));
+//!
+//! async fn update_user(State(db): State<Db>, request: UpdateUserRequest) -> RpcResult<User> {
+//!     let pool = DescriptorPool::decode(DESCRIPTOR_SET).unwrap();
+//!     let descriptor = pool.get_message_by_name("users.User").unwrap();
+//!     let mask = request.update_mask.unwrap_or_default();
+//!
+//!     let mut user = db.user(&request.id).await?;
+//!     apply_field_mask(&mut user, &request.user.unwrap_or_default(), &mask, &descriptor)?;
+//!     db.save(&user).await?;
+//!     Ok(user)
+//! }
+//! ```
+//!
+//! Paths are proto field names, dot separated for nested messages (ex. `address.city`). Only the
+//! last segment of a path may be a repeated or map field, and those are replaced as a whole.
+
+use pbjson_types::FieldMask;
+use prost::Message;
+use prost_reflect::{DynamicMessage, Kind, MessageDescriptor};
+
+use crate::{
+    error::{RpcError, RpcErrorCode},
+    response::RpcResult,
+};
+
+fn invalid(message: String) -> RpcError {
+    RpcError::new(RpcErrorCode::InvalidArgument, message)
+}
+
+/// Checks that every path of `mask` names a field of `descriptor`, with only singular message
+/// fields along the way. Fails with `InvalidArgument`, naming the first bad path.
+pub fn validate_field_mask(mask: &FieldMask, descriptor: &MessageDescriptor) -> RpcResult<()> {
+    for path in &mask.paths {
+        let mut message = descriptor.clone();
+        let mut segments = path.split('.').peekable();
+
+        while let Some(segment) = segments.next() {
+            let Some(field) = message.get_field_by_name(segment) else {
+                return Err(invalid(format!(
+                    "Invalid field mask path {}: {} has no field {}",
+                    path,
+                    message.full_name(),
+                    segment
+                )));
+            };
+
+            if segments.peek().is_none() {
+                break;
+            }
+
+            match field.kind() {
+                Kind::Message(next) if !field.is_list() && !field.is_map() => message = next,
+                _ => {
+                    return Err(invalid(format!(
+                        "Invalid field mask path {}: {} is not a singular message field",
+                        path, segment
+                    )))
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Copies the fields named by `mask` from `update` onto `target`, leaving the rest of `target`
+/// alone. A field that is unset in `update` is cleared in `target`. An empty mask updates every
+/// field, which is to say `target` becomes `update`.
+///
+/// The mask is validated first (see `validate_field_mask`), nothing is changed if it's invalid.
+pub fn apply_field_mask<M>(
+    target: &mut M,
+    update: &M,
+    mask: &FieldMask,
+    descriptor: &MessageDescriptor,
+) -> RpcResult<()>
+where
+    M: Message + Default + Clone,
+{
+    validate_field_mask(mask, descriptor)?;
+
+    if mask.paths.is_empty() {
+        *target = update.clone();
+        return Ok(());
+    }
+
+    let mut dynamic_target = to_dynamic(target, descriptor)?;
+    let dynamic_update = to_dynamic(update, descriptor)?;
+    for path in &mask.paths {
+        let path: Vec<_> = path.split('.').collect();
+        copy_path(&mut dynamic_target, &dynamic_update, &path);
+    }
+
+    *target = M::decode(dynamic_target.encode_to_vec().as_slice())
+        .map_err(|e| RpcError::internal(format!("Failed to apply field mask. {}", e)))?;
+    Ok(())
+}
+
+fn to_dynamic<M>(message: &M, descriptor: &MessageDescriptor) -> RpcResult<DynamicMessage>
+where
+    M: Message,
+{
+    DynamicMessage::decode(descriptor.clone(), message.encode_to_vec().as_slice()).map_err(|e| {
+        RpcError::internal(format!(
+            "Message doesn't match the {} descriptor. {}",
+            descriptor.full_name(),
+            e
+        ))
+    })
+}
+
+/// `path` is known to be valid.
+fn copy_path(target: &mut DynamicMessage, source: &DynamicMessage, path: &[&str]) {
+    let Some(field) = target.descriptor().get_field_by_name(path[0]) else {
+        return;
+    };
+
+    if path.len() == 1 {
+        if source.has_field(&field) {
+            target.set_field(&field, source.get_field(&field).into_owned());
+        } else {
+            target.clear_field(&field);
+        }
+        return;
+    }
+
+    let source_field = source.get_field(&field);
+    let (Some(source), Some(target)) = (
+        source_field.as_message(),
+        target.get_field_mut(&field).as_message_mut(),
+    ) else {
+        return;
+    };
+    copy_path(target, source, &path[1..]);
+}
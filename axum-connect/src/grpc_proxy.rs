@@ -0,0 +1,292 @@
+//! A Connect front for an upstream gRPC server, enabled by the `grpc-proxy` feature. Browsers (and
+//! anything else that speaks Connect) get JSON or binary Connect, the upstream only ever sees
+//! plain gRPC. Messages are transcoded with the descriptors of the proxied services, so no
+//! generated code is needed:
+//!
+//! ```ignore
+//! const DESCRIPTOR_SET: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/proto_descriptor.bin"));
+//!
+//! let upstream = Channel::from_static("http://[::1]:50051").connect().await?;
+//! let app = Router::new().rpc(grpc_proxy(DESCRIPTOR_SET, upstream)?);
+//! ```
+//!
+//! - Request headers are forwarded as gRPC metadata, minus the ones that only mean something to
+//!   the Connect hop (content type, compression, `connect-*`, hop-by-hop headers). A
+//!   `connect-timeout-ms` becomes the gRPC deadline.
+//! - Response metadata (on success and on error) is sent back as response headers. gRPC trailers
+//!   are not, Connect unary responses have nowhere to put them.
+//! - gRPC statuses become Connect errors with the same code, message and details. Like handler
+//!   errors, they go through the error hook and redaction of the `RpcConfig`.
+//!
+//! Unary and server-streaming methods are proxied, client and bidi streaming methods are skipped.
+
+use axum::{
+    body::Body,
+    http::{header, uri::PathAndQuery, HeaderMap, Request},
+    response::Response,
+    routing::post,
+    Router,
+};
+use futures::{Future, FutureExt, StreamExt};
+use prost::Message;
+use prost_reflect::{DescriptorError, DescriptorPool, DynamicMessage, MessageDescriptor};
+use tonic::{
+    client::Grpc,
+    codec::{Codec, DecodeBuf, Decoder, EncodeBuf, Encoder},
+    metadata::MetadataMap,
+    transport::Channel,
+    Status,
+};
+
+use crate::{
+    cors::{allow_origin, allowed_origin, preflight},
+    descriptor::record_registration,
+    dynamic::{decode, method_descriptor},
+    error::{RpcError, RpcIntoError},
+    handler::{
+        codec::{connect_timeout, read_request, ResponseEncoder},
+        method_not_allowed, ResponseTags,
+    },
+    router::RpcRouter,
+    stream::RpcStreamItem,
+};
+
+/// Returns a registration (for `RpcRouterExt::rpc`) proxying every unary and server-streaming
+/// method of every service in `descriptor_set` (an encoded `FileDescriptorSet`) to `upstream`.
+pub fn grpc_proxy<S>(
+    descriptor_set: &[u8],
+    upstream: Channel,
+) -> Result<impl FnOnce(Router<S>) -> RpcRouter<S>, DescriptorError>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let pool = DescriptorPool::decode(descriptor_set)?;
+    let methods: Vec<_> = pool
+        .services()
+        .flat_map(|service| service.methods())
+        .filter(|method| !method.is_client_streaming())
+        .collect();
+
+    Ok(move |mut router: Router<S>| {
+        for method in methods {
+            let descriptor = method_descriptor(&method);
+            let upstream = Grpc::new(upstream.clone());
+
+            record_registration(descriptor);
+            router = router.route(
+                descriptor.path,
+                post(move |mut request: Request<Body>| async move {
+                    request.extensions_mut().insert(descriptor);
+                    let origin = allowed_origin(&request);
+                    allow_origin(origin, proxy(upstream, method, request).await)
+                })
+                .options(
+                    |request: Request<Body>| async move { preflight("POST, OPTIONS", &request) },
+                )
+                .fallback(|request: Request<Body>| method_not_allowed("POST, OPTIONS", request)),
+            );
+        }
+        router
+    })
+}
+
+async fn proxy(
+    upstream: Grpc<Channel>,
+    method: prost_reflect::MethodDescriptor,
+    req: Request<Body>,
+) -> Response {
+    #[cfg(feature = "tracing")]
+    let span = crate::trace::request_span(&req);
+    let tags = ResponseTags::new(&req);
+    let for_streaming = method.is_server_streaming();
+
+    let fut = async move {
+        let (parts, encoder, payload) = match read_request(req, for_streaming).await {
+            Ok(read) => read,
+            Err(res) => return res,
+        };
+
+        let request = payload.and_then(|bytes| {
+            let message = decode(method.input(), bytes, &encoder)?;
+            let mut request = tonic::Request::new(message);
+            *request.metadata_mut() = MetadataMap::from_headers(forwarded_headers(&parts.headers));
+            if let Some(timeout) = connect_timeout(&parts.headers) {
+                request.set_timeout(timeout);
+            }
+            Ok(request)
+        });
+
+        let path = match PathAndQuery::try_from(format!(
+            "/{}/{}",
+            method.parent_service().full_name(),
+            method.name()
+        )) {
+            Ok(path) => path,
+            Err(e) => return encoder.encode_error_response(RpcError::internal(e.to_string())),
+        };
+
+        let request = match request {
+            Ok(request) => request,
+            Err(e) => return encoder.encode_error_response(e),
+        };
+
+        if for_streaming {
+            proxy_server_streaming(upstream, path, method.output(), request, encoder).await
+        } else {
+            proxy_unary(upstream, path, method.output(), request, encoder).await
+        }
+    }
+    .map(move |res| tags.apply(res));
+
+    #[cfg(feature = "tracing")]
+    let fut = tracing::Instrument::instrument(fut, span);
+
+    fut.await
+}
+
+async fn proxy_unary(
+    mut upstream: Grpc<Channel>,
+    path: PathAndQuery,
+    output: MessageDescriptor,
+    request: tonic::Request<DynamicMessage>,
+    encoder: ResponseEncoder,
+) -> Response {
+    let codec = DynamicCodec { output };
+    let response = match ready(&mut upstream).await {
+        Ok(()) => upstream.unary(request, path, codec).await,
+        Err(status) => Err(status),
+    };
+
+    match response {
+        Ok(response) => {
+            let (metadata, message, _) = response.into_parts();
+            with_metadata(encoder.encode_response(Ok(message)), metadata)
+        }
+        Err(status) => {
+            let metadata = status.metadata().clone();
+            with_metadata(
+                encoder.encode_error_response(status.rpc_into_error()),
+                metadata,
+            )
+        }
+    }
+}
+
+async fn proxy_server_streaming(
+    mut upstream: Grpc<Channel>,
+    path: PathAndQuery,
+    output: MessageDescriptor,
+    request: tonic::Request<DynamicMessage>,
+    encoder: ResponseEncoder,
+) -> Response {
+    let codec = DynamicCodec { output };
+    let response = match ready(&mut upstream).await {
+        Ok(()) => upstream.server_streaming(request, path, codec).await,
+        Err(status) => Err(status),
+    };
+
+    match response {
+        Ok(response) => {
+            let (metadata, stream, _) = response.into_parts();
+            let stream =
+                stream.map(|item| RpcStreamItem::from(item.map_err(RpcIntoError::rpc_into_error)));
+            with_metadata(encoder.encode_stream(stream), metadata)
+        }
+        Err(status) => {
+            let metadata = status.metadata().clone();
+            with_metadata(
+                encoder.encode_error_response(status.rpc_into_error()),
+                metadata,
+            )
+        }
+    }
+}
+
+fn ready(upstream: &mut Grpc<Channel>) -> impl Future<Output = Result<(), Status>> + '_ {
+    upstream
+        .ready()
+        .map(|res| res.map_err(|e| Status::unavailable(format!("Upstream unavailable: {}", e))))
+}
+
+/// The request headers worth forwarding: everything but the ones describing the Connect hop.
+fn forwarded_headers(headers: &HeaderMap) -> HeaderMap {
+    const DROPPED: &[&str] = &[
+        "accept-encoding",
+        "connection",
+        "content-encoding",
+        "content-length",
+        "content-type",
+        "host",
+        "keep-alive",
+        "proxy-connection",
+        "te",
+        "trailer",
+        "transfer-encoding",
+        "upgrade",
+    ];
+
+    headers
+        .iter()
+        .filter(|(name, _)| {
+            let name = name.as_str();
+            !DROPPED.contains(&name) && !name.starts_with("connect-") && !name.starts_with("grpc-")
+        })
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect()
+}
+
+fn with_metadata(mut res: Response, metadata: MetadataMap) -> Response {
+    for (name, value) in metadata.into_headers().iter() {
+        if name == header::CONTENT_TYPE || name.as_str().starts_with("grpc-") {
+            continue;
+        }
+        res.headers_mut().append(name.clone(), value.clone());
+    }
+    res
+}
+
+/// Binary protobuf for `DynamicMessage`s, which (unlike generated types) need to be told what
+/// they are decoding.
+struct DynamicCodec {
+    output: MessageDescriptor,
+}
+
+impl Codec for DynamicCodec {
+    type Encode = DynamicMessage;
+    type Decode = DynamicMessage;
+    type Encoder = DynamicEncoder;
+    type Decoder = DynamicDecoder;
+
+    fn encoder(&mut self) -> Self::Encoder {
+        DynamicEncoder
+    }
+
+    fn decoder(&mut self) -> Self::Decoder {
+        DynamicDecoder(self.output.clone())
+    }
+}
+
+struct DynamicEncoder;
+
+impl Encoder for DynamicEncoder {
+    type Item = DynamicMessage;
+    type Error = Status;
+
+    fn encode(&mut self, item: Self::Item, dst: &mut EncodeBuf<'_>) -> Result<(), Self::Error> {
+        item.encode(dst)
+            .map_err(|e| Status::internal(format!("Failed to encode request: {}", e)))
+    }
+}
+
+struct DynamicDecoder(MessageDescriptor);
+
+impl Decoder for DynamicDecoder {
+    type Item = DynamicMessage;
+    type Error = Status;
+
+    fn decode(&mut self, src: &mut DecodeBuf<'_>) -> Result<Option<Self::Item>, Self::Error> {
+        DynamicMessage::decode(self.0.clone(), src)
+            .map(Some)
+            .map_err(|e| Status::internal(format!("Failed to decode upstream response: {}", e)))
+    }
+}
@@ -1,32 +1,652 @@
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap},
+    convert::Infallible,
+    hash::{Hash, Hasher},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, OnceLock,
+    },
+    time::Instant,
+};
+
+use async_stream::stream;
 use axum::{
-    body::{self, Body},
-    extract::FromRequest,
-    http::{header, request, Request, StatusCode},
+    body::{to_bytes, Body, Bytes},
+    http::{header, request, HeaderMap, Method, Request, StatusCode},
     response::{IntoResponse, Response},
+    RequestExt,
+};
+use futures::{
+    future::{self, Either},
+    Future, FutureExt, Stream, StreamExt,
+};
+use http_body_util::LengthLimitError;
+use prost::{
+    bytes::{Buf, BufMut, BytesMut},
+    Message,
 };
-use prost::Message;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-use crate::prelude::{RpcError, RpcErrorCode};
+use crate::{
+    auth::check_scopes,
+    config::RpcConfig,
+    descriptor::MethodDescriptor,
+    prelude::{RpcError, RpcErrorCode, RpcResult},
+    stream::{RpcStreamItem, StreamEnd},
+};
+
+// Most of what's in here is deliberately not generic (or only generic over the message type).
+// The handler impls are stamped out once per extractor arity, per handler, so anything they do
+// themselves gets monomorphized a LOT. Keep them thin and do the real work here.
 
 pub(crate) struct ReqResInto {
     pub binary: bool,
 }
 
+/// Attached to the extensions of every streaming response. Filled in with the error code if the
+/// stream ends in an error, which is otherwise only visible inside the body (the status is always
+/// 200). Response classifiers check it once the body is done.
+#[derive(Clone, Default)]
+pub(crate) struct StreamOutcome(pub Arc<OnceLock<RpcErrorCode>>);
+
+/// Encodes everything that leaves a handler: errors (after they pass through the router's error
+/// hook) and messages in the negotiated codec.
+pub(crate) struct ResponseEncoder {
+    pub binary: bool,
+    pub for_streaming: bool,
+    pub config: RpcConfig,
+    pub method: Option<MethodDescriptor>,
+    #[cfg(feature = "tracing")]
+    pub span: tracing::Span,
+    pub started: Instant,
+    /// Request and response message sizes, for the access log.
+    request_size: AtomicUsize,
+    response_size: AtomicUsize,
+    /// When the call must be done by, see `before_deadline`.
+    #[cfg(feature = "timeout")]
+    pub deadline: Option<tokio::time::Instant>,
+    pub outcome: Option<StreamOutcome>,
+    /// Set for unary GET (and HEAD) calls, whose responses are tagged with an `ETag`.
+    pub cacheable: bool,
+}
+
+impl ResponseEncoder {
+    /// An encoder for a codec that was already settled on (ex. by a non-HTTP transport).
+    pub fn new(parts: &request::Parts, binary: bool, for_streaming: bool) -> Self {
+        let config = RpcConfig::from_parts(parts);
+        let method = parts.extensions.get::<MethodDescriptor>().copied();
+
+        Self {
+            binary,
+            for_streaming,
+            #[cfg(feature = "timeout")]
+            deadline: deadline(parts, &config, method.as_ref()),
+            config,
+            method,
+            // Handlers run inside their RPC's span, see `trace::request_span`.
+            #[cfg(feature = "tracing")]
+            span: tracing::Span::current(),
+            started: Instant::now(),
+            request_size: AtomicUsize::new(0),
+            response_size: AtomicUsize::new(0),
+            outcome: None,
+            cacheable: false,
+        }
+    }
+
+    /// Checks the protocol headers (or the query string, for unary GET requests) and settles on a
+    /// codec. On failure the error is already encoded into a response.
+    pub fn negotiate(parts: &mut request::Parts, for_streaming: bool) -> Result<Self, Response> {
+        // JSON until proven otherwise, errors are always JSON anyway.
+        let mut encoder = Self::new(parts, false, for_streaming);
+
+        let checked = if !for_streaming && is_get(parts) {
+            encoder.cacheable = true;
+            decode_check_query(parts)
+        } else {
+            decode_check_headers(parts, for_streaming)
+        };
+
+        match checked {
+            Ok(ReqResInto { binary }) => {
+                protocol_trace!(
+                    encoder.method.as_ref(),
+                    binary,
+                    for_streaming,
+                    http.method = %parts.method,
+                    "negotiated codec"
+                );
+                encoder.binary = binary;
+                #[cfg(feature = "tracing")]
+                crate::trace::record_codec(&encoder.span, binary);
+                Ok(encoder)
+            }
+            Err(e) => {
+                protocol_trace!(encoder.method.as_ref(), error = %e, "codec negotiation failed");
+                Err(encoder.encode_error_response(e))
+            }
+        }
+    }
+
+    pub fn encode_error_response(&self, e: RpcError) -> Response {
+        self.record_error(&e);
+        protocol_trace!(self.method.as_ref(), code = %e.code, "mapping error");
+        let e = self.config.outgoing_error(e, self.method.as_ref());
+        protocol_trace!(
+            self.method.as_ref(),
+            code = %e.code,
+            streaming = self.for_streaming,
+            "encoded error response"
+        );
+        let mut res = encode_error_response(&e, self.binary, self.for_streaming);
+        // Lets response classifiers see the code without parsing the body.
+        res.extensions_mut().insert(e.code);
+        res
+    }
+
+    /// Encodes the EndStreamResponse, the final frame of every stream: the error (if any) and
+    /// trailers of `end`.
+    pub fn encode_end_frame(&self, end: StreamEnd) -> Bytes {
+        let error = end.error.map(|e| {
+            self.record_error(&e);
+            protocol_trace!(self.method.as_ref(), code = %e.code, "mapping error");
+            let e = self.config.outgoing_error(e, self.method.as_ref());
+            if let Some(outcome) = &self.outcome {
+                let _ = outcome.0.set(e.code.clone());
+            }
+            e
+        });
+        if error.is_none() {
+            self.record_ok();
+        }
+
+        let frame = encode_end_stream(error.as_ref(), &end.trailers);
+        protocol_trace!(
+            self.method.as_ref(),
+            code = ?error.as_ref().map(|e| &e.code),
+            flags = frame[0],
+            size = frame.len() - 5,
+            "encoded end of stream"
+        );
+        Bytes::from(frame)
+    }
+
+    /// Encodes the result of a unary handler.
+    pub fn encode_response<M>(&self, res: RpcResult<M>) -> Response
+    where
+        M: EncodeMessage,
+    {
+        // The only part that depends on `M`, the rest is shared by every handler.
+        let body = res.and_then(|message| {
+            let mut buf = BytesMut::new();
+            message.encode_message(self.binary, &mut buf)?;
+            Ok(buf.freeze())
+        });
+
+        self.encode_response_body(body)
+    }
+
+    fn encode_response_body(&self, body: RpcResult<Bytes>) -> Response {
+        match body {
+            Ok(body) => {
+                protocol_trace!(
+                    self.method.as_ref(),
+                    size = body.len(),
+                    "encoded unary response"
+                );
+                self.record_response_size(body.len());
+                self.record_ok();
+                self.unary_response(body)
+            }
+            Err(e) => self.encode_error_response(e),
+        }
+    }
+
+    // These are all no-ops without the `tracing` and `metrics` features, and an access log.
+
+    /// Records a successful call. Called exactly once per call, as is `record_error`, after the
+    /// sizes are recorded.
+    pub fn record_ok(&self) {
+        #[cfg(feature = "tracing")]
+        crate::trace::record_ok(&self.span);
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_finished(self.method.as_ref(), "ok", self.started);
+        self.finished(None);
+    }
+
+    fn record_error(&self, e: &RpcError) {
+        #[cfg(feature = "tracing")]
+        crate::trace::record_error(&self.span, e);
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_finished(self.method.as_ref(), e.code.as_str(), self.started);
+        self.finished(Some(e.code.clone()));
+    }
+
+    pub fn record_request_size(&self, size: usize) {
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_request_size(self.method.as_ref(), size);
+        self.request_size.store(size, Ordering::Relaxed);
+    }
+
+    fn record_response_size(&self, size: usize) {
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_response_size(self.method.as_ref(), size);
+        self.response_size.fetch_add(size, Ordering::Relaxed);
+    }
+
+    /// The access log and slow RPC warning, once the call is done.
+    fn finished(&self, code: Option<RpcErrorCode>) {
+        let duration = self.started.elapsed();
+
+        #[cfg(feature = "tracing")]
+        if let Some(threshold) = self.config.slow_threshold_for(self.method.as_ref()) {
+            if duration > threshold {
+                tracing::warn!(
+                    target: "axum_connect::slow",
+                    rpc.path = self.method.map(|method| method.path).unwrap_or_default(),
+                    duration_ms = duration.as_secs_f64() * 1000.0,
+                    threshold_ms = threshold.as_secs_f64() * 1000.0,
+                    "slow RPC"
+                );
+            }
+        }
+
+        if let Some(sink) = &self.config.access_log {
+            sink(&crate::access_log::RpcAccessRecord {
+                method: self.method,
+                binary: self.binary,
+                streaming: self.for_streaming,
+                duration,
+                request_size: self.request_size.load(Ordering::Relaxed),
+                response_size: self.response_size.load(Ordering::Relaxed),
+                code,
+            });
+        }
+    }
+
+    fn unary_response(&self, body: Bytes) -> Response {
+        let etag = self.cacheable.then(|| etag(&body));
+        (
+            StatusCode::OK,
+            [(
+                header::CONTENT_TYPE,
+                if self.binary {
+                    "application/proto"
+                } else {
+                    "application/json"
+                },
+            )],
+            // Unary bodies are fully materialized, so spare proxies and clients chunked encoding.
+            [(header::CONTENT_LENGTH, body.len())],
+            etag.map(|etag| [(header::ETAG, etag)]),
+            body,
+        )
+            .into_response()
+    }
+
+    /// Encodes a single message as a stream envelope, using (and reusing) the spare capacity of
+    /// `buf`. Once the frames split off of it are dropped, `BytesMut::reserve` reclaims their
+    /// space, so a stream settles into a single allocation instead of one per message.
+    pub fn encode_envelope<M>(&self, message: &M, buf: &mut BytesMut) -> RpcResult<Bytes>
+    where
+        M: EncodeMessage,
+    {
+        // Serialize straight into the frame, then go back and fill in the size.
+        buf.clear();
+        buf.put_bytes(0, 5);
+        if let Err(e) = message.encode_message(self.binary, buf) {
+            buf.clear();
+            return Err(e);
+        }
+
+        let size = ((buf.len() - 5) as u32).to_be_bytes();
+        buf[1..5].copy_from_slice(&size);
+        protocol_trace!(
+            self.method.as_ref(),
+            flags = buf[0],
+            size = buf.len() - 5,
+            "encoded envelope"
+        );
+
+        Ok(buf.split().freeze())
+    }
+
+    /// Encodes the stream returned by a server-streaming handler. The first `End` item (or error)
+    /// ends the stream.
+    pub fn encode_stream<St, M>(mut self, stream: St) -> Response
+    where
+        St: Stream<Item = RpcStreamItem<M>> + Send + 'static,
+        M: EncodeMessage + Send + 'static,
+    {
+        let binary = self.binary;
+        let outcome = StreamOutcome::default();
+        self.outcome = Some(outcome.clone());
+        // The body is pulled by hyper as the client reads it, so at most this many envelopes are
+        // ever held here, however slow the client.
+        let buffer = self.config.stream_buffer.max(1);
+        let frames = self
+            .encode_frames(stream)
+            .ready_chunks(buffer)
+            .map(|frames| Result::<Bytes, Infallible>::Ok(concat_frames(frames)));
+
+        let mut res = (
+            StatusCode::OK,
+            [(
+                header::CONTENT_TYPE,
+                if binary {
+                    "application/connect+proto"
+                } else {
+                    "application/connect+json"
+                },
+            )],
+            Body::from_stream(frames),
+        )
+            .into_response();
+        res.extensions_mut().insert(outcome);
+        res
+    }
+
+    /// Runs a handler (`fut`) until the deadline of the call, if it has one: the sooner of the
+    /// client's `connect-timeout-ms` and the server's timeout (see `RpcConfig::timeout`). Without
+    /// the `timeout` feature calls have no deadline.
+    #[cfg(feature = "timeout")]
+    pub async fn before_deadline<F: Future>(&self, fut: F) -> RpcResult<F::Output> {
+        match self.deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, fut)
+                .await
+                .map_err(|_| deadline_exceeded()),
+            None => Ok(fut.await),
+        }
+    }
+
+    #[cfg(not(feature = "timeout"))]
+    pub async fn before_deadline<F: Future>(&self, fut: F) -> RpcResult<F::Output> {
+        Ok(fut.await)
+    }
+
+    /// Resolves with the error to end a stream with, once it's cut short by the deadline of the
+    /// call or a server shutdown. Never resolves otherwise.
+    fn stream_cutoff(&self) -> impl Future<Output = RpcError> + Send + 'static {
+        let shutdown = Box::pin(self.stream_shutdown());
+        let deadline = Box::pin(self.stream_deadline());
+        future::select(shutdown, deadline).map(|either| either.factor_first().0)
+    }
+
+    #[cfg(feature = "timeout")]
+    fn stream_deadline(&self) -> impl Future<Output = RpcError> + Send + 'static {
+        let deadline = self.deadline;
+        async move {
+            match deadline {
+                Some(deadline) => {
+                    tokio::time::sleep_until(deadline).await;
+                    deadline_exceeded()
+                }
+                None => future::pending().await,
+            }
+        }
+    }
+
+    #[cfg(not(feature = "timeout"))]
+    fn stream_deadline(&self) -> impl Future<Output = RpcError> + Send + 'static {
+        future::pending()
+    }
+
+    /// Resolves with the error to end a stream with once the server's shutdown drain period (see
+    /// `RpcShutdown`) is over. Never resolves without one.
+    #[cfg(feature = "shutdown")]
+    fn stream_shutdown(&self) -> impl Future<Output = RpcError> + Send + 'static {
+        let shutdown = self.config.shutdown.clone();
+        async move {
+            match shutdown {
+                Some(shutdown) => {
+                    shutdown.drained().await;
+                    shutdown.error()
+                }
+                None => future::pending().await,
+            }
+        }
+    }
+
+    #[cfg(not(feature = "shutdown"))]
+    fn stream_shutdown(&self) -> impl Future<Output = RpcError> + Send + 'static {
+        future::pending()
+    }
+
+    /// The envelopes of a stream, including the final EndStreamResponse. The first `End` item
+    /// (or error) ends the stream.
+    pub fn encode_frames<St, M>(self, stream: St) -> impl Stream<Item = Bytes> + Send + 'static
+    where
+        St: Stream<Item = RpcStreamItem<M>> + Send + 'static,
+        M: EncodeMessage + Send + 'static,
+    {
+        stream! {
+            let mut stream = Box::pin(stream);
+            let mut buf = BytesMut::new();
+            let mut end = StreamEnd::ok();
+            let mut cutoff = Box::pin(self.stream_cutoff());
+            loop {
+                let item = match future::select(stream.next(), cutoff.as_mut()).await {
+                    Either::Left((Some(item), _)) => item,
+                    Either::Left((None, _)) => break,
+                    Either::Right((e, _)) => {
+                        end = StreamEnd::error(e);
+                        break;
+                    }
+                };
+
+                let message = match item {
+                    RpcStreamItem::Message(message) => message,
+                    RpcStreamItem::End(item) => {
+                        end = item;
+                        break;
+                    }
+                };
+
+                match self.encode_envelope(&message, &mut buf) {
+                    Ok(frame) => {
+                        self.record_response_size(frame.len());
+                        yield frame;
+                    }
+                    Err(e) => {
+                        end = StreamEnd::error(e);
+                        break;
+                    }
+                }
+            }
+
+            yield self.encode_end_frame(end);
+        }
+    }
+}
+
+/// A response message, encoded by `ResponseEncoder` in the negotiated codec.
+pub(crate) trait EncodeMessage {
+    /// Appends the encoded message to `buf`.
+    fn encode_message(&self, binary: bool, buf: &mut BytesMut) -> RpcResult<()>;
+}
+
+impl<M> EncodeMessage for M
+where
+    M: Message + Serialize,
+{
+    fn encode_message(&self, binary: bool, buf: &mut BytesMut) -> RpcResult<()> {
+        if binary {
+            buf.reserve(self.encoded_len());
+            self.encode(buf)
+                .map_err(|e| RpcError::internal(e.to_string()))
+        } else {
+            serde_json::to_writer(buf.writer(), self)
+                .map_err(|e| RpcError::internal(format!("Failed to serialize response: {}", e)))
+        }
+    }
+}
+
+/// A message a raw handler (see `crate::raw`) already encoded, in the codec of the call.
+pub(crate) struct EncodedMessage(pub Bytes);
+
+impl EncodeMessage for EncodedMessage {
+    fn encode_message(&self, _binary: bool, buf: &mut BytesMut) -> RpcResult<()> {
+        buf.extend_from_slice(&self.0);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "timeout")]
+fn deadline_exceeded() -> RpcError {
+    RpcError::new(
+        RpcErrorCode::DeadlineExceeded,
+        "Deadline exceeded".to_string(),
+    )
+}
+
+/// The sooner of the client's and the server's timeout for the call, from now.
+#[cfg(feature = "timeout")]
+fn deadline(
+    parts: &request::Parts,
+    config: &RpcConfig,
+    method: Option<&MethodDescriptor>,
+) -> Option<tokio::time::Instant> {
+    [connect_timeout(&parts.headers), config.timeout_for(method)]
+        .into_iter()
+        .flatten()
+        .min()
+        .map(|timeout| tokio::time::Instant::now() + timeout)
+}
+
+/// The client's timeout for the call, from the `connect-timeout-ms` header.
+#[cfg(any(feature = "timeout", feature = "grpc-proxy"))]
+pub(crate) fn connect_timeout(headers: &HeaderMap) -> Option<std::time::Duration> {
+    headers
+        .get("connect-timeout-ms")?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+        .map(std::time::Duration::from_millis)
+}
+
+/// Joins envelopes that are written to the client together.
+fn concat_frames(mut frames: Vec<Bytes>) -> Bytes {
+    if frames.len() == 1 {
+        return frames.pop().unwrap();
+    }
+
+    let mut buf = BytesMut::with_capacity(frames.iter().map(Bytes::len).sum());
+    for frame in frames {
+        buf.put(frame);
+    }
+    buf.freeze()
+}
+
+/// An EndStreamResponse, see: https://connect.build/docs/protocol/#error-end-stream
+#[derive(Serialize)]
+struct EndStreamResponse<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a RpcError>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    metadata: BTreeMap<&'a str, Vec<&'a str>>,
+}
+
+/// Same envelope as `ResponseEncoder::encode_envelope`, but flagged as the end of the stream and
+/// always JSON (whatever the codec of the rest of the stream).
+fn encode_end_stream(error: Option<&RpcError>, trailers: &HeaderMap) -> Vec<u8> {
+    let mut metadata = BTreeMap::<_, Vec<_>>::new();
+    for (name, value) in trailers {
+        // Binary values would need `-bin` base64 handling, nobody has asked for that yet.
+        if let Ok(value) = value.to_str() {
+            metadata.entry(name.as_str()).or_default().push(value);
+        }
+    }
+
+    let mut v = vec![0x2, 0, 0, 0, 0];
+    serde_json::to_writer(&mut v, &EndStreamResponse { error, metadata }).unwrap();
+    let size = ((v.len() - 5) as u32).to_be_bytes();
+    v[1..5].copy_from_slice(&size);
+
+    v
+}
+
 pub(crate) fn encode_error(e: &RpcError, for_streaming: bool) -> Vec<u8> {
     if for_streaming {
-        // See `encode_message` for the format. It's the same, except always JSON.
-        let mut v = vec![0x2, 0, 0, 0, 0];
-        serde_json::to_writer(&mut v, &e).unwrap();
-        let size = ((v.len() - 5) as u32).to_be_bytes();
-        v[1..5].copy_from_slice(&size);
-
-        v
+        encode_end_stream(Some(e), &HeaderMap::new())
     } else {
         serde_json::to_vec(&e).unwrap()
     }
 }
 
+/// Unary GET requests, and HEAD requests for the same (which axum serves with the GET route, minus
+/// the body).
+fn is_get(parts: &request::Parts) -> bool {
+    parts.method == Method::GET || parts.method == Method::HEAD
+}
+
+/// A strong `ETag` for a unary response body. Only meant to be stable for a given build of the
+/// server, which is as long as the body itself is.
+fn etag(body: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+/// Guesses how a request that never made it to a handler (rejected by middleware, or for a
+/// method that isn't served) wants its errors encoded, from its content type:
+/// `(binary, for_streaming)`. `None` if it doesn't look like a Connect call at all.
+pub(crate) fn guess_codec(headers: &HeaderMap) -> Option<(bool, bool)> {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+
+    match content_type.as_str() {
+        "application/json" => Some((false, false)),
+        "application/proto" => Some((true, false)),
+        "application/connect+json" => Some((false, true)),
+        "application/connect+proto" => Some((true, true)),
+        // Unary GETs have no body, and so no content type.
+        _ if headers.contains_key("connect-protocol-version") => Some((false, false)),
+        _ => None,
+    }
+}
+
+/// Encodes `e` for a call rejected by middleware before it reached its route, for whatever kind of
+/// call `req` looks like. The error goes through the `RpcConfig` in the request extensions, if
+/// any, so the error hook (with `method`, or the method served on the path, as recorded by the
+/// `RpcMethods` the config was applied under) and redaction apply as they do to handler errors.
+pub(crate) fn encode_rejection<B>(
+    req: &Request<B>,
+    e: RpcError,
+    method: Option<MethodDescriptor>,
+) -> Response {
+    let (binary, for_streaming) = guess_codec(req.headers()).unwrap_or_default();
+    let config = req.extensions().get::<RpcConfig>();
+    let e = rejection_error(config, req.uri().path(), e, method);
+
+    let mut res = encode_error_response(&e, binary, for_streaming);
+    res.extensions_mut().insert(e.code);
+    res
+}
+
+/// `e` as sent for a call to `path` rejected by middleware, see `encode_rejection`.
+pub(crate) fn rejection_error(
+    config: Option<&RpcConfig>,
+    path: &str,
+    e: RpcError,
+    method: Option<MethodDescriptor>,
+) -> RpcError {
+    match config {
+        Some(config) => {
+            let method = method.or_else(|| config.methods.as_ref()?.find(path));
+            config.outgoing_error(e, method.as_ref())
+        }
+        None => e,
+    }
+}
+
 // Encode an error into a Response.
 pub(crate) fn encode_error_response(
     e: &RpcError,
@@ -50,64 +670,120 @@ pub(crate) fn encode_error_response(
         )
             .into_response()
     } else {
+        let body = encode_error(e, false);
         (
             StatusCode::from(e.code.clone()),
             [(header::CONTENT_TYPE, "application/json")],
-            encode_error(e, false),
+            [(header::CONTENT_LENGTH, body.len())],
+            body,
         )
             .into_response()
     }
 }
 
+/// The query parameters of a unary GET request, other than the message itself.
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub(crate) struct UnaryGetQuery {
-    pub message: String,
     pub encoding: String,
     pub base64: Option<usize>,
     pub compression: Option<String>,
     pub connect: Option<String>,
 }
 
-pub(crate) fn decode_check_query(parts: &request::Parts) -> Result<ReqResInto, Response> {
+/// The message of a unary GET request. Kept apart from `UnaryGetQuery` so that negotiating the
+/// codec doesn't have to allocate a copy of (a potentially large) message just to drop it again.
+#[derive(Deserialize, Debug)]
+struct UnaryGetMessage {
+    message: String,
+    base64: Option<usize>,
+    compression: Option<String>,
+}
+
+/// The most a compressed GET message may inflate to, same as axum's default body limit.
+#[cfg(feature = "gzip")]
+const MAX_DECOMPRESSED_LEN: u64 = 2 * 1024 * 1024;
+
+/// Checks the `compression` of a unary GET request. Unsupported ones are `Unimplemented`, per the
+/// spec.
+fn check_get_compression(compression: Option<&str>) -> Result<(), RpcError> {
+    match compression {
+        None | Some("identity") => Ok(()),
+        #[cfg(feature = "gzip")]
+        Some("gzip") => Ok(()),
+        Some(compression) => Err(RpcError::unimplemented(format!(
+            "Unsupported query.compression: {}",
+            compression
+        ))),
+    }
+}
+
+#[cfg(feature = "gzip")]
+fn decompress_get_message(compression: Option<&str>, message: Bytes) -> Result<Bytes, RpcError> {
+    use std::io::Read;
+
+    if compression != Some("gzip") {
+        return Ok(message);
+    }
+
+    let mut decompressed = Vec::new();
+    flate2::read::GzDecoder::new(&message[..])
+        .take(MAX_DECOMPRESSED_LEN + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(|e| {
+            RpcError::new(
+                RpcErrorCode::InvalidArgument,
+                format!("Failed to decompress query.message. {}", e),
+            )
+        })?;
+
+    if decompressed.len() as u64 > MAX_DECOMPRESSED_LEN {
+        return Err(RpcError::new(
+            RpcErrorCode::ResourceExhausted,
+            "Decompressed query.message is too large".to_string(),
+        ));
+    }
+
+    Ok(Bytes::from(decompressed))
+}
+
+#[cfg(not(feature = "gzip"))]
+fn decompress_get_message(_compression: Option<&str>, message: Bytes) -> Result<Bytes, RpcError> {
+    Ok(message)
+}
+
+fn parse_query<T: DeserializeOwned>(parts: &request::Parts) -> Result<T, RpcError> {
     let query_str = match parts.uri.query() {
         Some(x) => x,
         None => {
-            return Err(encode_error_response(
-                &RpcError::new(RpcErrorCode::InvalidArgument, "Missing query".into()),
-                false,
-                false,
+            return Err(RpcError::new(
+                RpcErrorCode::InvalidArgument,
+                "Missing query".into(),
             ))
         }
     };
 
-    let query = match serde_qs::from_str::<UnaryGetQuery>(query_str) {
-        Ok(x) => x,
-        Err(err) => {
-            return Err(encode_error_response(
-                &RpcError::new(
-                    RpcErrorCode::InvalidArgument,
-                    format!("Wrong query, {}", err),
-                ),
-                false,
-                false,
-            ))
-        }
-    };
+    serde_qs::from_str::<T>(query_str).map_err(|err| {
+        RpcError::new(
+            RpcErrorCode::InvalidArgument,
+            format!("Wrong query, {}", err),
+        )
+    })
+}
+
+pub(crate) fn decode_check_query(parts: &request::Parts) -> Result<ReqResInto, RpcError> {
+    let query: UnaryGetQuery = parse_query(parts)?;
 
     let binary = match query.encoding.as_str() {
         "json" => false,
         "proto" => true,
         s => {
-            return Err(encode_error_response(
-                &RpcError::new(
-                    RpcErrorCode::InvalidArgument,
-                    format!("Wrong or unknown query.encoding: {}", s),
-                ),
-                true,
-                true,
+            return Err(RpcError::new(
+                RpcErrorCode::InvalidArgument,
+                format!("Wrong or unknown query.encoding: {}", s),
             ))
         }
     };
+    check_get_compression(query.compression.as_deref())?;
 
     Ok(ReqResInto { binary })
 }
@@ -115,18 +791,14 @@ pub(crate) fn decode_check_query(parts: &request::Parts) -> Result<ReqResInto, R
 pub(crate) fn decode_check_headers(
     parts: &mut request::Parts,
     for_streaming: bool,
-) -> Result<ReqResInto, Response> {
+) -> Result<ReqResInto, RpcError> {
     // Check the version header, if specified.
     if let Some(version) = parts.headers.get("connect-protocol-version") {
         let version = version.to_str().unwrap_or_default();
         if version != "1" {
-            return Err(encode_error_response(
-                &RpcError::new(
-                    RpcErrorCode::InvalidArgument,
-                    format!("Unsupported protocol version: {}", version),
-                ),
-                true,
-                for_streaming,
+            return Err(RpcError::new(
+                RpcErrorCode::InvalidArgument,
+                format!("Unsupported protocol version: {}", version),
             ));
         }
     }
@@ -151,24 +823,16 @@ pub(crate) fn decode_check_headers(
             ("application/connect+json", true) => false,
             ("application/connect+proto", true) => true,
             (s, _) => {
-                return Err(encode_error_response(
-                    &RpcError::new(
-                        RpcErrorCode::InvalidArgument,
-                        format!("Wrong or unknown Content-Type: {}", s),
-                    ),
-                    true,
-                    true,
+                return Err(RpcError::new(
+                    RpcErrorCode::InvalidArgument,
+                    format!("Wrong or unknown Content-Type: {}", s),
                 ))
             }
         },
         None => {
-            return Err(encode_error_response(
-                &RpcError::new(
-                    RpcErrorCode::InvalidArgument,
-                    "Missing Content-Type header".to_string(),
-                ),
-                true,
-                true,
+            return Err(RpcError::new(
+                RpcErrorCode::InvalidArgument,
+                "Missing Content-Type header".to_string(),
             ))
         }
     };
@@ -176,154 +840,157 @@ pub(crate) fn decode_check_headers(
     Ok(ReqResInto { binary })
 }
 
-pub(crate) fn decode_request_payload_from_query<M, S>(
+/// Reads the raw request message, from the query string for unary GET requests and from the body
+/// for everything else. Axum-connect only supports unary request types, so there is only ever
+/// one message. The body should already be limited (see `RequestExt::with_limited_body`) so that
+/// `DefaultBodyLimit` is honored.
+///
+/// This is the hot path, so the payload is never copied once it's read: a body that arrives as a
+/// single frame is handed back as-is, and everything downstream (envelopes, prost, serde) works on
+/// `Bytes` or slices of it (the `simd-json` feature excepted, it needs a mutable copy).
+pub(crate) async fn read_request_payload(
     parts: &request::Parts,
-    _state: &S,
-    as_binary: bool,
-) -> Result<M, Response>
-where
-    M: Message + DeserializeOwned + Default,
-    S: Send + Sync + 'static,
-{
-    let for_streaming = false;
-
-    let query_str = match parts.uri.query() {
-        Some(x) => x,
-        None => {
-            return Err(encode_error_response(
-                &RpcError::new(RpcErrorCode::InvalidArgument, "Missing query".into()),
-                false,
-                false,
-            ))
-        }
-    };
+    body: Body,
+) -> Result<Bytes, RpcError> {
+    if is_get(parts) {
+        let query: UnaryGetMessage = parse_query(parts)?;
 
-    let query = match serde_qs::from_str::<UnaryGetQuery>(query_str) {
-        Ok(x) => x,
-        Err(err) => {
-            return Err(encode_error_response(
-                &RpcError::new(
-                    RpcErrorCode::InvalidArgument,
-                    format!("Wrong query, {}", err),
-                ),
-                false,
-                false,
-            ))
-        }
-    };
+        let message = if query.base64 == Some(1) {
+            use base64::{engine::general_purpose, Engine as _};
 
-    let message = if query.base64 == Some(1) {
-        use base64::{engine::general_purpose, Engine as _};
-
-        match general_purpose::URL_SAFE.decode(&query.message) {
-            Ok(x) => x,
-            Err(err) => {
-                return Err(encode_error_response(
-                    &RpcError::new(
+            general_purpose::URL_SAFE
+                .decode(&query.message)
+                .map(Bytes::from)
+                .map_err(|err| {
+                    RpcError::new(
                         RpcErrorCode::InvalidArgument,
                         format!("Wrong query.message, {}", err),
-                    ),
-                    false,
-                    false,
-                ))
-            }
+                    )
+                })?
+        } else {
+            Bytes::from(query.message)
+        };
+
+        // Compressed messages are binary, so they're always base64 encoded in practice.
+        return decompress_get_message(query.compression.as_deref(), message);
+    }
+
+    to_bytes(body, usize::MAX).await.map_err(|e| {
+        // The limit is axum's `DefaultBodyLimit`, which wraps the body in `http_body_util::Limited`.
+        let code = if is_length_limit_error(&e) {
+            RpcErrorCode::ResourceExhausted
+        } else {
+            RpcErrorCode::InvalidArgument
+        };
+        RpcError::new(code, format!("Failed to read request body. {}", e))
+    })
+}
+
+fn is_length_limit_error(e: &(dyn std::error::Error + 'static)) -> bool {
+    let mut source = Some(e);
+    while let Some(e) = source {
+        if e.is::<LengthLimitError>() {
+            return true;
         }
-    } else {
-        query.message.as_bytes().to_vec()
-    };
+        source = e.source();
+    }
+    false
+}
+
+/// Unwraps a single enveloped message (see `ResponseEncoder::encode_envelope`).
+pub(crate) fn decode_envelope(mut bytes: Bytes) -> Result<Bytes, RpcError> {
+    if bytes.len() < 5 {
+        return Err(RpcError::invalid_argument("Truncated envelope"));
+    }
 
+    let flags = bytes[0];
+    let size = u32::from_be_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]) as usize;
+    if flags & 0x1 != 0 {
+        return Err(RpcError::unimplemented(
+            "Compressed messages are not supported",
+        ));
+    }
+    if bytes.len() - 5 != size {
+        return Err(RpcError::invalid_argument(format!(
+            "Envelope claims {} bytes but carries {}",
+            size,
+            bytes.len() - 5
+        )));
+    }
+
+    bytes.advance(5);
+    Ok(bytes)
+}
+
+pub(crate) fn decode_message<M>(bytes: Bytes, as_binary: bool) -> Result<M, RpcError>
+where
+    M: Message + DeserializeOwned + Default,
+{
     if as_binary {
-        let message: M = M::decode(&message[..]).map_err(|e| {
-            encode_error_response(
-                &RpcError::new(
-                    RpcErrorCode::InvalidArgument,
-                    format!("Failed to decode binary protobuf. {}", e),
-                ),
-                as_binary,
-                for_streaming,
+        M::decode(bytes).map_err(|e| {
+            RpcError::new(
+                RpcErrorCode::InvalidArgument,
+                format!("Failed to decode binary protobuf. {}", e),
             )
-        })?;
-
-        Ok(message)
+        })
     } else {
-        let message: M = serde_json::from_slice(&message).map_err(|e| {
-            encode_error_response(
-                &RpcError::new(
-                    RpcErrorCode::InvalidArgument,
-                    format!("Failed to decode json. {}", e),
-                ),
-                as_binary,
-                for_streaming,
+        decode_json(bytes).map_err(|e| {
+            RpcError::new(
+                RpcErrorCode::InvalidArgument,
+                format!("Failed to decode JSON protobuf. {}", e),
             )
-        })?;
+        })
+    }
+}
+
+#[cfg(not(feature = "simd-json"))]
+fn decode_json<M: DeserializeOwned>(bytes: Bytes) -> Result<M, serde_json::Error> {
+    serde_json::from_slice(&bytes)
+}
 
-        Ok(message)
+/// simd-json parses in place, so it needs its own (mutable) copy of the payload. Anything it
+/// rejects is handed to serde_json, which has the final say on what's valid and on the error
+/// message the client sees.
+#[cfg(feature = "simd-json")]
+fn decode_json<M: DeserializeOwned>(bytes: Bytes) -> Result<M, serde_json::Error> {
+    let mut scratch = bytes.to_vec();
+    match simd_json::serde::from_slice(&mut scratch) {
+        Ok(message) => Ok(message),
+        Err(_) => serde_json::from_slice(&bytes),
     }
 }
 
-pub(crate) async fn decode_request_payload<M, S>(
+/// Everything the HTTP transport does before the message type matters: negotiates the codec and
+/// reads the request payload (out of its envelope, for streaming calls). On negotiation failure
+/// the error is already encoded into a response. Payload errors are returned as is, so they can
+/// be reported after the extractors'.
+pub(crate) async fn read_request(
     req: Request<Body>,
-    state: &S,
-    as_binary: bool,
     for_streaming: bool,
-) -> Result<M, Response>
-where
-    M: Message + DeserializeOwned + Default,
-    S: Send + Sync + 'static,
-{
-    // Axum-connect only supports unary request types, so we can ignore for_streaming.
-    if as_binary {
-        let bytes = body::to_bytes(req.into_body(), usize::MAX)
-            .await
-            .map_err(|e| {
-                encode_error_response(
-                    &RpcError::new(
-                        RpcErrorCode::InvalidArgument,
-                        format!("Failed to read request body. {}", e),
-                    ),
-                    as_binary,
-                    for_streaming,
-                )
-            })?;
+) -> Result<(request::Parts, ResponseEncoder, RpcResult<Bytes>), Response> {
+    let (mut parts, body) = req.with_limited_body().into_parts();
+    let encoder = ResponseEncoder::negotiate(&mut parts, for_streaming)?;
+    if let Err(e) = check_scopes(&mut parts).await {
+        return Err(encoder.encode_error_response(e));
+    }
 
-        let message: M = M::decode(bytes).map_err(|e| {
-            encode_error_response(
-                &RpcError::new(
-                    RpcErrorCode::InvalidArgument,
-                    format!("Failed to decode binary protobuf. {}", e),
-                ),
-                as_binary,
-                for_streaming,
-            )
-        })?;
+    let payload = read_request_payload(&parts, body).await;
+    if let Ok(bytes) = &payload {
+        protocol_trace!(
+            encoder.method.as_ref(),
+            size = bytes.len(),
+            binary = encoder.binary,
+            "read request payload"
+        );
+        encoder.record_request_size(bytes.len());
+    }
 
-        Ok(message)
+    // Streaming requests carry their one message in an envelope, same as the responses.
+    let payload = if for_streaming {
+        payload.and_then(decode_envelope)
     } else {
-        let str = match String::from_request(req, state).await {
-            Ok(value) => value,
-            Err(e) => {
-                return Err(encode_error_response(
-                    &RpcError::new(
-                        RpcErrorCode::InvalidArgument,
-                        format!("Failed to read request body. {}", e),
-                    ),
-                    as_binary,
-                    for_streaming,
-                ));
-            }
-        };
-
-        let message: M = serde_json::from_str(&str).map_err(|e| {
-            encode_error_response(
-                &RpcError::new(
-                    RpcErrorCode::InvalidArgument,
-                    format!("Failed to decode JSON protobuf. {}", e),
-                ),
-                as_binary,
-                for_streaming,
-            )
-        })?;
+        payload
+    };
 
-        Ok(message)
-    }
+    Ok((parts, encoder, payload))
 }
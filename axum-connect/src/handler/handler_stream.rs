@@ -1,24 +1,22 @@
-use std::{convert::Infallible, pin::Pin};
+use std::{panic::AssertUnwindSafe, pin::Pin};
 
-use async_stream::stream;
-use axum::{
-    body::Body,
-    http::{header, Request, StatusCode},
-    response::{IntoResponse, Response},
-};
-use futures::{Future, Stream, StreamExt};
+use axum::{body::Body, http::Request, response::Response};
+use futures::{Future, FutureExt, StreamExt};
 use prost::Message;
 use serde::{de::DeserializeOwned, Serialize};
 
 use crate::{
     error::RpcIntoError,
     parts::RpcFromRequestParts,
-    prelude::{RpcError, RpcErrorCode},
-    response::RpcIntoResponse,
+    response::{RpcIntoResponse, RpcIntoStream, RpcResult},
+    service::{RpcContext, RpcService, RpcStreamReply, ServerStreaming},
+    stream::{RpcStreamItem, StreamEnd},
 };
 
-use super::codec::{
-    decode_check_headers, decode_request_payload, encode_error, encode_error_response, ReqResInto,
+use super::{
+    call_handler,
+    codec::{decode_message, read_request},
+    recover_panic, ResponseTags,
 };
 
 pub trait RpcHandlerStream<TMReq, TMRes, TUid, TState>:
@@ -29,222 +27,118 @@ pub trait RpcHandlerStream<TMReq, TMRes, TUid, TState>:
     fn call(self, req: Request<Body>, state: TState) -> Self::Future;
 }
 
-// TODO: Get "connect-timeout-ms" (number as string) and apply timeout.
 // TODO: Parse request metadata from:
 //      - [0-9a-z]*!"-bin" ASCII value
 //      - [0-9a-z]*-bin" (base64 encoded binary)
 // TODO: Allow response to send back both leading and trailing metadata.
-// This is here because writing Rust macros sucks a**. So I uncomment this when I'm trying to modify
-// the below macro.
-// #[allow(unused_parens, non_snake_case, unused_mut)]
-// impl<TMReq, TMRes, TInto, TFnItem, TFnFut, TFn, TState, T1>
-//     RpcHandlerStream<TMReq, TMRes, (T1, TMReq), TState> for TFn
-// where
-//     TMReq: Message + DeserializeOwned + Default + Send + 'static,
-//     TMRes: Message + Serialize + Send + 'static,
-//     TInto: RpcIntoResponse<TMRes>,
-//     TFnItem: Stream<Item = TInto> + Send + Sized + 'static,
-//     TFnFut: Future<Output = TFnItem> + Send + Sync,
-//     TFn: FnOnce(T1, TMReq) -> TFnFut + Clone + Send + Sync + 'static,
-//     TState: Send + Sync + 'static,
-//     T1: RpcFromRequestParts<TMRes, TState> + Send,
-// {
-//     type Future = Pin<Box<dyn Future<Output = Response> + Send>>;
-
-//     fn call(self, req: Request<Body>, state: TState) -> Self::Future {
-//         Box::pin(async move {
-//             let (mut parts, body) = req.into_parts();
-
-//             let ReqResInto { binary } = match decode_check_headers(&mut parts, true) {
-//                 Ok(binary) => binary,
-//                 Err(e) => return e,
-//             };
-
-//             let state = &state;
-
-//             let t1 = match T1::rpc_from_request_parts(&mut parts, state).await {
-//                 Ok(value) => value,
-//                 Err(e) => {
-//                     let e = e.rpc_into_error();
-//                     return encode_error_response(&e, binary, true);
-//                 }
-//             };
-
-//             let req = Request::from_parts(parts, body);
-
-//             let proto_req: TMReq = match decode_request_payload(req, state, binary, true).await {
-//                 Ok(value) => value,
-//                 Err(e) => return e,
-//             };
-
-//             let mut res = Box::pin(self(t1, proto_req).await);
+/// The HTTP transport for server-streaming calls, see `RpcHandlerUnary`.
+impl<H, TMReq, TMRes, TUid, TState> RpcHandlerStream<TMReq, TMRes, TUid, TState> for H
+where
+    H: RpcService<TMReq, TMRes, ServerStreaming, TUid, TState, Reply = RpcStreamReply<TMRes>>,
+    TMReq: Message + DeserializeOwned + Default + Send + 'static,
+    TMRes: Message + Serialize + Send + 'static,
+    TState: Send + Sync + 'static,
+{
+    type Future = Pin<Box<dyn Future<Output = Response> + Send>>;
+
+    fn call(self, req: Request<Body>, state: TState) -> Self::Future {
+        #[cfg(feature = "tracing")]
+        let span = crate::trace::request_span(&req);
+        let tags = ResponseTags::new(&req);
+
+        let fut = async move {
+            let (parts, encoder, payload) = match read_request(req, true).await {
+                Ok(read) => read,
+                Err(res) => return res,
+            };
+
+            let message = payload.and_then(|bytes| decode_message(bytes, encoder.binary));
+
+            #[cfg(feature = "tracing")]
+            let log = crate::redact::RpcMessageLog::for_call(&parts, &encoder.config);
+            #[cfg(feature = "tracing")]
+            if let (Some(log), Ok(message)) = (&log, &message) {
+                log.request(message);
+            }
 
-//             let res = stream! {
-//                 while let Some(item) = res.next().await {
-//                     let rpc_item = item.rpc_into_response();
-//                     match rpc_item {
-//                         Ok(rpc_item) => {
-//                             if binary {
-//                                 let mut res = vec![0x2, 0, 0, 0, 0];
-//                                 if let Err(e) = rpc_item.encode(&mut res) {
-//                                     let e = RpcError::new(RpcErrorCode::Internal, e.to_string());
-//                                     yield Result::<Vec<u8>, Infallible>::Ok(encode_error(&e, true));
-//                                     break;
-//                                 }
-//                                 let size = ((res.len() - 5) as u32).to_be_bytes();
-//                                 res[1..5].copy_from_slice(&size);
-//                                 yield Ok(res);
-//                             } else {
-//                                 let mut res = vec![0x2, 0, 0, 0, 0];
-//                                 if let Err(e) = serde_json::to_writer(&mut res, &rpc_item) {
-//                                     let e = RpcError::new(RpcErrorCode::Internal, e.to_string());
-//                                     yield Ok(encode_error(&e, true));
-//                                     break;
-//                                 }
-//                                 let size = ((res.len() - 5) as u32).to_be_bytes();
-//                                 res[1..5].copy_from_slice(&size);
-//                                 yield Ok(res);
-//                             }
-//                         },
-//                         Err(e) => {
-//                             yield Ok(encode_error(&e, binary));
-//                             break;
-//                         }
-//                     }
-//                 }
+            let reply = self.call_rpc(RpcContext::from_parts(parts), message, state);
+            match encoder.before_deadline(reply).await.and_then(|res| res) {
+                Ok(stream) => {
+                    #[cfg(feature = "tracing")]
+                    let stream = match log {
+                        Some(log) => stream
+                            .inspect(move |item| {
+                                if let RpcStreamItem::Message(message) = item {
+                                    log.response(message);
+                                }
+                            })
+                            .boxed(),
+                        None => stream,
+                    };
+                    encoder.encode_stream(stream)
+                }
+                Err(e) => encoder.encode_error_response(e),
+            }
+        }
+        .map(move |res| tags.apply(res));
 
-//                 // EndStreamResponse, see: https://connect.build/docs/protocol/#error-end-stream
-//                 // TODO: Support returning trailers (they would need to bundle in the error type).
-//                 if binary {
-//                     yield Result::<Vec<u8>, Infallible>::Ok(vec![0x2, 0, 0, 0, 0]);
-//                 } else {
-//                     yield Result::<Vec<u8>, Infallible>::Ok(vec![0x2, 0, 0, 0, 2, b'{', b'}']);
-//                 }
-//             };
+        #[cfg(feature = "tracing")]
+        let fut = tracing::Instrument::instrument(fut, span);
 
-//             (
-//                 StatusCode::OK,
-//                 [(
-//                     header::CONTENT_TYPE,
-//                     if binary {
-//                         "application/connect+proto"
-//                     } else {
-//                         "application/connect+json"
-//                     },
-//                 )],
-//                 Body::from_stream(res),
-//             )
-//                 .into_response()
-//         })
-//     }
-// }
+        Box::pin(fut)
+    }
+}
 
+// Same deal as the unary impls: keep these thin, the transport does the heavy lifting.
 macro_rules! impl_handler {
     (
         [$($ty:ident),*]
     ) => {
         #[allow(unused_parens, non_snake_case, unused_mut)]
-        impl<TMReq, TMRes, TInto, TFnItem, TFnFut, TFn, TState, $($ty,)*>
-            RpcHandlerStream<TMReq, TMRes, ($($ty,)* TMReq), TState> for TFn
+        impl<TMReq, TMRes, TInto, TVia, TFnFut, TFn, TState, $($ty,)*>
+            RpcService<TMReq, TMRes, ServerStreaming, (($($ty,)* TMReq), TVia), TState> for TFn
         where
             TMReq: Message + DeserializeOwned + Default + Send + 'static,
             TMRes: Message + Serialize + Send + 'static,
-            TInto: RpcIntoResponse<TMRes>,
-            TFnItem: Stream<Item = TInto> + Send + Sized + 'static,
-            TFnFut: Future<Output = TFnItem> + Send + Sync,
+            TInto: RpcIntoStream<TMRes, TVia>,
+            TFnFut: Future<Output = TInto> + Send,
             TFn: FnOnce($($ty,)* TMReq) -> TFnFut + Clone + Send + Sync + 'static,
             TState: Send + Sync + 'static,
             $( $ty: RpcFromRequestParts<TMRes, TState> + Send, )*
         {
-
-            type Future = Pin<Box<dyn Future<Output = Response> + Send>>;
-
-            fn call(self, req: Request<Body>, state: TState) -> Self::Future {
+            type Reply = RpcStreamReply<TMRes>;
+
+            fn call_rpc(
+                self,
+                mut ctx: RpcContext,
+                message: RpcResult<TMReq>,
+                state: TState,
+            ) -> Pin<Box<dyn Future<Output = RpcResult<Self::Reply>> + Send>> {
                 Box::pin(async move {
-                    let (mut parts, body) = req.into_parts();
-
-                    let ReqResInto { binary } = match decode_check_headers(&mut parts, true) {
-                        Ok(binary) => binary,
-                        Err(e) => return e,
-                    };
-
                     let state = &state;
 
                     $(
-                    let $ty = match $ty::rpc_from_request_parts(&mut parts, state).await {
-                        Ok(value) => value,
-                        Err(e) => {
-                            let e = e.rpc_into_error();
-                            return encode_error_response(&e, binary, true);
-                        }
-                    };
+                        let $ty = $ty::rpc_from_request_parts(&mut ctx.parts, state)
+                            .await
+                            .map_err(RpcIntoError::rpc_into_error)?;
                     )*
 
-                    let req = Request::from_parts(parts, body);
-
-                    let proto_req: TMReq = match decode_request_payload(req, state, binary, true).await {
-                        Ok(value) => value,
-                        Err(e) => return e,
-                    };
+                    let message = message?;
+                    let config = ctx.config();
 
-                    let mut res = Box::pin(self($($ty,)* proto_req).await);
+                    let handler = async move { self($($ty,)* message).await };
+                    let res = call_handler(&config, handler).await?.rpc_into_stream()?;
 
-                    let res = stream! {
-                        while let Some(item) = res.next().await {
-                            let rpc_item = item.rpc_into_response();
-                            match rpc_item {
-                                Ok(rpc_item) => {
-                                    if binary {
-                                        let mut res = vec![0x2, 0, 0, 0, 0];
-                                        if let Err(e) = rpc_item.encode(&mut res) {
-                                            let e = RpcError::new(RpcErrorCode::Internal, e.to_string());
-                                            yield Result::<Vec<u8>, Infallible>::Ok(encode_error(&e, true));
-                                            break;
-                                        }
-                                        let size = ((res.len() - 5) as u32).to_be_bytes();
-                                        res[1..5].copy_from_slice(&size);
-                                        yield Ok(res);
-                                    } else {
-                                        let mut res = vec![0x2, 0, 0, 0, 0];
-                                        if let Err(e) = serde_json::to_writer(&mut res, &rpc_item) {
-                                            let e = RpcError::new(RpcErrorCode::Internal, e.to_string());
-                                            yield Ok(encode_error(&e, true));
-                                            break;
-                                        }
-                                        let size = ((res.len() - 5) as u32).to_be_bytes();
-                                        res[1..5].copy_from_slice(&size);
-                                        yield Ok(res);
-                                    }
-                                },
-                                Err(e) => {
-                                    yield Ok(encode_error(&e, binary));
-                                    break;
-                                }
-                            }
+                    let res = StreamExt::catch_unwind(AssertUnwindSafe(res)).map(move |item| {
+                        match item {
+                            Ok(item) => item.rpc_into_stream_item(),
+                            Err(panic) => RpcStreamItem::End(StreamEnd::error(recover_panic(
+                                &config, panic,
+                            ))),
                         }
+                    });
 
-                        // EndStreamResponse, see: https://connect.build/docs/protocol/#error-end-stream
-                        // TODO: Support returning trailers (they would need to bundle in the error type).
-                        if binary {
-                            yield Result::<Vec<u8>, Infallible>::Ok(vec![0x2, 0, 0, 0, 0]);
-                        } else {
-                            yield Result::<Vec<u8>, Infallible>::Ok(vec![0x2, 0, 0, 0, 2, b'{', b'}']);
-                        }
-                    };
-
-                    (
-                        StatusCode::OK,
-                        [(
-                            header::CONTENT_TYPE,
-                            if binary {
-                                "application/connect+proto"
-                            } else {
-                                "application/connect+json"
-                            },
-                        )],
-                        Body::from_stream(res),
-                    )
-                        .into_response()
+                    Ok(Box::pin(res) as RpcStreamReply<TMRes>)
                 })
             }
         }
@@ -1,24 +1,21 @@
-use std::{convert::Infallible, pin::Pin};
+use std::pin::Pin;
 
-use axum::{
-    body::Body,
-    http::{header, Method, Request, StatusCode},
-    response::{IntoResponse, Response},
-};
-use futures::Future;
+use axum::{body::Body, http::Request, response::Response};
+use futures::{Future, FutureExt};
 use prost::Message;
 use serde::{de::DeserializeOwned, Serialize};
 
 use crate::{
     error::RpcIntoError,
     parts::RpcFromRequestParts,
-    prelude::{RpcError, RpcErrorCode},
-    response::RpcIntoResponse,
+    response::{RpcIntoResponse, RpcResponseParts, RpcResult},
+    service::{RpcContext, RpcService, RpcUnaryReply, Unary},
 };
 
-use super::codec::{
-    decode_check_headers, decode_check_query, decode_request_payload,
-    decode_request_payload_from_query, encode_error_response, ReqResInto,
+use super::{
+    call_handler,
+    codec::{decode_message, read_request},
+    ResponseTags,
 };
 
 pub trait RpcHandlerUnary<TMReq, TMRes, TUid, TState>:
@@ -31,101 +28,83 @@ pub trait RpcHandlerUnary<TMReq, TMRes, TUid, TState>:
 
 // This is for Unary.
 // TODO: Check that the header "connect-protocol-version" == "1"
-// TODO: Get "connect-timeout-ms" (number as string) and apply timeout.
 // TODO: Parse request metadata from:
 //      - [0-9a-z]*!"-bin" ASCII value
 //      - [0-9a-z]*-bin" (base64 encoded binary)
 // TODO: Allow response to send back both leading and trailing metadata.
 
-// This is here because writing Rust macros sucks a**. So I uncomment this when I'm trying to modify
-// the below macro.
-// #[allow(unused_parens, non_snake_case, unused_mut)]
-// impl<TMReq, TMRes, TInto, TFnFut, TFn, TState, T1>
-//     RpcHandlerUnary<TMReq, TMRes, (T1, TMReq), TState> for TFn
-// where
-//     TMReq: Message + DeserializeOwned + Default + Send + 'static,
-//     TMRes: Message + Serialize + Send + 'static,
-//     TInto: RpcIntoResponse<TMRes>,
-//     TFnFut: Future<Output = TInto> + Send,
-//     TFn: FnOnce(T1, TMReq) -> TFnFut + Clone + Send + 'static,
-//     TState: Send + Sync + 'static,
-//     T1: RpcFromRequestParts<TMRes, TState> + Send,
-// {
-//     type Future = Pin<Box<dyn Future<Output = Response> + Send>>;
-
-//     fn call(self, req: Request<Body>, state: TState) -> Self::Future {
-//         Box::pin(async move {
-//             let (mut parts, body) = req.into_parts();
-
-//             let ReqResInto { binary } = match decode_check_headers(&mut parts, false) {
-//                 Ok(binary) => binary,
-//                 Err(e) => return e,
-//             };
-
-//             let state = &state;
-
-//             let t1 = match T1::rpc_from_request_parts(&mut parts, state).await {
-//                 Ok(value) => value,
-//                 Err(e) => {
-//                     let e = e.rpc_into_error();
-//                     return encode_error_response(&e, binary, false);
-//                 }
-//             };
-
-//             let req = Request::from_parts(parts, body);
-
-//             let proto_req: TMReq = match decode_request_payload(req, state, binary, false).await {
-//                 Ok(value) => value,
-//                 Err(e) => return e,
-//             };
-
-//             let res = self(t1, proto_req).await.rpc_into_response();
-//             let res = match res {
-//                 Ok(res) => {
-//                     if binary {
-//                         res.encode_to_vec()
-//                     } else {
-//                         match serde_json::to_vec(&res) {
-//                             Ok(res) => res,
-//                             Err(e) => {
-//                                 let e = RpcError::new(
-//                                     RpcErrorCode::Internal,
-//                                     format!("Failed to serialize response: {}", e),
-//                                 );
-//                                 return encode_error_response(&e, binary, false);
-//                             }
-//                         }
-//                     }
-//                 }
-//                 Err(e) => {
-//                     return encode_error_response(&e, binary, false);
-//                 }
-//             };
-
-//             (
-//                 StatusCode::OK,
-//                 [(
-//                     header::CONTENT_TYPE,
-//                     if binary {
-//                         "application/proto"
-//                     } else {
-//                         "application/json"
-//                     },
-//                 )],
-//                 Result::<Vec<u8>, Infallible>::Ok(res),
-//             )
-//                 .into_response()
-//         })
-//     }
-// }
+/// The HTTP transport: Connect unary calls in, `RpcService` in the middle, Connect responses out.
+impl<H, TMReq, TMRes, TUid, TState> RpcHandlerUnary<TMReq, TMRes, TUid, TState> for H
+where
+    H: RpcService<TMReq, TMRes, Unary, TUid, TState, Reply = RpcUnaryReply<TMRes>>,
+    TMReq: Message + DeserializeOwned + Default + Send + 'static,
+    TMRes: Message + Serialize + Send + 'static,
+    TState: Send + Sync + 'static,
+{
+    type Future = Pin<Box<dyn Future<Output = Response> + Send>>;
+
+    fn call(self, req: Request<Body>, state: TState) -> Self::Future {
+        #[cfg(feature = "tracing")]
+        let span = crate::trace::request_span(&req);
+        let tags = ResponseTags::new(&req);
+
+        let fut = async move {
+            let (parts, encoder, payload) = match read_request(req, false).await {
+                Ok(read) => read,
+                Err(res) => return res,
+            };
+
+            let message = payload.and_then(|bytes| decode_message(bytes, encoder.binary));
+
+            #[cfg(feature = "tracing")]
+            let log = crate::redact::RpcMessageLog::for_call(&parts, &encoder.config);
+            #[cfg(feature = "tracing")]
+            if let (Some(log), Ok(message)) = (&log, &message) {
+                log.request(message);
+            }
+
+            let reply = self.call_rpc(RpcContext::from_parts(parts), message, state);
+            match encoder.before_deadline(reply).await.and_then(|res| res) {
+                Ok(RpcUnaryReply::Message(res)) => {
+                    #[cfg(feature = "tracing")]
+                    if let Some(log) = &log {
+                        log.response(&res);
+                    }
+                    encoder.encode_response(Ok(res))
+                }
+                Ok(RpcUnaryReply::WithParts(res, parts)) => {
+                    #[cfg(feature = "tracing")]
+                    if let (Some(log), Ok(res)) = (&log, &res) {
+                        log.response(res);
+                    }
+                    parts.apply(encoder.encode_response(res))
+                }
+                Ok(RpcUnaryReply::Raw(raw)) => {
+                    encoder.record_ok();
+                    raw
+                }
+                Err(e) => encoder.encode_error_response(e),
+            }
+        }
+        .map(move |res| tags.apply(res));
+
+        #[cfg(feature = "tracing")]
+        let fut = tracing::Instrument::instrument(fut, span);
 
+        Box::pin(fut)
+    }
+}
+
+// The impls below are stamped out for every arity and every handler, so they only run the
+// extractors and the handler itself. Everything else lives in the transport above (and `codec`,
+// which is mostly not generic).
 macro_rules! impl_handler {
     (
         [$($ty:ident),*]
     ) => {
         #[allow(unused_parens, non_snake_case, unused_mut)]
         impl<TMReq, TMRes, TInto, TFnFut, TFn, TState, $($ty,)*>
-            RpcHandlerUnary<TMReq, TMRes, ($($ty,)* TMReq), TState> for TFn
+            RpcService<TMReq, TMRes, Unary, ($($ty,)* TMReq), TState> for TFn
         where
             TMReq: Message + DeserializeOwned + Default + Send + 'static,
             TMRes: Message + Serialize + Send + 'static,
@@ -135,88 +114,41 @@ macro_rules! impl_handler {
             TState: Send + Sync + 'static,
             $( $ty: RpcFromRequestParts<TMRes, TState> + Send, )*
         {
-            type Future = Pin<Box<dyn Future<Output = Response> + Send>>;
-
-            fn call(self, req: Request<Body>, state: TState) -> Self::Future {
+            type Reply = RpcUnaryReply<TMRes>;
+
+            fn call_rpc(
+                self,
+                mut ctx: RpcContext,
+                message: RpcResult<TMReq>,
+                state: TState,
+            ) -> Pin<Box<dyn Future<Output = RpcResult<Self::Reply>> + Send>> {
                 Box::pin(async move {
-                    let (mut parts, body) = req.into_parts();
-
-                    let ReqResInto { binary } = if parts.method == Method::GET {
-                        match decode_check_query(&parts) {
-                            Ok(binary) => binary,
-                            Err(e) => return e,
-                        }
-                    } else {
-                        match decode_check_headers(&mut parts, false) {
-                            Ok(binary) => binary,
-                            Err(e) => return e,
-                        }
-                    };
-
                     let state = &state;
 
                     $(
-                        let $ty = match $ty::rpc_from_request_parts(&mut parts, state).await {
-                            Ok(value) => value,
-                            Err(e) => {
-                                let e = e.rpc_into_error();
-                                return encode_error_response(&e, binary, false);
-                            }
-                        };
+                        let $ty = $ty::rpc_from_request_parts(&mut ctx.parts, state)
+                            .await
+                            .map_err(RpcIntoError::rpc_into_error)?;
                     )*
 
+                    let message = message?;
+                    let config = ctx.config();
 
+                    let handler = async move { self($($ty,)* message).await };
+                    let res = call_handler(&config, handler).await?;
 
-                    let proto_req: TMReq = if parts.method == Method::GET {
-                        match decode_request_payload_from_query(&parts, state, binary) {
-                            Ok(value) => value,
-                            Err(e) => return e,
-                        }
-                    } else {
-                        let req = Request::from_parts(parts, body);
-
-                        match decode_request_payload(req, state, binary, false).await {
-                            Ok(value) => value,
-                            Err(e) => return e,
-                        }
-                    };
-
-                    let res = self($($ty,)* proto_req).await.rpc_into_response();
-                    let res = match res {
-                        Ok(res) => {
-                            if binary {
-                                res.encode_to_vec()
-                            } else {
-                                match serde_json::to_vec(&res) {
-                                    Ok(res) => res,
-                                    Err(e) => {
-                                        let e = RpcError::new(
-                                            RpcErrorCode::Internal,
-                                            format!("Failed to serialize response: {}", e),
-                                        );
-                                        return encode_error_response(&e, binary, false);
-                                    }
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            return encode_error_response(&e, binary, false);
-                        }
+                    let res = match res.rpc_into_raw_response() {
+                        Ok(raw) => return Ok(RpcUnaryReply::Raw(raw)),
+                        Err(res) => res,
                     };
 
-                    (
-                        StatusCode::OK,
-                        [(
-                            header::CONTENT_TYPE,
-                            if binary {
-                                "application/proto"
-                            } else {
-                                "application/json"
-                            },
-                        )],
-                        Result::<Vec<u8>, Infallible>::Ok(res),
-                    )
-                        .into_response()
+                    let mut parts = RpcResponseParts::default();
+                    let res = res.rpc_into_response_with_parts(&mut parts);
+                    if parts.is_empty() {
+                        res.map(RpcUnaryReply::Message)
+                    } else {
+                        Ok(RpcUnaryReply::WithParts(res, parts))
+                    }
                 })
             }
         }
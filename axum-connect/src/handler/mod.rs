@@ -1,7 +1,187 @@
+use std::{any::Any, fmt, panic::AssertUnwindSafe};
+
+use futures::{Future, FutureExt};
+
+use axum::{
+    body::Body,
+    http::{header, HeaderValue, Request, StatusCode, Version},
+    response::Response,
+};
+
+use crate::{
+    config::RpcConfig,
+    descriptor::{MethodDescriptor, MethodKind},
+    error::{RpcError, RpcErrorCode},
+    parts::REQUEST_ID_HEADER,
+};
+
 pub mod handler_stream;
 pub mod handler_unary;
 
-mod codec;
+pub(crate) mod codec;
+
+use codec::ResponseEncoder;
 
 pub use handler_stream::*;
 pub use handler_unary::*;
+
+/// Turns a panic caught while running a handler into an `Internal` error. If the router didn't
+/// opt into panic recovery the panic simply keeps unwinding, same as it always did.
+///
+/// The panic message (which can be anything, an `unwrap`ped error included) is never sent to the
+/// client, it's the error's source (see `RpcError::with_source`) for error hooks to log.
+pub(crate) fn recover_panic(config: &RpcConfig, panic: Box<dyn Any + Send>) -> RpcError {
+    if !config.catch_panics {
+        std::panic::resume_unwind(panic);
+    }
+
+    let message = if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Box<dyn Any>".to_string()
+    };
+
+    RpcError::new(RpcErrorCode::Internal, "Handler panicked".to_string())
+        .with_source(HandlerPanic(message))
+}
+
+/// The message of a panic caught by `recover_panic`.
+#[derive(Debug)]
+struct HandlerPanic(String);
+
+impl fmt::Display for HandlerPanic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Handler panicked: {}", self.0)
+    }
+}
+
+impl std::error::Error for HandlerPanic {}
+
+/// Runs a handler future, catching a panic (as an error) if the router opted into it.
+pub(crate) async fn call_handler<F>(config: &RpcConfig, handler: F) -> Result<F::Output, RpcError>
+where
+    F: Future,
+{
+    AssertUnwindSafe(handler)
+        .catch_unwind()
+        .await
+        .map_err(|panic| recover_panic(config, panic))
+}
+
+/// Bits of the request that are copied onto the response, captured before the request is consumed.
+///
+/// - The method descriptor goes into the response extensions, so middleware wrapping the whole
+///   router (or a router this one was merged or nested into) can tell which RPC it was.
+/// - Deprecated methods (and ones with a sunset date) get `Deprecation`/`Sunset` headers.
+/// - The `x-request-id` header is echoed back (unless the response already has one), errors
+///   included, so a failed call can be matched with the server's logs.
+pub(crate) struct ResponseTags {
+    method: Option<MethodDescriptor>,
+    request_id: Option<HeaderValue>,
+}
+
+impl ResponseTags {
+    pub fn new(req: &Request<Body>) -> Self {
+        Self {
+            method: req.extensions().get::<MethodDescriptor>().copied(),
+            request_id: req.headers().get(REQUEST_ID_HEADER).cloned(),
+        }
+    }
+
+    pub fn apply(self, mut res: Response) -> Response {
+        if let Some(method) = self.method {
+            if method.deprecated {
+                res.headers_mut()
+                    .insert("deprecation", HeaderValue::from_static("true"));
+                #[cfg(feature = "metrics")]
+                crate::metrics::record_deprecated_call(&method);
+            }
+            if let Some(sunset) = method.sunset {
+                if let Ok(sunset) = HeaderValue::from_str(sunset) {
+                    res.headers_mut().insert("sunset", sunset);
+                }
+            }
+            res.extensions_mut().insert(method);
+        }
+        if let Some(request_id) = self.request_id {
+            res.headers_mut()
+                .entry(REQUEST_ID_HEADER)
+                .or_insert(request_id);
+        }
+        res
+    }
+}
+
+/// Answers a call to a client or bidi streaming method (which axum-connect doesn't support yet)
+/// with a Connect error, rather than letting the client hang on a framing it doesn't expect.
+/// Bidi streaming needs a full-duplex transport, so calls made over HTTP/1.x are told that
+/// specifically.
+pub async fn unsupported_streaming(req: Request<Body>) -> Response {
+    let tags = ResponseTags::new(&req);
+    let (mut parts, _) = req.into_parts();
+    let kind = parts
+        .extensions
+        .get::<MethodDescriptor>()
+        .map(|method| method.kind);
+
+    let encoder = match ResponseEncoder::negotiate(&mut parts, true) {
+        Ok(encoder) => encoder,
+        Err(res) => return tags.apply(res),
+    };
+
+    let e = match kind {
+        Some(MethodKind::BidiStreaming) if parts.version < Version::HTTP_2 => {
+            RpcError::unimplemented(format!(
+                "Bidirectional streaming requires HTTP/2, but this call was made over {:?}",
+                parts.version
+            ))
+        }
+        Some(MethodKind::BidiStreaming) => {
+            RpcError::unimplemented("Bidirectional streaming is not supported by this server")
+        }
+        _ => RpcError::unimplemented("Client streaming is not supported by this server"),
+    };
+
+    tags.apply(encoder.encode_error_response(e))
+}
+
+/// Answers a request to an RPC route made with an HTTP method it isn't served on: a 405 with an
+/// `Allow` header of `allow_methods` (comma separated), carrying an `Unimplemented` Connect error.
+/// Generated routes use this as their method fallback.
+pub async fn method_not_allowed(allow_methods: &'static str, req: Request<Body>) -> Response {
+    let (parts, _) = req.into_parts();
+    // The codec can't be negotiated for a method Connect doesn't use, so it's JSON.
+    let encoder = ResponseEncoder::new(&parts, false, false);
+
+    let mut res = encoder.encode_error_response(RpcError::unimplemented(format!(
+        "{} doesn't accept {} requests, only {}",
+        parts.uri.path(),
+        parts.method,
+        allow_methods
+    )));
+    *res.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+    res.headers_mut()
+        .insert(header::ALLOW, HeaderValue::from_static(allow_methods));
+    res
+}
+
+/// Answers a call to a method the server doesn't serve (it's unknown, or just not registered)
+/// with an `Unimplemented` Connect error, see `router::unimplemented_service`. Whether the call
+/// was meant to be streaming is guessed from its content type.
+pub async fn unimplemented_method(req: Request<Body>) -> Response {
+    let (mut parts, _) = req.into_parts();
+    let for_streaming =
+        codec::guess_codec(&parts.headers).is_some_and(|(_, for_streaming)| for_streaming);
+
+    let encoder = match ResponseEncoder::negotiate(&mut parts, for_streaming) {
+        Ok(encoder) => encoder,
+        Err(res) => return res,
+    };
+
+    encoder.encode_error_response(RpcError::unimplemented(format!(
+        "{} is not implemented",
+        parts.uri.path()
+    )))
+}
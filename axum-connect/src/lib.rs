@@ -1,8 +1,84 @@
+// The prost version (and the pbjson built on it) is picked with a feature, see Cargo.toml. The
+// crate refers to them by their usual names either way.
+#[cfg(all(feature = "prost-0-12", feature = "prost-0-13"))]
+compile_error!(
+    "the `prost-0-12` and `prost-0-13` features are mutually exclusive, disable default features \
+    to use `prost-0-13`"
+);
+#[cfg(not(any(feature = "prost-0-12", feature = "prost-0-13")))]
+compile_error!("enable one of the `prost-0-12` or `prost-0-13` features");
+#[cfg(all(
+    feature = "prost-0-13",
+    any(feature = "dynamic", feature = "field-mask", feature = "json-schema")
+))]
+compile_error!("`dynamic`, `field-mask`, `grpc-proxy` and `json-schema` require `prost-0-12`");
+#[cfg(all(feature = "tonic", not(feature = "prost-0-13")))]
+compile_error!("`tonic` requires `prost-0-13`, tonic 0.12 is built on prost 0.13");
+#[cfg(all(feature = "prost-0-13", not(feature = "prost-0-12")))]
+extern crate pbjson_0_7 as pbjson;
+#[cfg(all(feature = "prost-0-13", not(feature = "prost-0-12")))]
+extern crate pbjson_types_0_7 as pbjson_types;
+#[cfg(all(feature = "prost-0-13", not(feature = "prost-0-12")))]
+extern crate prost_0_13 as prost;
+
+/// Logs a protocol decision (codec, envelopes, error mapping) at `TRACE` under the
+/// `axum_connect::protocol` target, tagged with the method path. Only with the `protocol-trace`
+/// feature, otherwise it compiles to nothing.
+macro_rules! protocol_trace {
+    ($method:expr, $($arg:tt)+) => {
+        #[cfg(feature = "protocol-trace")]
+        tracing::trace!(
+            target: "axum_connect::protocol",
+            rpc.path = $method.map(|method| method.path).unwrap_or_default(),
+            $($arg)+
+        );
+    };
+}
+
+pub mod access_log;
+pub mod auth;
+#[cfg(feature = "tower-http")]
+pub mod classify;
+pub mod concurrency_limit;
+pub mod config;
+pub mod cors;
+pub mod descriptor;
+#[cfg(feature = "dynamic")]
+pub mod dynamic;
 pub mod error;
+#[cfg(feature = "field-mask")]
+pub mod field_mask;
+#[cfg(feature = "grpc-proxy")]
+pub mod grpc_proxy;
 pub mod handler;
+pub mod load_shed;
+#[cfg(feature = "metrics")]
+pub(crate) mod metrics;
+#[cfg(feature = "nats")]
+pub mod nats;
 pub mod parts;
+pub mod rate_limit;
+pub mod raw;
+pub mod redact;
+pub mod rejection;
 pub mod response;
 pub mod router;
+pub mod rpcz;
+#[cfg(feature = "json-schema")]
+pub mod schema;
+pub mod service;
+#[cfg(feature = "shutdown")]
+pub mod shutdown;
+pub mod stream;
+#[cfg(feature = "text-format")]
+pub mod text_format;
+#[cfg(feature = "tonic")]
+pub mod tonic_interop;
+#[cfg(feature = "tracing")]
+pub(crate) mod trace;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+pub mod wkt;
 
 // Re-export several crates
 pub use futures;
@@ -12,8 +88,15 @@ pub use prost;
 pub use serde;
 
 pub mod prelude {
+    pub use crate::auth::{
+        RpcAuthenticated, RpcBearer, RpcBearerValidator, RpcScoped, RpcScopesLayer,
+    };
+    pub use crate::config::RpcConfig;
+    pub use crate::descriptor::{MethodDescriptor, MethodKind};
     pub use crate::error::*;
     pub use crate::parts::*;
     pub use crate::response::*;
     pub use crate::router::RpcRouterExt;
+    pub use crate::stream::{RpcStreamExt, StreamEnd, StreamPosition};
+    pub use crate::wkt::{pack_any, unpack_any, RpcDurationExt, RpcJsonExt, RpcTimestampExt};
 }
@@ -0,0 +1,123 @@
+//! Load shedding that answers in Connect. While a signal of the application's (queue depth,
+//! memory use, anything it can check quickly) says the server is overloaded, calls are rejected
+//! with an `Unavailable` Connect error and a `google.rpc.RetryInfo` detail, encoded for unary and
+//! streaming calls alike:
+//!
+//! ```ignore
+//! let queued = Arc::new(AtomicUsize::new(0));
+//! let app = Router::new()
+//!     .rpc(HelloWorldService::say_hello(say_hello))
+//!     .layer(RpcLoadShedLayer::above(queued.clone(), 1_000));
+//! ```
+//!
+//! The signal is checked once per call, before the call is handled. Preflights are never shed.
+//! Shed calls go through the error hook and redaction of the `RpcConfig`, if the layer is applied
+//! before `rpc_config`.
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use axum::{
+    http::{Method, Request},
+    response::Response,
+};
+use futures::future::{self, Either};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{
+    error::{RpcError, RpcErrorCode, RpcErrorDetail},
+    handler::codec::encode_rejection,
+};
+
+#[derive(Clone)]
+pub struct RpcLoadShedLayer {
+    overloaded: Arc<dyn Fn() -> bool + Send + Sync>,
+    retry_after: Duration,
+}
+
+impl fmt::Debug for RpcLoadShedLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RpcLoadShedLayer")
+            .field("retry_after", &self.retry_after)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RpcLoadShedLayer {
+    /// Sheds calls whenever `overloaded` returns `true`. It's called for every call, keep it cheap.
+    pub fn new<F>(overloaded: F) -> Self
+    where
+        F: Fn() -> bool + Send + Sync + 'static,
+    {
+        Self {
+            overloaded: Arc::new(overloaded),
+            retry_after: Duration::from_secs(1),
+        }
+    }
+
+    /// Sheds calls while `depth` (ex. the length of a work queue the application keeps up to date)
+    /// is above `max`.
+    pub fn above(depth: Arc<AtomicUsize>, max: usize) -> Self {
+        Self::new(move || depth.load(Ordering::Relaxed) > max)
+    }
+
+    /// How long shed clients are told to wait before retrying. Defaults to a second.
+    pub fn retry_after(mut self, retry_after: Duration) -> Self {
+        self.retry_after = retry_after;
+        self
+    }
+}
+
+impl<S> Layer<S> for RpcLoadShedLayer {
+    type Service = RpcLoadShed<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RpcLoadShed {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RpcLoadShed<S> {
+    inner: S,
+    layer: RpcLoadShedLayer,
+}
+
+impl<S, B> Service<Request<B>> for RpcLoadShed<S>
+where
+    S: Service<Request<B>, Response = Response>,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Either<S::Future, future::Ready<Result<Response, S::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        if req.method() == Method::OPTIONS || !(self.layer.overloaded)() {
+            return Either::Left(self.inner.call(req));
+        }
+
+        Either::Right(future::ready(Ok(shed(&req, self.layer.retry_after))))
+    }
+}
+
+/// An `Unavailable` error, encoded for whatever kind of call `req` is.
+fn shed<B>(req: &Request<B>, retry_after: Duration) -> Response {
+    let mut e = RpcError::new(RpcErrorCode::Unavailable, "Server overloaded".to_string());
+    e.details.push(RpcErrorDetail::retry_info(retry_after));
+
+    encode_rejection(req, e, None)
+}
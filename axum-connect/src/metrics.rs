@@ -0,0 +1,75 @@
+//! Per-method RPC metrics, enabled by the `metrics` feature.
+//!
+//! Recorded through the [`metrics`](https://docs.rs/metrics) facade, so they end up in whatever
+//! exporter (Prometheus, StatsD, ...) the application installed. Every metric is labeled with
+//! `service` and `method`, and the ones recorded when a call finishes also with `code` (`ok` or a
+//! Connect error code):
+//!
+//! - `rpc_server_requests_total` (counter, with `code`)
+//! - `rpc_server_duration_seconds` (histogram, with `code`)
+//! - `rpc_server_request_size_bytes` (histogram)
+//! - `rpc_server_response_size_bytes` (histogram, once per message for streams)
+//! - `rpc_server_deprecated_calls_total` (counter, only for methods marked `deprecated`)
+
+use std::time::Instant;
+
+use crate::descriptor::MethodDescriptor;
+
+fn labels(method: Option<&MethodDescriptor>) -> (&'static str, &'static str) {
+    method.map_or(("", ""), |m| (m.service, m.method))
+}
+
+pub(crate) fn record_finished(
+    method: Option<&MethodDescriptor>,
+    code: &'static str,
+    started: Instant,
+) {
+    let (service, method) = labels(method);
+
+    ::metrics::counter!(
+        "rpc_server_requests_total",
+        "service" => service,
+        "method" => method,
+        "code" => code,
+    )
+    .increment(1);
+
+    ::metrics::histogram!(
+        "rpc_server_duration_seconds",
+        "service" => service,
+        "method" => method,
+        "code" => code,
+    )
+    .record(started.elapsed().as_secs_f64());
+}
+
+pub(crate) fn record_request_size(method: Option<&MethodDescriptor>, size: usize) {
+    let (service, method) = labels(method);
+
+    ::metrics::histogram!(
+        "rpc_server_request_size_bytes",
+        "service" => service,
+        "method" => method,
+    )
+    .record(size as f64);
+}
+
+pub(crate) fn record_response_size(method: Option<&MethodDescriptor>, size: usize) {
+    let (service, method) = labels(method);
+
+    ::metrics::histogram!(
+        "rpc_server_response_size_bytes",
+        "service" => service,
+        "method" => method,
+    )
+    .record(size as f64);
+}
+
+pub(crate) fn record_deprecated_call(method: &MethodDescriptor) {
+    ::metrics::counter!(
+        "rpc_server_deprecated_calls_total",
+        "service" => method.service,
+        "method" => method.method,
+    )
+    .increment(1);
+}
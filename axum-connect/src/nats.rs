@@ -0,0 +1,259 @@
+//! Serve RPC handlers over NATS request-reply, enabled by the `nats` feature.
+//!
+//! The same handlers (extractors, state, errors and all) registered on an axum router can be
+//! served to internal consumers over NATS:
+//!
+//! - The subject is the method path without the leading slash (ex.
+//!   `hello.HelloWorldService/SayHello`), behind an optional prefix.
+//! - The request payload is a single enveloped message (Connect streaming framing), binary proto
+//!   unless the request carries a `Content-Type: application/connect+json` header.
+//! - The reply is a sequence of envelopes ending with an EndStreamResponse, exactly like the body
+//!   of a Connect server-streaming response. Unary replies are a single NATS message containing
+//!   both envelopes, so plain `request` calls work. Streams publish one NATS message per
+//!   envelope to the reply subject.
+//!
+//! NATS headers are copied into the request headers, so header-reading extractors keep working.
+//! Extractors that need an actual HTTP connection (ex. `ConnectInfo`) will fail.
+
+use std::sync::Arc;
+
+use async_nats::{Client, Message as NatsMessage, SubscribeError};
+use axum::{body::Bytes, http};
+use futures::{
+    future,
+    stream::{self, BoxStream, SelectAll},
+    FutureExt, StreamExt,
+};
+use prost::Message;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    config::RpcConfig,
+    descriptor::{MethodDescriptor, MethodKind},
+    error::RpcError,
+    handler::codec::{decode_envelope, decode_message, ResponseEncoder},
+    service::{RpcContext, RpcService, RpcStreamReply, RpcUnaryReply, ServerStreaming, Unary},
+    stream::{RpcStreamItem, StreamEnd},
+};
+
+type NatsMethod<S> =
+    Arc<dyn Fn(RpcContext, Bytes, bool, S) -> BoxStream<'static, Bytes> + Send + Sync>;
+
+pub struct NatsRpcServer<S> {
+    subject_prefix: String,
+    queue_group: Option<String>,
+    config: RpcConfig,
+    methods: Vec<(MethodDescriptor, NatsMethod<S>)>,
+}
+
+impl<S> Default for NatsRpcServer<S> {
+    fn default() -> Self {
+        Self {
+            subject_prefix: String::new(),
+            queue_group: None,
+            config: RpcConfig::default(),
+            methods: Vec::new(),
+        }
+    }
+}
+
+impl<S> NatsRpcServer<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Prepended (as is) to every method subject, ex. `"rpc."`.
+    pub fn subject_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.subject_prefix = prefix.into();
+        self
+    }
+
+    /// Subscribe as part of a queue group, so several instances share the load.
+    pub fn queue_group(mut self, group: impl Into<String>) -> Self {
+        self.queue_group = Some(group.into());
+        self
+    }
+
+    /// Same as `RpcRouterExt::rpc_config`, for calls arriving over NATS.
+    pub fn rpc_config(mut self, config: RpcConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Serve a unary handler for `method` (one of the generated descriptor consts).
+    pub fn unary<TMReq, TMRes, T, H>(mut self, method: MethodDescriptor, handler: H) -> Self
+    where
+        H: RpcService<TMReq, TMRes, Unary, T, S, Reply = RpcUnaryReply<TMRes>>,
+        TMReq: Message + DeserializeOwned + Default + Send + 'static,
+        TMRes: Message + Serialize + Send + 'static,
+    {
+        let call = move |ctx: RpcContext, payload: Bytes, binary: bool, state: S| {
+            let handler = handler.clone();
+            let encoder = ResponseEncoder::new(&ctx.parts, binary, true);
+            let message = decode_payload(&encoder, payload);
+
+            async move {
+                let reply = handler.call_rpc(ctx, message, state);
+                let res = match encoder.before_deadline(reply).await.and_then(|res| res) {
+                    Ok(RpcUnaryReply::Message(res)) => Ok(res),
+                    Ok(RpcUnaryReply::WithParts(res, _)) => res,
+                    Ok(RpcUnaryReply::Raw(_)) => Err(RpcError::unimplemented(
+                        "RpcRawResponse is only supported over HTTP",
+                    )),
+                    Err(e) => Err(e),
+                };
+
+                encoder.encode_frames(stream::once(future::ready(RpcStreamItem::from(res))))
+            }
+            .flatten_stream()
+            .boxed()
+        };
+
+        self.methods.push((method, Arc::new(call)));
+        self
+    }
+
+    /// Serve a server-streaming handler for `method` (one of the generated descriptor consts).
+    pub fn server_streaming<TMReq, TMRes, T, H>(
+        mut self,
+        method: MethodDescriptor,
+        handler: H,
+    ) -> Self
+    where
+        H: RpcService<TMReq, TMRes, ServerStreaming, T, S, Reply = RpcStreamReply<TMRes>>,
+        TMReq: Message + DeserializeOwned + Default + Send + 'static,
+        TMRes: Message + Serialize + Send + 'static,
+    {
+        let call = move |ctx: RpcContext, payload: Bytes, binary: bool, state: S| {
+            let handler = handler.clone();
+            let encoder = ResponseEncoder::new(&ctx.parts, binary, true);
+            let message = decode_payload(&encoder, payload);
+
+            async move {
+                let reply = handler.call_rpc(ctx, message, state);
+                match encoder.before_deadline(reply).await.and_then(|res| res) {
+                    Ok(stream) => encoder.encode_frames(stream).boxed(),
+                    Err(e) => encoder
+                        .encode_frames(stream::once(future::ready(RpcStreamItem::<TMRes>::End(
+                            StreamEnd::error(e),
+                        ))))
+                        .boxed(),
+                }
+            }
+            .flatten_stream()
+            .boxed()
+        };
+
+        self.methods.push((method, Arc::new(call)));
+        self
+    }
+
+    /// Subscribe to every registered method and answer requests until the subscriptions end
+    /// (ex. the client is drained or closed). Each request is handled on its own task.
+    pub async fn serve(self, client: Client, state: S) -> Result<(), SubscribeError> {
+        let mut subscriptions = SelectAll::new();
+        for (index, (method, _)) in self.methods.iter().enumerate() {
+            let subject = format!(
+                "{}{}",
+                self.subject_prefix,
+                method.path.trim_start_matches('/')
+            );
+            let subscriber = match &self.queue_group {
+                Some(group) => client.queue_subscribe(subject, group.clone()).await?,
+                None => client.subscribe(subject).await?,
+            };
+            subscriptions.push(subscriber.map(move |message| (index, message)).boxed());
+        }
+
+        while let Some((index, message)) = subscriptions.next().await {
+            let (method, call) = self.methods[index].clone();
+            let ctx = request_context(method, &self.config, &message);
+
+            tokio::spawn(reply(client.clone(), message, ctx, call, state.clone()));
+        }
+
+        Ok(())
+    }
+}
+
+fn decode_payload<M>(encoder: &ResponseEncoder, payload: Bytes) -> Result<M, RpcError>
+where
+    M: Message + DeserializeOwned + Default,
+{
+    let payload = decode_envelope(payload)?;
+    protocol_trace!(
+        encoder.method.as_ref(),
+        size = payload.len(),
+        binary = encoder.binary,
+        "read request envelope"
+    );
+    encoder.record_request_size(payload.len());
+    decode_message(payload, encoder.binary)
+}
+
+fn request_context(
+    method: MethodDescriptor,
+    config: &RpcConfig,
+    message: &NatsMessage,
+) -> RpcContext {
+    let mut ctx = RpcContext::new(method).with_config(config.clone());
+
+    if let Some(headers) = &message.headers {
+        for (name, values) in headers.iter() {
+            let Ok(name) = http::HeaderName::from_bytes(name.to_string().as_bytes()) else {
+                continue;
+            };
+            for value in values {
+                if let Ok(value) = http::HeaderValue::from_str(value.as_str()) {
+                    ctx.parts.headers.append(name.clone(), value);
+                }
+            }
+        }
+    }
+
+    ctx
+}
+
+async fn reply<S>(
+    client: Client,
+    message: NatsMessage,
+    ctx: RpcContext,
+    call: NatsMethod<S>,
+    state: S,
+) {
+    // Nobody to answer, ex. a plain publish to the method subject.
+    let Some(reply_to) = message.reply else {
+        return;
+    };
+
+    let binary = !ctx
+        .parts
+        .headers
+        .get(http::header::CONTENT_TYPE)
+        .is_some_and(|content_type| content_type == "application/connect+json");
+    let kind = ctx.method().map(|method| method.kind);
+
+    let mut frames = call(ctx, message.payload, binary, state);
+
+    // Errors publishing a reply only mean the requester went away; there is no one to tell.
+    if kind == Some(MethodKind::Unary) {
+        let mut payload = Vec::new();
+        while let Some(frame) = frames.next().await {
+            payload.extend_from_slice(&frame);
+        }
+        let _ = client.publish(reply_to, payload.into()).await;
+    } else {
+        while let Some(frame) = frames.next().await {
+            if client
+                .publish(reply_to.clone(), frame.into())
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+    }
+}
@@ -1,17 +1,28 @@
 use async_trait::async_trait;
 use axum::{
     extract::{
-        connect_info::MockConnectInfo, ConnectInfo, FromRef, FromRequestParts, Query, State,
+        connect_info::MockConnectInfo, ConnectInfo, FromRef, FromRequestParts, MatchedPath,
+        OriginalUri, Query, State,
     },
     http::{self},
     Extension,
 };
 #[cfg(feature = "axum-extra")]
-use axum_extra::extract::Host;
+use axum_extra::{
+    extract::{CookieJar, Host},
+    headers::Header,
+    TypedHeader,
+};
 use prost::Message;
 use serde::de::DeserializeOwned;
 
-use crate::error::{RpcError, RpcErrorCode, RpcIntoError};
+use crate::{
+    config::RpcConfig,
+    error::{RpcError, RpcErrorCode, RpcIntoError},
+};
+
+/// The header `RpcRequestId` reads, same as tower-http's request-id layers.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
 
 #[async_trait]
 pub trait RpcFromRequestParts<T, S>: Sized
@@ -49,6 +60,67 @@ where
     }
 }
 
+/// Missing and malformed headers are both rejected as `InvalidArgument`.
+#[cfg(feature = "axum-extra")]
+#[async_trait]
+impl<M, S, T> RpcFromRequestParts<M, S> for TypedHeader<T>
+where
+    M: Message,
+    S: Send + Sync,
+    T: Header + Send,
+{
+    type Rejection = RpcError;
+
+    async fn rpc_from_request_parts(
+        parts: &mut http::request::Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        TypedHeader::from_request_parts(parts, state)
+            .await
+            .map_err(|e| (RpcErrorCode::InvalidArgument, e.to_string()).rpc_into_error())
+    }
+}
+
+#[cfg(feature = "axum-extra")]
+#[async_trait]
+impl<M, S> RpcFromRequestParts<M, S> for CookieJar
+where
+    M: Message,
+    S: Send + Sync,
+{
+    type Rejection = RpcError;
+
+    async fn rpc_from_request_parts(
+        parts: &mut http::request::Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        CookieJar::from_request_parts(parts, state)
+            .await
+            .map_err(|e| match e {})
+    }
+}
+
+/// The key is taken from the state, same as in axum. Cookies that fail to decrypt are left out.
+#[cfg(feature = "cookie-private")]
+#[async_trait]
+impl<M, S, K> RpcFromRequestParts<M, S> for axum_extra::extract::PrivateCookieJar<K>
+where
+    M: Message,
+    S: Send + Sync,
+    K: FromRef<S> + Into<axum_extra::extract::cookie::Key>,
+{
+    type Rejection = RpcError;
+
+    async fn rpc_from_request_parts(
+        parts: &mut http::request::Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        axum_extra::extract::PrivateCookieJar::from_request_parts(parts, state)
+            .await
+            .map_err(|e| match e {})
+    }
+}
+
 #[async_trait]
 impl<M, S, T> RpcFromRequestParts<M, S> for Query<T>
 where
@@ -91,6 +163,66 @@ where
     }
 }
 
+/// A missing extension is a server misconfiguration (ex. the layer inserting it wasn't added), so
+/// it's rejected as `Internal`, same as axum does.
+#[async_trait]
+impl<M, S, T> RpcFromRequestParts<M, S> for Extension<T>
+where
+    M: Message,
+    S: Send + Sync,
+    T: Clone + Send + Sync + 'static,
+{
+    type Rejection = RpcError;
+
+    async fn rpc_from_request_parts(
+        parts: &mut http::request::Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        Extension::from_request_parts(parts, state)
+            .await
+            .map_err(|e| (RpcErrorCode::Internal, e.to_string()).rpc_into_error())
+    }
+}
+
+/// The route that matched, ex. `/hello.HelloWorldService/SayHello`, including the prefix of a
+/// nested router.
+#[async_trait]
+impl<M, S> RpcFromRequestParts<M, S> for MatchedPath
+where
+    M: Message,
+    S: Send + Sync,
+{
+    type Rejection = RpcError;
+
+    async fn rpc_from_request_parts(
+        parts: &mut http::request::Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        MatchedPath::from_request_parts(parts, state)
+            .await
+            .map_err(|e| (RpcErrorCode::Internal, e.to_string()).rpc_into_error())
+    }
+}
+
+/// The URI before any nesting stripped its prefix.
+#[async_trait]
+impl<M, S> RpcFromRequestParts<M, S> for OriginalUri
+where
+    M: Message,
+    S: Send + Sync,
+{
+    type Rejection = RpcError;
+
+    async fn rpc_from_request_parts(
+        parts: &mut http::request::Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        OriginalUri::from_request_parts(parts, state)
+            .await
+            .map_err(|e| match e {})
+    }
+}
+
 #[async_trait]
 impl<M, OuterState, InnerState> RpcFromRequestParts<M, OuterState> for State<InnerState>
 where
@@ -108,3 +240,94 @@ where
         Ok(Self(inner_state))
     }
 }
+
+/// A copy of the whole request head (method, URI, headers, extensions), for when there is no
+/// dedicated extractor for what a handler needs.
+#[async_trait]
+impl<M, S> RpcFromRequestParts<M, S> for http::request::Parts
+where
+    M: Message,
+    S: Send + Sync,
+{
+    type Rejection = RpcError;
+
+    async fn rpc_from_request_parts(
+        parts: &mut http::request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(parts.clone())
+    }
+}
+
+/// A copy of all the request headers.
+#[async_trait]
+impl<M, S> RpcFromRequestParts<M, S> for http::HeaderMap
+where
+    M: Message,
+    S: Send + Sync,
+{
+    type Rejection = RpcError;
+
+    async fn rpc_from_request_parts(
+        parts: &mut http::request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(parts.headers.clone())
+    }
+}
+
+/// The `x-request-id` of the call, if it has one. Set it with tower-http's `SetRequestIdLayer` (or
+/// let clients send it). Either way it's echoed back on the response, errors included.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RpcRequestId(pub Option<String>);
+
+#[async_trait]
+impl<M, S> RpcFromRequestParts<M, S> for RpcRequestId
+where
+    M: Message,
+    S: Send + Sync,
+{
+    type Rejection = RpcError;
+
+    async fn rpc_from_request_parts(
+        parts: &mut http::request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Self(
+            parts
+                .headers
+                .get(REQUEST_ID_HEADER)
+                .and_then(|id| id.to_str().ok())
+                .map(|id| id.to_string()),
+        ))
+    }
+}
+
+/// The request headers allow-listed with `RpcConfig::propagate_headers`, ready to be copied onto
+/// the outbound calls a handler makes. Empty if nothing is allow-listed.
+#[derive(Clone, Debug, Default)]
+pub struct RpcPropagatedHeaders(pub http::HeaderMap);
+
+#[async_trait]
+impl<M, S> RpcFromRequestParts<M, S> for RpcPropagatedHeaders
+where
+    M: Message,
+    S: Send + Sync,
+{
+    type Rejection = RpcError;
+
+    async fn rpc_from_request_parts(
+        parts: &mut http::request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let config = RpcConfig::from_parts(parts);
+        let mut headers = http::HeaderMap::new();
+        for name in config.propagate_headers.iter() {
+            for value in parts.headers.get_all(name) {
+                headers.append(name.clone(), value.clone());
+            }
+        }
+
+        Ok(Self(headers))
+    }
+}
@@ -0,0 +1,298 @@
+//! Rate limiting that answers in Connect. A plain HTTP 429 (ex. from `tower::limit` or a proxy)
+//! is an opaque failure to Connect clients; this rejects with a `ResourceExhausted` Connect error
+//! instead, with a `google.rpc.RetryInfo` detail saying when to try again.
+//!
+//! ```ignore
+//! let app = Router::new()
+//!     .rpc(HelloWorldService::say_hello(say_hello))
+//!     .layer(RpcRateLimitLayer::new(100, Duration::from_secs(60)).key_by_header("x-api-key"));
+//! ```
+//!
+//! Each key gets its own token bucket, holding up to `burst` tokens (`rate` by default) and
+//! refilling at `rate` tokens per `per`. Every call takes a token. Preflights are never limited.
+//!
+//! Rejections go through the error hook and redaction of the `RpcConfig`, if the layer is applied
+//! before `rpc_config` (so the config is in the request extensions by the time it runs).
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::ConnectInfo,
+    http::{HeaderName, Method, Request},
+    response::Response,
+};
+use futures::future::{self, Either};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{
+    error::{RpcError, RpcErrorCode, RpcErrorDetail},
+    handler::codec::encode_rejection,
+};
+
+/// Past this many buckets, full ones (which are no different from a missing one) are dropped.
+/// After that, only once the number of buckets doubled again, so pruning stays amortized O(1) per
+/// call even when (ex. with a client making up keys) few buckets are full.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// What requests are counted against. Requests without a key share one bucket.
+#[derive(Clone, Debug)]
+enum RateLimitKey {
+    Global,
+    Header(HeaderName),
+    Peer,
+}
+
+#[derive(Clone, Debug)]
+pub struct RpcRateLimitLayer {
+    key: RateLimitKey,
+    limiter: Arc<Limiter>,
+}
+
+impl RpcRateLimitLayer {
+    /// Allows `rate` calls per `per`, across all clients until a key is set.
+    ///
+    /// Panics if `rate` or `per` is zero.
+    pub fn new(rate: u32, per: Duration) -> Self {
+        assert!(rate > 0 && !per.is_zero(), "rate limit must be positive");
+
+        Self {
+            key: RateLimitKey::Global,
+            limiter: Arc::new(Limiter {
+                burst: rate as f64,
+                per_second: rate as f64 / per.as_secs_f64(),
+                buckets: Default::default(),
+            }),
+        }
+    }
+
+    /// How many calls may be made back to back after a quiet period. Defaults to `rate`.
+    pub fn burst(mut self, burst: u32) -> Self {
+        Arc::make_mut(&mut self.limiter).burst = burst.max(1) as f64;
+        self
+    }
+
+    /// Limits each value of the `name` request header (ex. an API key) separately.
+    ///
+    /// Panics if `name` isn't a valid header name.
+    pub fn key_by_header(mut self, name: &str) -> Self {
+        self.key = RateLimitKey::Header(
+            HeaderName::from_bytes(name.to_lowercase().as_bytes()).expect("valid header name"),
+        );
+        self
+    }
+
+    /// Limits each peer IP address separately. Requires serving the app with
+    /// `into_make_service_with_connect_info::<SocketAddr>()`.
+    pub fn key_by_peer(mut self) -> Self {
+        self.key = RateLimitKey::Peer;
+        self
+    }
+
+    fn key<B>(&self, req: &Request<B>) -> String {
+        match &self.key {
+            RateLimitKey::Global => String::new(),
+            RateLimitKey::Header(name) => req
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default()
+                .to_string(),
+            RateLimitKey::Peer => req
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip().to_string())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl<S> Layer<S> for RpcRateLimitLayer {
+    type Service = RpcRateLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RpcRateLimit {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RpcRateLimit<S> {
+    inner: S,
+    layer: RpcRateLimitLayer,
+}
+
+impl<S, B> Service<Request<B>> for RpcRateLimit<S>
+where
+    S: Service<Request<B>, Response = Response>,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Either<S::Future, future::Ready<Result<Response, S::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        if req.method() == Method::OPTIONS {
+            return Either::Left(self.inner.call(req));
+        }
+
+        match self.layer.limiter.acquire(self.layer.key(&req)) {
+            Ok(()) => Either::Left(self.inner.call(req)),
+            Err(retry_after) => Either::Right(future::ready(Ok(reject(&req, retry_after)))),
+        }
+    }
+}
+
+/// A `ResourceExhausted` error, encoded for whatever kind of call `req` is.
+fn reject<B>(req: &Request<B>, retry_after: Duration) -> Response {
+    let mut e = RpcError::new(
+        RpcErrorCode::ResourceExhausted,
+        "Rate limit exceeded".to_string(),
+    );
+    e.details.push(RpcErrorDetail::retry_info(retry_after));
+
+    encode_rejection(req, e, None)
+}
+
+#[derive(Debug)]
+struct Limiter {
+    burst: f64,
+    per_second: f64,
+    buckets: Mutex<Buckets>,
+}
+
+#[derive(Debug)]
+struct Buckets {
+    by_key: HashMap<String, Bucket>,
+    /// Prune once there are more buckets than this.
+    prune_above: usize,
+}
+
+impl Default for Buckets {
+    fn default() -> Self {
+        Self {
+            by_key: Default::default(),
+            prune_above: PRUNE_THRESHOLD,
+        }
+    }
+}
+
+impl Clone for Limiter {
+    // Only used by `burst`, before the layer is in use, so there are no buckets to copy.
+    fn clone(&self) -> Self {
+        Self {
+            burst: self.burst,
+            per_second: self.per_second,
+            buckets: Default::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Limiter {
+    /// Takes a token from `key`'s bucket, or says how long until there is one.
+    fn acquire(&self, key: String) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.by_key.len() > buckets.prune_above {
+            buckets
+                .by_key
+                .retain(|_, bucket| self.refill(bucket, now) < self.burst);
+            buckets.prune_above = PRUNE_THRESHOLD.max(buckets.by_key.len() * 2);
+        }
+
+        let bucket = buckets.by_key.entry(key).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+
+        if self.refill(bucket, now) >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.per_second,
+            ))
+        }
+    }
+
+    fn refill(&self, bucket: &mut Bucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.per_second).min(self.burst);
+        bucket.updated = now;
+        bucket.tokens
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn many_keys_prune_on_an_amortized_schedule() {
+        // A bucket per key, none of them full again before the end of the test.
+        let limiter = RpcRateLimitLayer::new(10, Duration::from_secs(3600)).limiter;
+        let mut scans = 0;
+        let mut prune_above = PRUNE_THRESHOLD;
+
+        for key in 0..PRUNE_THRESHOLD * 8 {
+            limiter.acquire(key.to_string()).unwrap();
+
+            let buckets = limiter.buckets.lock().unwrap();
+            if buckets.prune_above != prune_above {
+                scans += 1;
+                prune_above = buckets.prune_above;
+            }
+        }
+
+        // Nothing could be dropped, and the buckets were only scanned as their number doubled.
+        assert_eq!(
+            limiter.buckets.lock().unwrap().by_key.len(),
+            PRUNE_THRESHOLD * 8
+        );
+        assert_eq!(scans, 3);
+    }
+
+    #[test]
+    fn full_buckets_are_dropped() {
+        // Refills in well under a millisecond.
+        let limiter = RpcRateLimitLayer::new(1_000_000, Duration::from_millis(1)).limiter;
+        for key in 0..=PRUNE_THRESHOLD {
+            limiter.acquire(key.to_string()).unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(5));
+
+        limiter.acquire("last".to_string()).unwrap();
+        let buckets = limiter.buckets.lock().unwrap();
+        assert_eq!(buckets.by_key.len(), 1);
+        assert_eq!(buckets.prune_above, PRUNE_THRESHOLD);
+    }
+
+    #[test]
+    fn limits_survive_pruning() {
+        let limiter = RpcRateLimitLayer::new(1, Duration::from_secs(3600)).limiter;
+        limiter.acquire("limited".to_string()).unwrap();
+        for key in 0..PRUNE_THRESHOLD * 2 {
+            limiter.acquire(key.to_string()).unwrap();
+        }
+
+        assert!(limiter.acquire("limited".to_string()).is_err());
+    }
+}
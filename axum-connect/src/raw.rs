@@ -0,0 +1,221 @@
+//! Handlers that skip decoding and encoding messages, for pass-through proxies and custom codecs.
+//! The request is still negotiated and read like any other (protocol headers, body limits,
+//! tracing, ...), but the handler gets the payload as is and answers with the bytes of an already
+//! encoded message, in the codec of the call:
+//!
+//! ```ignore
+//! let app = Router::new().rpc(raw_unary(
+//!     HelloWorldService::SAY_HELLO,
+//!     |_ctx, request: RpcRawMessage| async move {
+//!         upstream.forward(HelloWorldService::SAY_HELLO.path, request.binary, request.bytes).await
+//!     },
+//! ));
+//! ```
+//!
+//! Server-streaming handlers answer with a stream of encoded messages instead, which are each put
+//! in an envelope. The EndStreamResponse is written by the library, as usual.
+
+use std::panic::AssertUnwindSafe;
+
+use axum::{
+    body::{Body, Bytes},
+    http::Request,
+    response::Response,
+    routing::post,
+    Router,
+};
+use futures::{Future, FutureExt, Stream, StreamExt};
+
+use crate::{
+    cors::{allow_origin, allowed_origin, preflight},
+    descriptor::{record_registration, MethodDescriptor, MethodKind},
+    handler::{
+        call_handler,
+        codec::{read_request, EncodedMessage},
+        method_not_allowed, recover_panic, ResponseTags,
+    },
+    response::RpcResult,
+    router::RpcRouter,
+    service::RpcContext,
+    stream::{RpcStreamItem, StreamEnd},
+};
+
+/// The payload of a call, exactly as the client sent it (unwrapped from its envelope, for
+/// streaming calls).
+#[derive(Clone, Debug)]
+pub struct RpcRawMessage {
+    pub bytes: Bytes,
+    /// Whether the call uses the binary protobuf codec, rather than JSON. Responses must be
+    /// encoded the same way.
+    pub binary: bool,
+}
+
+/// Returns a registration (for `RpcRouterExt::rpc`) serving the unary `method` with `handler`,
+/// which answers with an encoded response message.
+///
+/// # Panics
+///
+/// If `method` is a streaming method.
+pub fn raw_unary<S, H, F>(
+    method: MethodDescriptor,
+    handler: H,
+) -> impl FnOnce(Router<S>) -> RpcRouter<S>
+where
+    S: Clone + Send + Sync + 'static,
+    H: FnOnce(RpcContext, RpcRawMessage) -> F + Clone + Send + Sync + 'static,
+    F: Future<Output = RpcResult<Bytes>> + Send + 'static,
+{
+    assert!(
+        method.kind == MethodKind::Unary,
+        "{} is not a unary method",
+        method.path
+    );
+
+    move |router: Router<S>| {
+        record_registration(method);
+        router.route(
+            method.path,
+            post(move |mut request: Request<Body>| async move {
+                request.extensions_mut().insert(method);
+                let origin = allowed_origin(&request);
+                allow_origin(origin, call_unary(handler, request).await)
+            })
+            .options(|request: Request<Body>| async move { preflight("POST, OPTIONS", &request) })
+            .fallback(|request: Request<Body>| method_not_allowed("POST, OPTIONS", request)),
+        )
+    }
+}
+
+/// Returns a registration (for `RpcRouterExt::rpc`) serving the server-streaming `method` with
+/// `handler`, which answers with a stream of encoded response messages.
+///
+/// # Panics
+///
+/// If `method` isn't a server-streaming method.
+pub fn raw_server_streaming<S, H, F, St>(
+    method: MethodDescriptor,
+    handler: H,
+) -> impl FnOnce(Router<S>) -> RpcRouter<S>
+where
+    S: Clone + Send + Sync + 'static,
+    H: FnOnce(RpcContext, RpcRawMessage) -> F + Clone + Send + Sync + 'static,
+    F: Future<Output = RpcResult<St>> + Send + 'static,
+    St: Stream<Item = RpcResult<Bytes>> + Send + 'static,
+{
+    assert!(
+        method.kind == MethodKind::ServerStreaming,
+        "{} is not a server-streaming method",
+        method.path
+    );
+
+    move |router: Router<S>| {
+        record_registration(method);
+        router.route(
+            method.path,
+            post(move |mut request: Request<Body>| async move {
+                request.extensions_mut().insert(method);
+                let origin = allowed_origin(&request);
+                allow_origin(origin, call_server_streaming(handler, request).await)
+            })
+            .options(|request: Request<Body>| async move { preflight("POST, OPTIONS", &request) })
+            .fallback(|request: Request<Body>| method_not_allowed("POST, OPTIONS", request)),
+        )
+    }
+}
+
+async fn call_unary<H, F>(handler: H, req: Request<Body>) -> Response
+where
+    H: FnOnce(RpcContext, RpcRawMessage) -> F,
+    F: Future<Output = RpcResult<Bytes>>,
+{
+    #[cfg(feature = "tracing")]
+    let span = crate::trace::request_span(&req);
+    let tags = ResponseTags::new(&req);
+
+    let fut = async move {
+        let (parts, encoder, payload) = match read_request(req, false).await {
+            Ok(read) => read,
+            Err(res) => return res,
+        };
+
+        let message = match payload {
+            Ok(bytes) => RpcRawMessage {
+                bytes,
+                binary: encoder.binary,
+            },
+            Err(e) => return encoder.encode_error_response(e),
+        };
+
+        let ctx = RpcContext::from_parts(parts);
+        let config = ctx.config();
+        let handler = async move { handler(ctx, message).await };
+        let res = encoder
+            .before_deadline(call_handler(&config, handler))
+            .await
+            .and_then(|res| res)
+            .and_then(|res| res)
+            .map(EncodedMessage);
+
+        encoder.encode_response(res)
+    }
+    .map(move |res| tags.apply(res));
+
+    #[cfg(feature = "tracing")]
+    let fut = tracing::Instrument::instrument(fut, span);
+
+    fut.await
+}
+
+async fn call_server_streaming<H, F, St>(handler: H, req: Request<Body>) -> Response
+where
+    H: FnOnce(RpcContext, RpcRawMessage) -> F,
+    F: Future<Output = RpcResult<St>>,
+    St: Stream<Item = RpcResult<Bytes>> + Send + 'static,
+{
+    #[cfg(feature = "tracing")]
+    let span = crate::trace::request_span(&req);
+    let tags = ResponseTags::new(&req);
+
+    let fut = async move {
+        let (parts, encoder, payload) = match read_request(req, true).await {
+            Ok(read) => read,
+            Err(res) => return res,
+        };
+
+        let message = match payload {
+            Ok(bytes) => RpcRawMessage {
+                bytes,
+                binary: encoder.binary,
+            },
+            Err(e) => return encoder.encode_error_response(e),
+        };
+
+        let ctx = RpcContext::from_parts(parts);
+        let config = ctx.config();
+        let handler = async move { handler(ctx, message).await };
+        let stream = encoder
+            .before_deadline(call_handler(&config, handler))
+            .await
+            .and_then(|res| res)
+            .and_then(|res| res);
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => return encoder.encode_error_response(e),
+        };
+
+        let stream =
+            StreamExt::catch_unwind(AssertUnwindSafe(stream)).map(move |item| match item {
+                Ok(Ok(bytes)) => RpcStreamItem::Message(EncodedMessage(bytes)),
+                Ok(Err(e)) => RpcStreamItem::End(StreamEnd::error(e)),
+                Err(panic) => RpcStreamItem::End(StreamEnd::error(recover_panic(&config, panic))),
+            });
+
+        encoder.encode_stream(stream)
+    }
+    .map(move |res| tags.apply(res));
+
+    #[cfg(feature = "tracing")]
+    let fut = tracing::Instrument::instrument(fut, span);
+
+    fut.await
+}
@@ -0,0 +1,128 @@
+//! Masking sensitive fields (passwords, tokens, ...) out of logged messages.
+//!
+//! Mark fields with the `(axum_connect.redact)` option, from the `axum_connect/options.proto` that
+//! axum-connect-build puts on the include path:
+//!
+//! ```protobuf
+//! import "axum_connect/options.proto";
+//!
+//! message LoginRequest {
+//!   string user = 1;
+//!   string password = 2 [(axum_connect.redact) = true];
+//! }
+//! ```
+//!
+//! Codegen implements `RpcRedact` for every message, clearing marked fields (and those of nested
+//! messages). With the `tracing` feature and `RpcConfig::log_messages`, requests and responses are
+//! logged at `DEBUG` in the RPC's span, redacted. Use `Redacted` to log messages yourself.
+
+use std::{any::Any, fmt};
+
+/// Implemented by generated messages, see the module docs.
+pub trait RpcRedact {
+    /// Resets every field marked with `(axum_connect.redact)` to its default value.
+    fn redact(&mut self);
+}
+
+/// `Debug`s a message with its sensitive fields masked, ex.
+/// `tracing::info!(request = ?Redacted(&request))`.
+pub struct Redacted<'a, M>(pub &'a M);
+
+impl<M> fmt::Debug for Redacted<'_, M>
+where
+    M: RpcRedact + Clone + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut message = self.0.clone();
+        message.redact();
+        message.fmt(f)
+    }
+}
+
+/// How to log the request and response messages of a method, redacted. Generated routes attach
+/// it to their requests, so transports can log messages without knowing their types.
+#[derive(Clone, Copy)]
+pub struct RpcMessageLog {
+    request: fn(&dyn Any) -> Option<String>,
+    response: fn(&dyn Any) -> Option<String>,
+}
+
+impl RpcMessageLog {
+    pub fn of<TMReq, TMRes>() -> Self
+    where
+        TMReq: RpcRedact + Clone + fmt::Debug + 'static,
+        TMRes: RpcRedact + Clone + fmt::Debug + 'static,
+    {
+        Self {
+            request: describe::<TMReq>,
+            response: describe::<TMRes>,
+        }
+    }
+
+    /// The log of the call, if the router asked for messages to be logged.
+    #[cfg(feature = "tracing")]
+    pub(crate) fn for_call(
+        parts: &axum::http::request::Parts,
+        config: &crate::config::RpcConfig,
+    ) -> Option<Self> {
+        if config.log_messages {
+            parts.extensions.get::<Self>().copied()
+        } else {
+            None
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    pub(crate) fn request(&self, message: &dyn Any) {
+        if let Some(message) = (self.request)(message) {
+            tracing::debug!(request = %message, "RPC request");
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    pub(crate) fn response(&self, message: &dyn Any) {
+        if let Some(message) = (self.response)(message) {
+            tracing::debug!(response = %message, "RPC response");
+        }
+    }
+}
+
+fn describe<M>(message: &dyn Any) -> Option<String>
+where
+    M: RpcRedact + Clone + fmt::Debug + 'static,
+{
+    message
+        .downcast_ref::<M>()
+        .map(|message| format!("{:?}", Redacted(message)))
+}
+
+// Well-known types have nothing to redact, but are often used as requests (`Empty`) or responses.
+macro_rules! impl_nothing_to_redact {
+    ($($ty:ident),*) => {
+        $(
+            impl RpcRedact for pbjson_types::$ty {
+                fn redact(&mut self) {}
+            }
+        )*
+    };
+}
+
+impl_nothing_to_redact!(
+    Any,
+    BoolValue,
+    BytesValue,
+    DoubleValue,
+    Duration,
+    Empty,
+    FieldMask,
+    FloatValue,
+    Int32Value,
+    Int64Value,
+    ListValue,
+    StringValue,
+    Struct,
+    Timestamp,
+    UInt32Value,
+    UInt64Value,
+    Value
+);
@@ -0,0 +1,142 @@
+//! Connect errors for requests rejected before they reach an RPC handler. Middleware (ex.
+//! `DefaultBodyLimit`, `tower_http`'s `RequestBodyLimitLayer`, an auth proxy layer, axum's own
+//! extractors) answers with plain HTTP errors, which Connect clients can only report as opaque
+//! failures. `RpcRejectionLayer` re-encodes them as Connect errors, with a code matching the
+//! status (ex. `resource_exhausted` for a 413):
+//!
+//! ```ignore
+//! let app = Router::new()
+//!     .rpc(HelloWorldService::say_hello(say_hello))
+//!     .layer(RequestBodyLimitLayer::new(1024 * 1024))
+//!     .layer(RpcRejectionLayer);
+//! ```
+//!
+//! Only error responses to requests that look like Connect calls (by content type) are touched,
+//! and never ones coming from an RPC handler. Re-encoded errors go through the error hook and
+//! redaction of the `RpcConfig`, if the layer is applied before `rpc_config`.
+
+use std::task::{Context, Poll};
+
+use axum::{
+    body::to_bytes,
+    http::{Method, Request, StatusCode},
+    response::Response,
+};
+use futures::future::BoxFuture;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{
+    config::RpcConfig,
+    descriptor::MethodDescriptor,
+    error::{RpcError, RpcErrorCode},
+    handler::codec::{encode_error_response, guess_codec, rejection_error, StreamOutcome},
+};
+
+/// Rejection bodies longer than this aren't used as the error message.
+const MAX_MESSAGE_LEN: usize = 1024;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RpcRejectionLayer;
+
+impl<S> Layer<S> for RpcRejectionLayer {
+    type Service = RpcRejection<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RpcRejection { inner }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RpcRejection<S> {
+    inner: S,
+}
+
+impl<S, B> Service<Request<B>> for RpcRejection<S>
+where
+    S: Service<Request<B>, Response = Response>,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let codec = if req.method() == Method::OPTIONS {
+            None
+        } else {
+            guess_codec(req.headers())
+        };
+        let config = req.extensions().get::<RpcConfig>().cloned();
+        let path = req.uri().path().to_string();
+        let fut = self.inner.call(req);
+
+        Box::pin(async move {
+            let res = fut.await?;
+            match codec {
+                Some((binary, for_streaming)) if is_rejection(&res) => {
+                    let e = rejection_error(config.as_ref(), &path, connect_error(res).await, None);
+                    let mut res = encode_error_response(&e, binary, for_streaming);
+                    res.extensions_mut().insert(e.code);
+                    Ok(res)
+                }
+                _ => Ok(res),
+            }
+        })
+    }
+}
+
+/// Whether `res` is a plain HTTP error, rather than a success or anything from an RPC handler.
+fn is_rejection(res: &Response) -> bool {
+    let extensions = res.extensions();
+    (res.status().is_client_error() || res.status().is_server_error())
+        && extensions.get::<MethodDescriptor>().is_none()
+        && extensions.get::<RpcErrorCode>().is_none()
+        && extensions.get::<StreamOutcome>().is_none()
+}
+
+/// The Connect error matching the plain HTTP error `res`.
+async fn connect_error(res: Response) -> RpcError {
+    let status = res.status();
+    // Plain text rejections (axum's are) make for a good message, anything else doesn't.
+    let message = to_bytes(res.into_body(), MAX_MESSAGE_LEN)
+        .await
+        .ok()
+        .and_then(|body| String::from_utf8(body.to_vec()).ok())
+        .map(|body| body.trim().to_string())
+        .filter(|body| !body.is_empty() && !body.starts_with('<') && !body.starts_with('{'))
+        .unwrap_or_else(|| {
+            status
+                .canonical_reason()
+                .unwrap_or("Request rejected")
+                .to_string()
+        });
+
+    RpcError::new(code_for_status(status), message)
+}
+
+fn code_for_status(status: StatusCode) -> RpcErrorCode {
+    match status {
+        StatusCode::BAD_REQUEST
+        | StatusCode::UNSUPPORTED_MEDIA_TYPE
+        | StatusCode::UNPROCESSABLE_ENTITY => RpcErrorCode::InvalidArgument,
+        StatusCode::UNAUTHORIZED => RpcErrorCode::Unauthenticated,
+        StatusCode::FORBIDDEN => RpcErrorCode::PermissionDenied,
+        StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED => {
+            RpcErrorCode::Unimplemented
+        }
+        StatusCode::REQUEST_TIMEOUT | StatusCode::GATEWAY_TIMEOUT => RpcErrorCode::DeadlineExceeded,
+        StatusCode::CONFLICT => RpcErrorCode::Aborted,
+        StatusCode::PRECONDITION_FAILED => RpcErrorCode::FailedPrecondition,
+        StatusCode::PAYLOAD_TOO_LARGE | StatusCode::TOO_MANY_REQUESTS => {
+            RpcErrorCode::ResourceExhausted
+        }
+        StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE => RpcErrorCode::Unavailable,
+        status if status.is_server_error() => RpcErrorCode::Internal,
+        _ => RpcErrorCode::Unknown,
+    }
+}
@@ -1,14 +1,49 @@
+use axum::{
+    http::{HeaderMap, HeaderName},
+    response::{IntoResponse, Response},
+};
+use futures::Stream;
 use prost::Message;
 
-use crate::error::{RpcError, RpcIntoError};
+use crate::{
+    error::{RpcError, RpcErrorCode, RpcIntoError},
+    stream::{RpcStreamItem, StreamEnd},
+};
 
 pub type RpcResult<M> = Result<M, RpcError>;
 
-pub trait RpcIntoResponse<T>: Send + Sync + 'static
+pub trait RpcIntoResponse<T>: Send + 'static
 where
     T: Message,
 {
     fn rpc_into_response(self) -> RpcResult<T>;
+
+    /// Same as `rpc_into_response`, but also collects the headers and trailers contributed by
+    /// the leading elements of a tuple (see `RpcIntoResponseParts`). Only tuples override this.
+    fn rpc_into_response_with_parts(self, _parts: &mut RpcResponseParts) -> RpcResult<T>
+    where
+        Self: Sized,
+    {
+        self.rpc_into_response()
+    }
+
+    /// Escape hatch for return types that opt out of Connect encoding entirely. Only
+    /// `RpcRawResponse` overrides this, everything else is encoded as a Connect message or error.
+    fn rpc_into_raw_response(self) -> Result<Response, Self>
+    where
+        Self: Sized,
+    {
+        Err(self)
+    }
+
+    /// How an item of a response stream is sent: as a message, or as the end of the stream. Only
+    /// `StreamEnd` (and `Result`s of one) override this, an error ends the stream too.
+    fn rpc_into_stream_item(self) -> RpcStreamItem<T>
+    where
+        Self: Sized,
+    {
+        self.rpc_into_response().into()
+    }
 }
 
 impl<T> RpcIntoResponse<T> for T
@@ -23,9 +58,277 @@ where
 impl<T, E> RpcIntoResponse<T> for Result<T, E>
 where
     T: Message + 'static,
-    E: RpcIntoError + Send + Sync + 'static,
+    E: RpcIntoError + Send + 'static,
 {
     fn rpc_into_response(self) -> RpcResult<T> {
         self.map_err(|e| e.rpc_into_error())
     }
 }
+
+// `StreamEnd` is deliberately not `RpcIntoError`, which keeps this from overlapping with the impl
+// above.
+impl<T> RpcIntoResponse<T> for Result<T, StreamEnd>
+where
+    T: Message + 'static,
+{
+    fn rpc_into_response(self) -> RpcResult<T> {
+        self.or_else(|end| end.rpc_into_response())
+    }
+
+    fn rpc_into_stream_item(self) -> RpcStreamItem<T> {
+        match self {
+            Ok(message) => RpcStreamItem::Message(message),
+            Err(end) => RpcStreamItem::End(end),
+        }
+    }
+}
+
+impl<T> RpcIntoResponse<T> for StreamEnd
+where
+    T: Message,
+{
+    fn rpc_into_response(self) -> RpcResult<T> {
+        Err(self.error.unwrap_or_else(|| {
+            RpcError::new(
+                RpcErrorCode::Internal,
+                "StreamEnd only means something in a response stream".to_string(),
+            )
+        }))
+    }
+
+    fn rpc_into_stream_item(self) -> RpcStreamItem<T> {
+        RpcStreamItem::End(self)
+    }
+}
+
+/// What a server-streaming handler can return: a stream of anything that is `RpcIntoResponse`, or
+/// a `Result` of one. An error rejects the call before the stream starts (ex. failed auth or
+/// validation, a missing resource), and is sent as the one and only frame.
+///
+/// `M` only tells the two impls apart, it's inferred.
+pub trait RpcIntoStream<T, M>: Send + 'static
+where
+    T: Message,
+{
+    type Item: RpcIntoResponse<T>;
+    type Stream: Stream<Item = Self::Item> + Send + 'static;
+
+    fn rpc_into_stream(self) -> RpcResult<Self::Stream>;
+}
+
+// Tells the `RpcIntoStream` impls apart: a `Result` could (as far as the compiler knows) also be
+// a `Stream` some day. Same trick as axum's `ViaParts`/`ViaRequest`.
+mod private {
+    pub enum ViaStream {}
+    pub enum ViaResult {}
+}
+
+impl<T, S> RpcIntoStream<T, private::ViaStream> for S
+where
+    T: Message,
+    S: Stream + Send + 'static,
+    S::Item: RpcIntoResponse<T>,
+{
+    type Item = S::Item;
+    type Stream = S;
+
+    fn rpc_into_stream(self) -> RpcResult<Self::Stream> {
+        Ok(self)
+    }
+}
+
+impl<T, S, E> RpcIntoStream<T, private::ViaResult> for Result<S, E>
+where
+    T: Message,
+    S: Stream + Send + 'static,
+    S::Item: RpcIntoResponse<T>,
+    E: RpcIntoError + Send + 'static,
+{
+    type Item = S::Item;
+    type Stream = S;
+
+    fn rpc_into_stream(self) -> RpcResult<Self::Stream> {
+        self.map_err(|e| e.rpc_into_error())
+    }
+}
+
+/// A fully custom HTTP response returned from a unary RPC handler.
+///
+/// This is NOT a Connect response. The wrapped response is sent to the client as-is: no content
+/// type negotiation, no Connect error encoding, nothing. Connect clients will not know what to do
+/// with it, so only reach for this when the caller isn't a Connect client anyway (for example an
+/// OAuth device-flow redirect, or a binary download served from an RPC route).
+///
+/// Returning this from a server-streaming handler is an `Internal` error.
+pub struct RpcRawResponse(pub Response);
+
+impl RpcRawResponse {
+    pub fn new<R>(response: R) -> Self
+    where
+        R: IntoResponse,
+    {
+        Self(response.into_response())
+    }
+}
+
+impl<T> RpcIntoResponse<T> for RpcRawResponse
+where
+    T: Message,
+{
+    fn rpc_into_response(self) -> RpcResult<T> {
+        Err(RpcError::new(
+            RpcErrorCode::Internal,
+            "RpcRawResponse cannot be encoded as a Connect message".to_string(),
+        ))
+    }
+
+    fn rpc_into_raw_response(self) -> Result<Response, Self> {
+        Ok(self.0)
+    }
+}
+
+/// Headers and trailers contributed to a unary response by a handler's return value, on top of
+/// the message (or error) itself.
+///
+/// Connect unary responses don't have real trailers; they are sent as headers prefixed with
+/// `trailer-`, which Connect clients turn back into trailers.
+#[derive(Clone, Debug, Default)]
+pub struct RpcResponseParts {
+    pub headers: HeaderMap,
+    pub trailers: HeaderMap,
+}
+
+impl RpcResponseParts {
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty() && self.trailers.is_empty()
+    }
+
+    pub(crate) fn apply(self, mut res: Response) -> Response {
+        res.headers_mut().extend(self.headers);
+
+        let mut name = None;
+        for (next, value) in self.trailers {
+            // `HeaderMap`'s owned iterator only yields the name for the first of several values.
+            if let Some(next) = next {
+                name = HeaderName::try_from(format!("trailer-{}", next)).ok();
+            }
+            if let Some(name) = &name {
+                res.headers_mut().append(name.clone(), value);
+            }
+        }
+
+        res
+    }
+}
+
+/// The response-side twin of `RpcFromRequestParts`: something a handler can return ahead of its
+/// message in a tuple, ex. `(headers, HelloResponse { .. })`, to add headers or trailers to the
+/// response. Like `IntoResponseParts` in axum, but it cannot touch the status or the body.
+pub trait RpcIntoResponseParts: Send + 'static {
+    fn rpc_into_response_parts(self, parts: &mut RpcResponseParts) -> Result<(), RpcError>;
+}
+
+impl RpcIntoResponseParts for HeaderMap {
+    fn rpc_into_response_parts(self, parts: &mut RpcResponseParts) -> Result<(), RpcError> {
+        parts.headers.extend(self);
+        Ok(())
+    }
+}
+
+/// Trailers to send with the response (see `RpcResponseParts`).
+#[derive(Clone, Debug, Default)]
+pub struct RpcTrailers(pub HeaderMap);
+
+impl RpcIntoResponseParts for RpcTrailers {
+    fn rpc_into_response_parts(self, parts: &mut RpcResponseParts) -> Result<(), RpcError> {
+        parts.trailers.extend(self.0);
+        Ok(())
+    }
+}
+
+/// Sends the cookies added to (or removed from) the jar as `Set-Cookie` headers.
+#[cfg(feature = "axum-extra")]
+impl RpcIntoResponseParts for axum_extra::extract::CookieJar {
+    fn rpc_into_response_parts(self, parts: &mut RpcResponseParts) -> Result<(), RpcError> {
+        parts
+            .headers
+            .extend(self.into_response().into_parts().0.headers);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "cookie-private")]
+impl<K> RpcIntoResponseParts for axum_extra::extract::PrivateCookieJar<K>
+where
+    K: Send + 'static,
+{
+    fn rpc_into_response_parts(self, parts: &mut RpcResponseParts) -> Result<(), RpcError> {
+        parts
+            .headers
+            .extend(self.into_response().into_parts().0.headers);
+        Ok(())
+    }
+}
+
+impl<P> RpcIntoResponseParts for Option<P>
+where
+    P: RpcIntoResponseParts,
+{
+    fn rpc_into_response_parts(self, parts: &mut RpcResponseParts) -> Result<(), RpcError> {
+        match self {
+            Some(inner) => inner.rpc_into_response_parts(parts),
+            None => Ok(()),
+        }
+    }
+}
+
+// The message always comes last, same as request extractors. There are separate impls for a bare
+// message and a `Result`, rather than one for any `RpcIntoResponse`, because the latter would
+// overlap with the blanket `impl RpcIntoResponse<T> for T`.
+macro_rules! impl_into_response_tuple {
+    ([$($ty:ident),*]) => {
+        #[allow(non_snake_case)]
+        impl<T, $($ty,)*> RpcIntoResponse<T> for ($($ty,)* T,)
+        where
+            T: Message + 'static,
+            $( $ty: RpcIntoResponseParts, )*
+        {
+            fn rpc_into_response(self) -> RpcResult<T> {
+                self.rpc_into_response_with_parts(&mut RpcResponseParts::default())
+            }
+
+            fn rpc_into_response_with_parts(self, parts: &mut RpcResponseParts) -> RpcResult<T> {
+                let ($($ty,)* message,) = self;
+                $( $ty.rpc_into_response_parts(parts)?; )*
+                Ok(message)
+            }
+        }
+
+        #[allow(non_snake_case)]
+        impl<T, E, $($ty,)*> RpcIntoResponse<T> for ($($ty,)* Result<T, E>,)
+        where
+            T: Message + 'static,
+            E: RpcIntoError + Send + 'static,
+            $( $ty: RpcIntoResponseParts, )*
+        {
+            fn rpc_into_response(self) -> RpcResult<T> {
+                self.rpc_into_response_with_parts(&mut RpcResponseParts::default())
+            }
+
+            fn rpc_into_response_with_parts(self, parts: &mut RpcResponseParts) -> RpcResult<T> {
+                let ($($ty,)* res,) = self;
+                $( $ty.rpc_into_response_parts(parts)?; )*
+                res.map_err(|e| e.rpc_into_error())
+            }
+        }
+    };
+}
+
+impl_into_response_tuple!([T1]);
+impl_into_response_tuple!([T1, T2]);
+impl_into_response_tuple!([T1, T2, T3]);
+impl_into_response_tuple!([T1, T2, T3, T4]);
+impl_into_response_tuple!([T1, T2, T3, T4, T5]);
+impl_into_response_tuple!([T1, T2, T3, T4, T5, T6]);
+impl_into_response_tuple!([T1, T2, T3, T4, T5, T6, T7]);
+impl_into_response_tuple!([T1, T2, T3, T4, T5, T6, T7, T8]);
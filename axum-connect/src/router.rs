@@ -1,18 +1,271 @@
-use axum::Router;
+use std::{
+    convert::Infallible,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use axum::{
+    extract::Request,
+    response::{IntoResponse, Response},
+    routing::{post, Route},
+    Extension, Router,
+};
+use futures::future::Either;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{
+    config::RpcConfig,
+    cors::{allow_origin, allowed_origin, preflight},
+    descriptor::{is_rpc_path, RpcMethods},
+    handler::{method_not_allowed, unimplemented_method},
+};
 
 pub trait RpcRouterExt<S>: Sized {
     fn rpc<F>(self, register: F) -> Self
     where
         F: FnOnce(Self) -> RpcRouter<S>;
+
+    /// `rpc`, but served under `prefix` (ex. `/api/hello.HelloWorldService/SayHello` for a prefix
+    /// of `/api`). Shorthand for nesting a router of just this RPC, see `nest_rpc`.
+    fn rpc_with_prefix<F>(self, prefix: &str, register: F) -> Self
+    where
+        F: FnOnce(Self) -> RpcRouter<S>;
+
+    /// Applies `config` to every RPC registered on this router so far.
+    fn rpc_config(self, config: RpcConfig) -> Self;
+
+    /// `Router::merge` for routers of RPCs. Everything RPC-specific (method descriptors, the
+    /// `RpcConfig` of each side) travels with the routes, so nothing is lost. Note that an
+    /// `rpc_config` applied to `self` *before* merging doesn't cover the merged routes.
+    fn merge_rpc(self, other: RpcRouter<S>) -> Self;
+
+    /// `Router::nest` for routers of RPCs. Connect clients then need the prefix as part of their
+    /// base URL. Nesting at `""` or `"/"` (which axum rejects) merges instead.
+    fn nest_rpc(self, path: &str, other: RpcRouter<S>) -> Self;
+
+    /// Replaces the handler of the RPC already registered on `path` (ex.
+    /// `HelloWorldService::SAY_HELLO.path`) with the one `register` registers, so tests can stub
+    /// out specific methods of a fully assembled app:
+    ///
+    /// ```ignore
+    /// let app = app().override_rpc(
+    ///     HelloWorldService::SAY_HELLO.path,
+    ///     HelloWorldService::say_hello(|_: HelloRequest| async { fake_response() }),
+    /// );
+    /// ```
+    ///
+    /// The replacement has no state of its own, and runs *outside* every layer already applied to
+    /// the router: it doesn't see their extensions (ex. `rpc_config`) or checks (ex. auth). Apply
+    /// the ones it needs in `register` itself, ex.
+    /// `|router| HelloWorldService::say_hello(handler)(router).rpc_config(config)`.
+    ///
+    /// `path` also matches under any prefix the RPC is nested at, whole path segments only. Calls
+    /// to any other path are unaffected.
+    fn override_rpc<F>(self, path: &str, register: F) -> Self
+    where
+        F: FnOnce(Router) -> RpcRouter<()>;
 }
 
-impl<S> RpcRouterExt<S> for Router<S> {
+impl<S> RpcRouterExt<S> for Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
     fn rpc<F>(self, register: F) -> Self
     where
         F: FnOnce(Self) -> RpcRouter<S>,
     {
         register(self)
     }
+
+    fn rpc_with_prefix<F>(self, prefix: &str, register: F) -> Self
+    where
+        F: FnOnce(Self) -> RpcRouter<S>,
+    {
+        self.nest_rpc(prefix, register(Router::new()))
+    }
+
+    fn rpc_config(self, mut config: RpcConfig) -> Self {
+        config.methods = config.methods.or_else(RpcMethods::current);
+        self.layer(Extension(config))
+    }
+
+    fn merge_rpc(self, other: RpcRouter<S>) -> Self {
+        self.merge(other)
+    }
+
+    fn nest_rpc(self, path: &str, other: RpcRouter<S>) -> Self {
+        match path.trim_end_matches('/') {
+            "" => self.merge(other),
+            path => self.nest(path, other),
+        }
+    }
+
+    fn override_rpc<F>(self, path: &str, register: F) -> Self
+    where
+        F: FnOnce(Router) -> RpcRouter<()>,
+    {
+        self.layer(OverrideLayer {
+            path: path.into(),
+            replacement: register(Router::new()),
+        })
+    }
 }
 
 pub type RpcRouter<S> = Router<S>;
+
+/// Sends calls to `path` to `replacement` instead of the route they were meant for, see
+/// `RpcRouterExt::override_rpc`.
+#[derive(Clone)]
+struct OverrideLayer {
+    path: Arc<str>,
+    replacement: Router,
+}
+
+impl<I> Layer<I> for OverrideLayer {
+    type Service = Override<I>;
+
+    fn layer(&self, inner: I) -> Self::Service {
+        Override {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Override<I> {
+    inner: I,
+    layer: OverrideLayer,
+}
+
+impl<I> Service<Request> for Override<I>
+where
+    I: Service<Request, Response = Response, Error = Infallible>,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Either<I::Future, <Router as Service<Request>>::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        if is_rpc_path(req.uri().path(), &self.layer.path) {
+            Either::Right(self.layer.replacement.call(req))
+        } else {
+            Either::Left(self.inner.call(req))
+        }
+    }
+}
+
+/// Returns a registration (for `RpcRouterExt::rpc`) answering calls to every method of `service`
+/// (ex. `hello.HelloWorldService`) that isn't registered with an `Unimplemented` Connect error,
+/// rather than a bare 404. Registered methods take precedence, whatever the order. Generated
+/// services expose this as `unimplemented_fallback()`.
+pub fn unimplemented_service<S>(service: &str) -> impl FnOnce(Router<S>) -> RpcRouter<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let path = format!("/{}/{{*method}}", service);
+
+    move |router: Router<S>| {
+        let handler = |request: Request| async move {
+            let origin = allowed_origin(&request);
+            allow_origin(origin, unimplemented_method(request).await)
+        };
+
+        router.route(
+            &path,
+            post(handler)
+                .get(handler)
+                .options(
+                    |request: Request| async move { preflight("POST, GET, OPTIONS", &request) },
+                )
+                .fallback(|request: Request| method_not_allowed("POST, GET, OPTIONS", request)),
+        )
+    }
+}
+
+type Register<S> = Box<dyn FnOnce(Router<S>) -> Router<S>>;
+
+/// Collects the RPCs of one service along with the layers that should wrap all of them (and only
+/// them). Generated services wrap this in a typed builder, ex. `HelloWorldService::builder()`.
+///
+/// Layers wrap the routes the same way `Router::layer` does: the last layer added is the
+/// outermost one, regardless of whether it was added before or after the RPCs.
+pub struct RpcServiceBuilder<S> {
+    routes: Vec<Register<S>>,
+    layers: Vec<Register<S>>,
+}
+
+impl<S> Default for RpcServiceBuilder<S> {
+    fn default() -> Self {
+        Self {
+            routes: vec![],
+            layers: vec![],
+        }
+    }
+}
+
+impl<S> RpcServiceBuilder<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn rpc<F>(mut self, register: F) -> Self
+    where
+        F: FnOnce(Router<S>) -> RpcRouter<S> + 'static,
+    {
+        self.routes.push(Box::new(register));
+        self
+    }
+
+    pub fn layer<L>(self, layer: L) -> Self
+    where
+        Self: RpcServiceLayer<L>,
+    {
+        RpcServiceLayer::push_layer(self, layer)
+    }
+
+    /// Finishes the service, returning something that can be passed to `RpcRouterExt::rpc`.
+    pub fn build(self) -> impl FnOnce(Router<S>) -> RpcRouter<S> {
+        move |router: Router<S>| {
+            let mut service = Router::new();
+            for register in self.routes {
+                service = register(service);
+            }
+            for layer in self.layers {
+                service = layer(service);
+            }
+
+            router.merge(service)
+        }
+    }
+}
+
+/// Implemented for every layer `Router::layer` accepts. Exists so generated service builders can
+/// forward layers without spelling out (and keeping in sync) axum's bounds themselves.
+pub trait RpcServiceLayer<L> {
+    fn push_layer(self, layer: L) -> Self;
+}
+
+impl<S, L> RpcServiceLayer<L> for RpcServiceBuilder<S>
+where
+    S: Clone + Send + Sync + 'static,
+    L: Layer<Route> + Clone + Send + Sync + 'static,
+    L::Service: Service<Request> + Clone + Send + Sync + 'static,
+    <L::Service as Service<Request>>::Response: IntoResponse + 'static,
+    <L::Service as Service<Request>>::Error: Into<Infallible> + 'static,
+    <L::Service as Service<Request>>::Future: Send + 'static,
+{
+    fn push_layer(mut self, layer: L) -> Self {
+        self.layers
+            .push(Box::new(move |router: Router<S>| router.layer(layer)));
+        self
+    }
+}
@@ -0,0 +1,261 @@
+//! A `/debug/rpcz`-style page listing the RPCs of a router, how often they were called (overall
+//! and in the last minute) and how often they failed, by error code. Meant for development, mount
+//! it with one line, *after* the RPCs it should count. The RPCs listed are the ones recorded by the
+//! `RpcMethods` the router is built in:
+//!
+//! ```ignore
+//! let app = RpcMethods::new().record(|| {
+//!     Router::new()
+//!         .rpc(HelloWorldService::say_hello(say_hello))
+//!         .rpc(rpcz("/debug/rpcz"))
+//! });
+//! ```
+//!
+//! Browsers get an HTML table, anything asking for `application/json` gets JSON. Streams count as
+//! failed if they end in an error, and as active until their body is dropped.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use axum::{
+    http::{header, HeaderMap},
+    middleware::map_response,
+    response::{Html, IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde::Serialize;
+
+use crate::{
+    descriptor::{MethodDescriptor, RpcMethods},
+    error::RpcErrorCode,
+    handler::codec::StreamOutcome,
+    router::RpcRouter,
+};
+
+/// How far back "recent" counts go, in seconds.
+const RECENT_SECONDS: u64 = 60;
+
+/// Returns a registration (for `RpcRouterExt::rpc`) counting calls to every RPC registered on the
+/// router so far, and serving the stats on `path`. Outside of `RpcMethods::record`, no RPCs are
+/// listed.
+pub fn rpcz<S>(path: &str) -> impl FnOnce(Router<S>) -> RpcRouter<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let path = path.to_string();
+
+    move |router: Router<S>| {
+        let stats = Arc::new(Stats::new(RpcMethods::current().unwrap_or_default()));
+        let recorder = stats.clone();
+        router
+            .layer(map_response(move |res: Response| {
+                recorder.record(&res);
+                async move { res }
+            }))
+            .route(
+                &path,
+                get(move |headers: HeaderMap| async move { stats.render(&headers) }),
+            )
+    }
+}
+
+struct Stats {
+    registered: RpcMethods,
+    started: Instant,
+    methods: Mutex<HashMap<&'static str, MethodStats>>,
+}
+
+struct MethodStats {
+    calls: u64,
+    codes: BTreeMap<&'static str, u64>,
+    /// Calls and errors per second, for the last `RECENT_SECONDS`, indexed by second modulo
+    /// `RECENT_SECONDS`.
+    recent: [(u64, u64, u64); RECENT_SECONDS as usize],
+    /// Streams that were still open the last time they were checked.
+    streams: Vec<StreamOutcome>,
+}
+
+impl Stats {
+    fn new(registered: RpcMethods) -> Self {
+        Self {
+            registered,
+            started: Instant::now(),
+            methods: Default::default(),
+        }
+    }
+
+    fn now(&self) -> u64 {
+        self.started.elapsed().as_secs()
+    }
+
+    fn record(&self, res: &Response) {
+        let Some(method) = res.extensions().get::<MethodDescriptor>() else {
+            return;
+        };
+
+        let now = self.now();
+        let mut methods = self.methods.lock().unwrap();
+        let stats = methods.entry(method.path).or_default();
+        stats.calls += 1;
+        stats.bucket(now).1 += 1;
+
+        if let Some(code) = res.extensions().get::<RpcErrorCode>() {
+            stats.record_error(code, now);
+        } else if let Some(outcome) = res.extensions().get::<StreamOutcome>() {
+            stats.streams.push(outcome.clone());
+        }
+        stats.settle_streams(now);
+    }
+
+    fn render(&self, headers: &HeaderMap) -> Response {
+        let now = self.now();
+        let mut methods = self.methods.lock().unwrap();
+
+        let report: Vec<_> = self
+            .registered
+            .get()
+            .into_iter()
+            .map(|method| {
+                let stats = methods.entry(method.path).or_default();
+                stats.settle_streams(now);
+                MethodReport::new(&method, stats, now)
+            })
+            .collect();
+
+        let wants_json = headers
+            .get(header::ACCEPT)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|accept| accept.contains("application/json"));
+
+        if wants_json {
+            Json(report).into_response()
+        } else {
+            Html(render_html(&report)).into_response()
+        }
+    }
+}
+
+impl Default for MethodStats {
+    // Arrays this long don't implement `Default`.
+    fn default() -> Self {
+        Self {
+            calls: 0,
+            codes: Default::default(),
+            recent: [(0, 0, 0); RECENT_SECONDS as usize],
+            streams: vec![],
+        }
+    }
+}
+
+impl MethodStats {
+    /// The `(second, calls, errors)` bucket of second `now`, reset if it was last used a while ago.
+    fn bucket(&mut self, now: u64) -> &mut (u64, u64, u64) {
+        let bucket = &mut self.recent[(now % RECENT_SECONDS) as usize];
+        if bucket.0 != now {
+            *bucket = (now, 0, 0);
+        }
+        bucket
+    }
+
+    fn record_error(&mut self, code: &RpcErrorCode, now: u64) {
+        *self.codes.entry(code.as_str()).or_default() += 1;
+        self.bucket(now).2 += 1;
+    }
+
+    /// Counts the errors of streams that are done. A stream is done once its body (which holds
+    /// the other handle on its outcome) is dropped.
+    fn settle_streams(&mut self, now: u64) {
+        let streams = std::mem::take(&mut self.streams);
+        for stream in streams {
+            if Arc::strong_count(&stream.0) > 1 {
+                self.streams.push(stream);
+            } else if let Some(code) = stream.0.get() {
+                self.record_error(code, now);
+            }
+        }
+    }
+
+    fn recent(&self, now: u64) -> (u64, u64) {
+        self.recent
+            .iter()
+            .filter(|(second, _, _)| now.saturating_sub(*second) < RECENT_SECONDS)
+            .fold((0, 0), |(calls, errors), (_, c, e)| (calls + c, errors + e))
+    }
+}
+
+#[derive(Serialize)]
+struct MethodReport {
+    path: &'static str,
+    kind: String,
+    calls: u64,
+    errors: u64,
+    error_rate: f64,
+    recent_calls: u64,
+    recent_errors: u64,
+    active_streams: usize,
+    error_codes: BTreeMap<&'static str, u64>,
+}
+
+impl MethodReport {
+    fn new(method: &MethodDescriptor, stats: &MethodStats, now: u64) -> Self {
+        let errors = stats.codes.values().sum();
+        let (recent_calls, recent_errors) = stats.recent(now);
+
+        Self {
+            path: method.path,
+            kind: format!("{:?}", method.kind),
+            calls: stats.calls,
+            errors,
+            error_rate: match stats.calls {
+                0 => 0.0,
+                calls => errors as f64 / calls as f64,
+            },
+            recent_calls,
+            recent_errors,
+            active_streams: stats.streams.len(),
+            error_codes: stats.codes.clone(),
+        }
+    }
+}
+
+fn render_html(report: &[MethodReport]) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html><html><head><title>rpcz</title></head><body><h1>rpcz</h1>\
+        <table border=\"1\" cellpadding=\"4\"><tr><th>Method</th><th>Kind</th><th>Calls</th>\
+        <th>Errors</th><th>Error rate</th><th>Calls (1m)</th><th>Errors (1m)</th>\
+        <th>Active streams</th><th>Error codes</th></tr>",
+    );
+
+    for method in report {
+        let codes = method
+            .error_codes
+            .iter()
+            .map(|(code, count)| format!("{}: {}", code, count))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        // Paths and codes come from proto identifiers, there's nothing to escape.
+        let _ = write!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{:.2}%</td><td>{}</td>\
+            <td>{}</td><td>{}</td><td>{}</td></tr>",
+            method.path,
+            method.kind,
+            method.calls,
+            method.errors,
+            method.error_rate * 100.0,
+            method.recent_calls,
+            method.recent_errors,
+            method.active_streams,
+            codes
+        );
+    }
+
+    html.push_str("</table></body></html>");
+    html
+}
@@ -0,0 +1,82 @@
+//! Serves JSON Schemas for the messages in a descriptor set, following the proto3 JSON mapping
+//! (the same one pbjson uses). Handy for form generators and contract testing tools. Mount it
+//! with:
+//!
+//! ```ignore
+//! const DESCRIPTOR_SET: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/proto_descriptor.bin"));
+//!
+//! let app = Router::new().rpc(json_schema_endpoint("/debug/schema", DESCRIPTOR_SET, false)?);
+//! ```
+//!
+//! `GET /debug/schema` lists every service method with links to its request and response
+//! schemas, `GET /debug/schema/{message}` returns the schema for a fully qualified message name.
+
+use axum::{
+    extract::Path,
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use prost_reflect::{DescriptorError, DescriptorPool};
+use serde_json::{json, Value};
+
+use crate::{error::RpcError, handler::codec::encode_error_response, router::RpcRouter};
+
+pub use axum_connect_schema::message_schema;
+
+/// Returns a registration (for `RpcRouterExt::rpc`) serving JSON Schemas for every message in
+/// `descriptor_set` (an encoded `FileDescriptorSet`) under `path`. Pass the
+/// `AxumConnectGenSettings::json_proto_field_names` the messages were generated with as
+/// `proto_field_names`, so the schemas name fields the way the messages serialize them.
+pub fn json_schema_endpoint<S>(
+    path: &str,
+    descriptor_set: &[u8],
+    proto_field_names: bool,
+) -> Result<impl FnOnce(Router<S>) -> RpcRouter<S>, DescriptorError>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let pool = DescriptorPool::decode(descriptor_set)?;
+    let path = path.trim_end_matches('/').to_string();
+
+    let services: Vec<Value> = pool
+        .services()
+        .map(|service| {
+            let methods: Vec<Value> = service
+                .methods()
+                .map(|method| {
+                    json!({
+                        "name": method.name(),
+                        "request": format!("{}/{}", path, method.input().full_name()),
+                        "response": format!("{}/{}", path, method.output().full_name()),
+                    })
+                })
+                .collect();
+
+            json!({ "name": service.full_name(), "methods": methods })
+        })
+        .collect();
+    let index = json!({ "services": services });
+
+    Ok(move |router: Router<S>| {
+        router
+            .route(&path, get(move || async move { Json(index) }))
+            .route(
+                &format!("{}/{{message}}", path),
+                get(move |Path(name): Path<String>| async move {
+                    schema_response(&pool, &name, proto_field_names)
+                }),
+            )
+    })
+}
+
+fn schema_response(pool: &DescriptorPool, name: &str, proto_field_names: bool) -> Response {
+    match pool.get_message_by_name(name) {
+        Some(message) => Json(message_schema(&message, proto_field_names)).into_response(),
+        None => encode_error_response(
+            &RpcError::not_found(format!("Unknown message: {}", name)),
+            false,
+            false,
+        ),
+    }
+}
@@ -0,0 +1,104 @@
+//! The transport-agnostic core of an RPC handler.
+//!
+//! Every handler function (any arity of extractors) implements `RpcService`: give it a decoded
+//! request message plus an `RpcContext` and it runs the extractors, the handler itself and the
+//! response conversion, yielding a message (or a stream of them) or an `RpcError`. Nothing in here
+//! knows about HTTP bodies, content types or envelopes; the axum integration is just one transport
+//! built on top of it (see `RpcHandlerUnary` and `RpcHandlerStream`). Other transports (WebSocket,
+//! NATS request-reply, an in-process bus, ...) can drive the exact same handlers.
+
+use std::pin::Pin;
+
+use axum::{
+    http::{request, Method, Request},
+    response::Response,
+};
+use futures::{Future, Stream};
+
+use crate::{
+    config::RpcConfig,
+    descriptor::MethodDescriptor,
+    response::{RpcResponseParts, RpcResult},
+    stream::RpcStreamItem,
+};
+
+/// Marker for `RpcService` impls of unary handlers.
+pub struct Unary;
+
+/// Marker for `RpcService` impls of server-streaming handlers.
+pub struct ServerStreaming;
+
+/// Everything about a call besides the request message: headers, extensions (including the
+/// `MethodDescriptor` and `RpcConfig`) and so on. Extractors read from (and may modify) these
+/// parts, so transports should fill in whatever their extractors need.
+pub struct RpcContext {
+    pub parts: request::Parts,
+}
+
+impl RpcContext {
+    /// An empty context for a call to `method`, for transports that don't have HTTP request parts
+    /// of their own. The parts look like a Connect POST to the method's path.
+    pub fn new(method: MethodDescriptor) -> Self {
+        let (mut parts, _) = Request::new(()).into_parts();
+        parts.method = Method::POST;
+        parts.uri = method
+            .path
+            .parse()
+            .expect("method paths are valid URI paths");
+        parts.extensions.insert(method);
+
+        Self { parts }
+    }
+
+    pub fn from_parts(parts: request::Parts) -> Self {
+        Self { parts }
+    }
+
+    /// Router-wide settings for this call (panic recovery, error hooks, ...).
+    pub fn with_config(mut self, config: RpcConfig) -> Self {
+        self.parts.extensions.insert(config);
+        self
+    }
+
+    pub fn method(&self) -> Option<MethodDescriptor> {
+        self.parts.extensions.get::<MethodDescriptor>().copied()
+    }
+
+    pub(crate) fn config(&self) -> RpcConfig {
+        RpcConfig::from_parts(&self.parts)
+    }
+}
+
+/// What a unary handler answered with.
+pub enum RpcUnaryReply<M> {
+    Message(M),
+    /// A message or error, along with headers and trailers contributed by the handler (see
+    /// `RpcIntoResponseParts`). Transports that can't carry them may drop them.
+    WithParts(RpcResult<M>, RpcResponseParts),
+    /// An `RpcRawResponse`. This only means something over HTTP, other transports should treat it
+    /// as an error.
+    Raw(Response),
+}
+
+/// What a server-streaming handler answered with. The stream ends at its first `End` item (an
+/// error included).
+pub type RpcStreamReply<M> = Pin<Box<dyn Stream<Item = RpcStreamItem<M>> + Send>>;
+
+pub trait RpcService<TMReq, TMRes, TKind, TUid, TState>:
+    Clone + Send + Sync + Sized + 'static
+{
+    /// `RpcUnaryReply<TMRes>` for unary handlers, `RpcStreamReply<TMRes>` for streaming ones.
+    type Reply: Send + 'static;
+
+    /// Runs the extractors, then the handler.
+    ///
+    /// The message is passed as a `Result` so transports can decode it up front but still report
+    /// a malformed message only after the extractors had their say: an unauthenticated request
+    /// should be told so, not that its body was garbage.
+    fn call_rpc(
+        self,
+        ctx: RpcContext,
+        message: RpcResult<TMReq>,
+        state: TState,
+    ) -> Pin<Box<dyn Future<Output = RpcResult<Self::Reply>> + Send>>;
+}
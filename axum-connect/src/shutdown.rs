@@ -0,0 +1,76 @@
+//! Ending open response streams cleanly on shutdown, enabled by the `shutdown` feature.
+//!
+//! Without this, a server shutting down gracefully waits on every open stream (which may well run
+//! forever), and eventually severs them mid-frame. With it, streams get a drain period to finish
+//! on their own, after which they are ended with a well-formed `Unavailable` error carrying a
+//! `google.rpc.RetryInfo` detail, so clients know to reconnect (to another instance) right away:
+//!
+//! ```ignore
+//! let shutdown = RpcShutdown::new(Duration::from_secs(5));
+//! let app = Router::new()
+//!     .rpc(HelloWorldService::say_hello_stream(say_hello_stream))
+//!     .rpc_config(RpcConfig::new().shutdown(shutdown.clone()));
+//!
+//! axum::serve(listener, app)
+//!     .with_graceful_shutdown(async move {
+//!         tokio::signal::ctrl_c().await.unwrap();
+//!         shutdown.begin();
+//!     })
+//!     .await?;
+//! ```
+
+use std::{sync::Arc, time::Duration};
+
+use futures::Future;
+use tokio::sync::watch;
+
+use crate::error::{RpcError, RpcErrorCode, RpcErrorDetail};
+
+/// The shutdown signal shared by every response stream. Cheap to clone, all clones are the same
+/// signal.
+#[derive(Clone, Debug)]
+pub struct RpcShutdown {
+    signal: Arc<watch::Sender<bool>>,
+    drain: Duration,
+}
+
+impl RpcShutdown {
+    /// Open streams are given `drain` to end on their own once shutdown begins.
+    pub fn new(drain: Duration) -> Self {
+        Self {
+            signal: Arc::new(watch::Sender::new(false)),
+            drain,
+        }
+    }
+
+    /// Starts the drain period. Calling it more than once is harmless.
+    pub fn begin(&self) {
+        self.signal.send_replace(true);
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        *self.signal.borrow()
+    }
+
+    /// Resolves once the drain period is over.
+    pub(crate) fn drained(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut signal = self.signal.subscribe();
+        let drain = self.drain;
+
+        async move {
+            // The sender lives as long as this (or any other) handle, so this can't fail.
+            let _ = signal.wait_for(|shutting_down| *shutting_down).await;
+            tokio::time::sleep(drain).await;
+        }
+    }
+
+    /// The error streams still open at the end of the drain period are ended with.
+    pub(crate) fn error(&self) -> RpcError {
+        let mut e = RpcError::new(
+            RpcErrorCode::Unavailable,
+            "Server is shutting down".to_string(),
+        );
+        e.details.push(RpcErrorDetail::retry_info(Duration::ZERO));
+        e
+    }
+}
@@ -0,0 +1,104 @@
+use axum::http::HeaderMap;
+use futures::{Stream, StreamExt};
+
+use crate::{
+    error::{RpcError, RpcIntoError},
+    response::RpcResult,
+};
+
+/// Where a message sits within a response stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StreamPosition {
+    /// Zero-based, strictly increasing index of the message within the stream.
+    pub sequence: u64,
+    /// Total number of messages the stream will yield, if known up front.
+    pub total: Option<u64>,
+}
+
+pub trait RpcStreamExt: Stream + Sized {
+    /// Hands each item to `stamp` along with its position in the stream, so the sequence number
+    /// (and total count, when known) can be written into the response message. Connect frames
+    /// don't carry per-message metadata, so the message itself is the only place clients can read
+    /// it back from to detect gaps or resume a feed.
+    ///
+    /// The total is taken from the stream's `size_hint` when it's exact, use `sequenced_with_total`
+    /// if you know it some other way.
+    fn sequenced<F, T>(self, stamp: F) -> impl Stream<Item = T>
+    where
+        F: FnMut(Self::Item, StreamPosition) -> T,
+    {
+        let total = match self.size_hint() {
+            (lower, Some(upper)) if lower == upper => Some(lower as u64),
+            _ => None,
+        };
+
+        self.sequenced_with_total(total, stamp)
+    }
+
+    /// Same as `sequenced`, but with an explicitly provided total.
+    fn sequenced_with_total<F, T>(self, total: Option<u64>, mut stamp: F) -> impl Stream<Item = T>
+    where
+        F: FnMut(Self::Item, StreamPosition) -> T,
+    {
+        self.enumerate().map(move |(sequence, item)| {
+            stamp(
+                item,
+                StreamPosition {
+                    sequence: sequence as u64,
+                    total,
+                },
+            )
+        })
+    }
+}
+
+impl<S> RpcStreamExt for S where S: Stream {}
+
+/// Ends a response stream on the handler's terms: cleanly or with an error, along with trailers
+/// either way. Both end up in the stream's EndStreamResponse, error details included.
+///
+/// Yield it from a streaming handler, either directly or as the error of a `Result` item (ex. a
+/// stream of `Result<HelloResponse, StreamEnd>`). Nothing after it is sent.
+#[derive(Clone, Debug, Default)]
+pub struct StreamEnd {
+    pub error: Option<RpcError>,
+    pub trailers: HeaderMap,
+}
+
+impl StreamEnd {
+    /// Ends the stream without an error.
+    pub fn ok() -> Self {
+        Self::default()
+    }
+
+    /// Ends the stream with `error`.
+    pub fn error<E>(error: E) -> Self
+    where
+        E: RpcIntoError,
+    {
+        Self {
+            error: Some(error.rpc_into_error()),
+            trailers: HeaderMap::new(),
+        }
+    }
+
+    pub fn with_trailers(mut self, trailers: HeaderMap) -> Self {
+        self.trailers.extend(trailers);
+        self
+    }
+}
+
+/// One item of a handler's response stream, as the transports see it.
+pub enum RpcStreamItem<M> {
+    Message(M),
+    End(StreamEnd),
+}
+
+impl<M> From<RpcResult<M>> for RpcStreamItem<M> {
+    fn from(res: RpcResult<M>) -> Self {
+        match res {
+            Ok(message) => Self::Message(message),
+            Err(e) => Self::End(StreamEnd::error(e)),
+        }
+    }
+}
@@ -0,0 +1,191 @@
+//! Protobuf text format for unary RPCs, for poking at services with curl, enabled by the
+//! `text-format` feature. Meant for development: JSON is the better choice for anything else.
+//!
+//! `RpcTextFormatLayer` translates requests sent as `application/x-protobuf-text` into binary
+//! Connect calls (using the descriptors of the served services), and their responses back:
+//!
+//! ```ignore
+//! const DESCRIPTOR_SET: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/proto_descriptor.bin"));
+//!
+//! let app = Router::new()
+//!     .rpc(HelloWorldService::say_hello(say_hello))
+//!     .layer(RpcTextFormatLayer::new(DESCRIPTOR_SET)?);
+//! ```
+//!
+//! ```sh
+//! curl -H 'content-type: application/x-protobuf-text' -d 'name: "Alec"' \
+//!     localhost:3030/hello.HelloWorldService/SayHello
+//! ```
+//!
+//! Errors are sent as usual, in JSON. Everything else passes through untouched.
+
+use std::{
+    mem,
+    task::{Context, Poll},
+};
+
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    http::{header, HeaderValue, Request, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures::future::BoxFuture;
+use prost::Message;
+use prost_reflect::{DescriptorError, DescriptorPool, DynamicMessage, MethodDescriptor};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{
+    error::{RpcError, RpcErrorCode},
+    handler::codec::encode_error_response,
+};
+
+pub const TEXT_FORMAT_CONTENT_TYPE: &str = "application/x-protobuf-text";
+
+#[derive(Clone, Debug)]
+pub struct RpcTextFormatLayer {
+    pool: DescriptorPool,
+}
+
+impl RpcTextFormatLayer {
+    /// `descriptor_set` is an encoded `FileDescriptorSet` covering the served services, ex. the
+    /// `proto_descriptor.bin` axum-connect-build writes.
+    pub fn new(descriptor_set: &[u8]) -> Result<Self, DescriptorError> {
+        Ok(Self {
+            pool: DescriptorPool::decode(descriptor_set)?,
+        })
+    }
+
+    /// The unary method served on `path`, ex. `/hello.HelloWorldService/SayHello`. Nested routes
+    /// see their path relative to where they were nested, so only the last two segments count.
+    fn method(&self, path: &str) -> Option<MethodDescriptor> {
+        let mut segments = path.rsplit('/');
+        let method = segments.next()?;
+        let service = segments.next()?;
+
+        self.pool
+            .get_service_by_name(service)?
+            .methods()
+            .find(|m| m.name() == method)
+            .filter(|m| !m.is_client_streaming() && !m.is_server_streaming())
+    }
+}
+
+impl<S> Layer<S> for RpcTextFormatLayer {
+    type Service = RpcTextFormat<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RpcTextFormat {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RpcTextFormat<S> {
+    inner: S,
+    layer: RpcTextFormatLayer,
+}
+
+impl<S> Service<Request<Body>> for RpcTextFormat<S>
+where
+    S: Service<Request<Body>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let content_type = req.headers().get(header::CONTENT_TYPE);
+        let method = content_type
+            .filter(|value| {
+                value
+                    .as_bytes()
+                    .starts_with(TEXT_FORMAT_CONTENT_TYPE.as_bytes())
+            })
+            .and_then(|_| self.layer.method(req.uri().path()));
+
+        let Some(method) = method else {
+            return Box::pin(self.inner.call(req));
+        };
+
+        // The ready service is the one that must be called, leave a fresh clone in its place.
+        let clone = self.inner.clone();
+        let mut inner = mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            let (mut parts, body) = req.into_parts();
+            let request = match read_text(&method, body).await {
+                Ok(request) => request,
+                Err(e) => return Ok(encode_error_response(&e, false, false)),
+            };
+
+            parts.headers.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/proto"),
+            );
+            parts.headers.remove(header::CONTENT_LENGTH);
+
+            let res = inner
+                .call(Request::from_parts(parts, Body::from(request)))
+                .await?;
+            Ok(write_text(&method, res).await)
+        })
+    }
+}
+
+/// Reads a text format request, and encodes it as binary protobuf.
+async fn read_text(method: &MethodDescriptor, body: Body) -> Result<Bytes, RpcError> {
+    let invalid = |message: String| RpcError::new(RpcErrorCode::InvalidArgument, message);
+
+    let body = to_bytes(body, usize::MAX)
+        .await
+        .map_err(|e| invalid(format!("Failed to read request body. {}", e)))?;
+    let text =
+        std::str::from_utf8(&body).map_err(|e| invalid(format!("Request isn't UTF-8. {}", e)))?;
+
+    DynamicMessage::parse_text_format(method.input(), text)
+        .map(|message| message.encode_to_vec().into())
+        .map_err(|e| invalid(format!("Failed to parse text format. {}", e)))
+}
+
+/// Turns a successful binary response into text format. Anything else (errors) is left alone.
+async fn write_text(method: &MethodDescriptor, res: Response) -> Response {
+    let is_binary = res.status() == StatusCode::OK
+        && res
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .is_some_and(|value| value == "application/proto");
+    if !is_binary {
+        return res;
+    }
+
+    let (mut parts, body) = res.into_parts();
+    let text = to_bytes(body, usize::MAX)
+        .await
+        .map_err(|e| RpcError::internal(format!("Failed to read response body. {}", e)))
+        .and_then(|body| {
+            DynamicMessage::decode(method.output(), body)
+                .map_err(|e| RpcError::internal(format!("Failed to decode response. {}", e)))
+        })
+        .map(|message| message.to_text_format());
+
+    match text {
+        Ok(text) => {
+            parts.headers.insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(TEXT_FORMAT_CONTENT_TYPE),
+            );
+            parts.headers.remove(header::CONTENT_LENGTH);
+            parts.headers.remove(header::ETAG);
+            (parts, text).into_response()
+        }
+        Err(e) => encode_error_response(&e, false, false),
+    }
+}
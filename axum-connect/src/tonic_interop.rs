@@ -0,0 +1,210 @@
+//! Bridges to and from `tonic`, enabled by the `tonic` feature, so a codebase can move between
+//! gRPC and Connect one method at a time without duplicating business logic. Both sides use prost
+//! messages, so generated types are shared as-is, as long as both sides are generated against the
+//! same prost. tonic 0.12 is built on prost 0.13, so this feature requires `prost-0-13` (on
+//! `axum-connect-build` too). Under `prost-0-12`, generated types implement a different
+//! `prost::Message` than the one tonic expects.
+//!
+//! Serve an existing tonic service implementation over Connect:
+//!
+//! ```ignore
+//! let greeter = Arc::new(MyGreeter::default());
+//! let app = Router::new().rpc(HelloWorldService::say_hello(tonic_unary(move |request| {
+//!     let greeter = greeter.clone();
+//!     async move { greeter.say_hello(request).await }
+//! })));
+//! ```
+//!
+//! Or go the other way, implementing a tonic service with Connect handlers (extractors included):
+//!
+//! ```ignore
+//! #[tonic::async_trait]
+//! impl Greeter for MyGreeter {
+//!     async fn say_hello(
+//!         &self,
+//!         request: tonic::Request<HelloRequest>,
+//!     ) -> Result<tonic::Response<HelloResponse>, tonic::Status> {
+//!         call_unary(say_hello, HelloWorldService::SAY_HELLO, request, self.state.clone()).await
+//!     }
+//! }
+//! ```
+//!
+//! Request headers and gRPC metadata are copied across in both directions, and so are response
+//! headers (see `RpcIntoResponseParts`) and response metadata.
+
+use std::pin::Pin;
+
+use futures::{future, Future, Stream, StreamExt};
+use prost::Message;
+use serde::{de::DeserializeOwned, Serialize};
+use tonic::metadata::MetadataMap;
+
+use crate::{
+    descriptor::MethodDescriptor,
+    error::{RpcError, RpcIntoError},
+    response::{RpcResponseParts, RpcResult},
+    service::{RpcContext, RpcService, RpcStreamReply, RpcUnaryReply, ServerStreaming, Unary},
+    stream::RpcStreamItem,
+};
+
+/// A tonic unary method, usable anywhere a Connect unary handler is. See `tonic_unary`.
+#[derive(Clone)]
+pub struct TonicUnary<F>(F);
+
+/// A tonic server-streaming method, usable anywhere a Connect streaming handler is. See
+/// `tonic_server_streaming`.
+#[derive(Clone)]
+pub struct TonicServerStreaming<F>(F);
+
+/// Wraps a function calling a tonic unary method (usually a closure calling into a tonic service
+/// implementation) so it can be registered as a Connect handler.
+pub fn tonic_unary<F>(f: F) -> TonicUnary<F> {
+    TonicUnary(f)
+}
+
+/// Same as `tonic_unary`, for server-streaming methods.
+pub fn tonic_server_streaming<F>(f: F) -> TonicServerStreaming<F> {
+    TonicServerStreaming(f)
+}
+
+fn tonic_request<M>(ctx: RpcContext, message: M) -> tonic::Request<M> {
+    let mut request = tonic::Request::new(message);
+    *request.metadata_mut() = MetadataMap::from_headers(ctx.parts.headers);
+    request
+}
+
+impl<TMReq, TMRes, TState, F, Fut> RpcService<TMReq, TMRes, Unary, TonicUnary<()>, TState>
+    for TonicUnary<F>
+where
+    TMReq: Send + 'static,
+    TMRes: Send + 'static,
+    F: FnOnce(tonic::Request<TMReq>) -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = Result<tonic::Response<TMRes>, tonic::Status>> + Send + 'static,
+{
+    type Reply = RpcUnaryReply<TMRes>;
+
+    fn call_rpc(
+        self,
+        ctx: RpcContext,
+        message: RpcResult<TMReq>,
+        _state: TState,
+    ) -> Pin<Box<dyn Future<Output = RpcResult<Self::Reply>> + Send>> {
+        let request = message.map(|message| tonic_request(ctx, message));
+        let f = self.0;
+
+        Box::pin(async move {
+            let response = f(request?).await.map_err(RpcIntoError::rpc_into_error)?;
+            let (metadata, message, _) = response.into_parts();
+            if metadata.is_empty() {
+                return Ok(RpcUnaryReply::Message(message));
+            }
+
+            let parts = RpcResponseParts {
+                headers: metadata.into_headers(),
+                ..Default::default()
+            };
+            Ok(RpcUnaryReply::WithParts(Ok(message), parts))
+        })
+    }
+}
+
+impl<TMReq, TMRes, TState, F, Fut, St>
+    RpcService<TMReq, TMRes, ServerStreaming, TonicServerStreaming<()>, TState>
+    for TonicServerStreaming<F>
+where
+    TMReq: Send + 'static,
+    TMRes: Send + 'static,
+    F: FnOnce(tonic::Request<TMReq>) -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = Result<tonic::Response<St>, tonic::Status>> + Send + 'static,
+    St: Stream<Item = Result<TMRes, tonic::Status>> + Send + 'static,
+{
+    type Reply = RpcStreamReply<TMRes>;
+
+    fn call_rpc(
+        self,
+        ctx: RpcContext,
+        message: RpcResult<TMReq>,
+        _state: TState,
+    ) -> Pin<Box<dyn Future<Output = RpcResult<Self::Reply>> + Send>> {
+        let request = message.map(|message| tonic_request(ctx, message));
+        let f = self.0;
+
+        Box::pin(async move {
+            let stream = f(request?).await.map_err(RpcIntoError::rpc_into_error)?;
+            let stream = stream
+                .into_inner()
+                .map(|item| RpcStreamItem::from(item.map_err(RpcIntoError::rpc_into_error)));
+            Ok(Box::pin(stream) as RpcStreamReply<TMRes>)
+        })
+    }
+}
+
+fn rpc_context<M>(method: MethodDescriptor, request: tonic::Request<M>) -> (RpcContext, M) {
+    let mut ctx = RpcContext::new(method);
+    ctx.parts.headers = request.metadata().clone().into_headers();
+    (ctx, request.into_inner())
+}
+
+/// Calls a Connect unary handler from a tonic service implementation. `method` is the generated
+/// descriptor const of the method being implemented. Extractors see the gRPC metadata as request
+/// headers.
+pub async fn call_unary<H, TMReq, TMRes, TUid, TState>(
+    handler: H,
+    method: MethodDescriptor,
+    request: tonic::Request<TMReq>,
+    state: TState,
+) -> Result<tonic::Response<TMRes>, tonic::Status>
+where
+    H: RpcService<TMReq, TMRes, Unary, TUid, TState, Reply = RpcUnaryReply<TMRes>>,
+    TMReq: Message + DeserializeOwned + Default + Send + 'static,
+    TMRes: Message + Serialize + Send + 'static,
+{
+    let (ctx, message) = rpc_context(method, request);
+
+    match handler.call_rpc(ctx, Ok(message), state).await? {
+        RpcUnaryReply::Message(res) => Ok(tonic::Response::new(res)),
+        RpcUnaryReply::WithParts(res, parts) => {
+            let mut response = tonic::Response::new(res?);
+            *response.metadata_mut() = MetadataMap::from_headers(parts.headers);
+            Ok(response)
+        }
+        RpcUnaryReply::Raw(_) => {
+            Err(RpcError::unimplemented("RpcRawResponse is only supported over HTTP").into())
+        }
+    }
+}
+
+/// Same as `call_unary`, for server-streaming handlers.
+pub async fn call_server_streaming<H, TMReq, TMRes, TUid, TState>(
+    handler: H,
+    method: MethodDescriptor,
+    request: tonic::Request<TMReq>,
+    state: TState,
+) -> Result<
+    tonic::Response<Pin<Box<dyn Stream<Item = Result<TMRes, tonic::Status>> + Send>>>,
+    tonic::Status,
+>
+where
+    H: RpcService<TMReq, TMRes, ServerStreaming, TUid, TState, Reply = RpcStreamReply<TMRes>>,
+    TMReq: Message + DeserializeOwned + Default + Send + 'static,
+    TMRes: Message + Serialize + Send + 'static,
+{
+    let (ctx, message) = rpc_context(method, request);
+
+    // gRPC trailers are tonic's business, only the error of a `StreamEnd` makes it across.
+    let stream = handler
+        .call_rpc(ctx, Ok(message), state)
+        .await?
+        .scan(false, |ended, item| {
+            let item = match item {
+                _ if *ended => None,
+                RpcStreamItem::Message(message) => Some(Ok(message)),
+                RpcStreamItem::End(end) => {
+                    *ended = true;
+                    end.error.map(|e| Err(tonic::Status::from(e)))
+                }
+            };
+            future::ready(item)
+        });
+    Ok(tonic::Response::new(Box::pin(stream)))
+}
@@ -0,0 +1,92 @@
+//! Per-RPC `tracing` spans, enabled by the `tracing` feature.
+//!
+//! Every RPC gets an `rpc` span carrying the (OpenTelemetry style) `rpc.system`, `rpc.service`
+//! and `rpc.method` fields, plus the negotiated codec and the final Connect status once known.
+//! Errors are also logged as events in that span: server-side failures at `ERROR`, anything the
+//! client is likely responsible for at `INFO`.
+//!
+//! The span also carries the fields `tracing-opentelemetry` maps onto OpenTelemetry span data
+//! (`otel.name`, `otel.kind`, `otel.status_code`) and the `rpc.connect_rpc.error_code` attribute
+//! from the OTel RPC semantic conventions. With the `opentelemetry` feature, the remote parent
+//! context (`traceparent`, `baggage`, ...) is also extracted from the request headers with the
+//! globally registered propagator and set as the span's parent.
+
+use axum::{body::Body, http::Request};
+use tracing::{field::Empty, Level, Span};
+
+use crate::{
+    descriptor::MethodDescriptor,
+    error::{RpcError, RpcErrorCode},
+};
+
+pub(crate) fn request_span(req: &Request<Body>) -> Span {
+    let (service, method, path) = match req.extensions().get::<MethodDescriptor>() {
+        Some(descriptor) => (descriptor.service, descriptor.method, descriptor.path),
+        None => ("", "", ""),
+    };
+
+    let span = tracing::info_span!(
+        "rpc",
+        otel.name = path.trim_start_matches('/'),
+        otel.kind = "server",
+        otel.status_code = Empty,
+        rpc.system = "connect_rpc",
+        rpc.service = service,
+        rpc.method = method,
+        rpc.connect_rpc.error_code = Empty,
+        rpc.connect.codec = Empty,
+        rpc.connect.status = Empty,
+    );
+
+    #[cfg(feature = "opentelemetry")]
+    {
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+        let parent = opentelemetry::global::get_text_map_propagator(|propagator| {
+            propagator.extract(&HeaderExtractor(req.headers()))
+        });
+        span.set_parent(parent);
+    }
+
+    span
+}
+
+#[cfg(feature = "opentelemetry")]
+struct HeaderExtractor<'a>(&'a axum::http::HeaderMap);
+
+#[cfg(feature = "opentelemetry")]
+impl<'a> opentelemetry::propagation::Extractor for HeaderExtractor<'a> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+pub(crate) fn record_codec(span: &Span, binary: bool) {
+    span.record("rpc.connect.codec", if binary { "proto" } else { "json" });
+}
+
+pub(crate) fn record_ok(span: &Span) {
+    span.record("rpc.connect.status", "ok");
+}
+
+pub(crate) fn record_error(span: &Span, e: &RpcError) {
+    span.record("rpc.connect.status", e.code.as_str());
+    span.record("rpc.connect_rpc.error_code", e.code.as_str());
+    span.record("otel.status_code", "ERROR");
+
+    span.in_scope(|| match e.code {
+        RpcErrorCode::Unknown
+        | RpcErrorCode::Internal
+        | RpcErrorCode::Unavailable
+        | RpcErrorCode::DataLoss => {
+            tracing::event!(Level::ERROR, error = %e, "RPC failed");
+        }
+        _ => {
+            tracing::event!(Level::INFO, error = %e, "RPC failed");
+        }
+    });
+}
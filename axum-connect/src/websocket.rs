@@ -0,0 +1,297 @@
+//! Client and bidi streaming over WebSockets, enabled by the `websocket` feature. Browsers can't
+//! stream a request body with `fetch`, let alone read the response while still writing it, so
+//! Connect's HTTP streaming isn't an option there. A WebSocket is.
+//!
+//! `bidi_streaming` and `client_streaming` register a WebSocket endpoint (a `GET` upgrade) on the
+//! path of a generated method descriptor. They can sit next to the method's own registration:
+//!
+//! ```ignore
+//! let app = Router::new().rpc(websocket::bidi_streaming(
+//!     ChatService::CHAT,
+//!     |_ctx, mut requests: RpcRequestStream<ChatRequest>| async move {
+//!         Ok(async_stream::stream! {
+//!             while let Some(request) = requests.next().await {
+//!                 yield request.map(|request| ChatResponse { text: request.text });
+//!             }
+//!         })
+//!     },
+//! ));
+//! ```
+//!
+//! # Framing
+//!
+//! - The codec is picked with the WebSocket subprotocol: `connect+proto` for binary protobuf,
+//!   `connect+json` (or no subprotocol at all) for JSON.
+//! - Every WebSocket message is a binary message carrying exactly one Connect envelope (a flags
+//!   byte, a big-endian `u32` size, then the message), the same envelopes as Connect streaming
+//!   over HTTP. Compression isn't supported.
+//! - The client sends its request messages, then half-closes its side with an envelope flagged
+//!   end-of-stream (`0x02`, the payload is ignored). Closing the WebSocket also ends the requests.
+//! - The server sends its response messages, then an EndStreamResponse envelope (flagged `0x02`,
+//!   always JSON) with the error (if any) and trailers, then closes the WebSocket.
+//!
+//! Browsers can't set headers on a WebSocket, so the call has no `connect-timeout-ms`. The
+//! server's own timeout (see `RpcConfig::timeout`) still applies.
+
+use std::{
+    panic::AssertUnwindSafe,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use async_stream::stream;
+use axum::{
+    body::Body,
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        FromRequestParts,
+    },
+    http::{request, Request},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use futures::{future, stream::SplitStream, Future, FutureExt, SinkExt, Stream, StreamExt};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    auth::check_scopes,
+    descriptor::{record_registration, MethodDescriptor, MethodKind},
+    error::RpcError,
+    handler::{
+        call_handler,
+        codec::{decode_envelope, decode_message, ResponseEncoder},
+        recover_panic, ResponseTags,
+    },
+    response::RpcResult,
+    router::RpcRouter,
+    service::RpcContext,
+    stream::{RpcStreamItem, StreamEnd},
+};
+
+/// The subprotocol of calls using the binary protobuf codec.
+pub const PROTOCOL_PROTO: &str = "connect+proto";
+/// The subprotocol of calls using the JSON codec, the default.
+pub const PROTOCOL_JSON: &str = "connect+json";
+
+/// The request messages of a WebSocket call, as the client sends them. Ends once the client
+/// half-closes (or closes) the WebSocket. Messages that fail to decode are yielded as errors.
+pub struct RpcRequestStream<M> {
+    inner: Pin<Box<dyn Stream<Item = RpcResult<M>> + Send>>,
+}
+
+impl<M> Stream for RpcRequestStream<M> {
+    type Item = RpcResult<M>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.as_mut().poll_next(cx)
+    }
+}
+
+/// Returns a registration (for `RpcRouterExt::rpc`) serving the bidi streaming `method` over
+/// WebSockets with `handler`, which answers with a stream of response messages.
+///
+/// # Panics
+///
+/// If `method` isn't a bidi streaming method.
+pub fn bidi_streaming<S, TMReq, TMRes, H, F, St>(
+    method: MethodDescriptor,
+    handler: H,
+) -> impl FnOnce(Router<S>) -> RpcRouter<S>
+where
+    S: Clone + Send + Sync + 'static,
+    TMReq: prost::Message + DeserializeOwned + Default + Send + 'static,
+    TMRes: prost::Message + Serialize + Send + 'static,
+    H: FnOnce(RpcContext, RpcRequestStream<TMReq>) -> F + Clone + Send + Sync + 'static,
+    F: Future<Output = RpcResult<St>> + Send + 'static,
+    St: Stream<Item = RpcResult<TMRes>> + Send + 'static,
+{
+    assert!(
+        method.kind == MethodKind::BidiStreaming,
+        "{} is not a bidi streaming method",
+        method.path
+    );
+
+    register(method, handler)
+}
+
+/// Returns a registration (for `RpcRouterExt::rpc`) serving the client streaming `method` over
+/// WebSockets with `handler`, which answers with a single response message once it's done reading
+/// the requests.
+///
+/// # Panics
+///
+/// If `method` isn't a client streaming method.
+pub fn client_streaming<S, TMReq, TMRes, H, F>(
+    method: MethodDescriptor,
+    handler: H,
+) -> impl FnOnce(Router<S>) -> RpcRouter<S>
+where
+    S: Clone + Send + Sync + 'static,
+    TMReq: prost::Message + DeserializeOwned + Default + Send + 'static,
+    TMRes: prost::Message + Serialize + Send + 'static,
+    H: FnOnce(RpcContext, RpcRequestStream<TMReq>) -> F + Clone + Send + Sync + 'static,
+    F: Future<Output = RpcResult<TMRes>> + Send + 'static,
+{
+    assert!(
+        method.kind == MethodKind::ClientStreaming,
+        "{} is not a client streaming method",
+        method.path
+    );
+
+    register(method, move |ctx, requests| {
+        handler(ctx, requests)
+            .map(|res| res.map(|message| futures::stream::once(future::ready(Ok(message)))))
+    })
+}
+
+fn register<S, TMReq, TMRes, H, F, St>(
+    method: MethodDescriptor,
+    handler: H,
+) -> impl FnOnce(Router<S>) -> RpcRouter<S>
+where
+    S: Clone + Send + Sync + 'static,
+    TMReq: prost::Message + DeserializeOwned + Default + Send + 'static,
+    TMRes: prost::Message + Serialize + Send + 'static,
+    H: FnOnce(RpcContext, RpcRequestStream<TMReq>) -> F + Clone + Send + Sync + 'static,
+    F: Future<Output = RpcResult<St>> + Send + 'static,
+    St: Stream<Item = RpcResult<TMRes>> + Send + 'static,
+{
+    move |router: Router<S>| {
+        record_registration(method);
+        // Only a GET route, so it merges with the method's POST route (and its fallback), if
+        // that's registered too.
+        router.route(
+            method.path,
+            get(move |mut request: Request<Body>| async move {
+                request.extensions_mut().insert(method);
+                upgrade(handler, request).await
+            }),
+        )
+    }
+}
+
+async fn upgrade<TMReq, TMRes, H, F, St>(handler: H, req: Request<Body>) -> Response
+where
+    TMReq: prost::Message + DeserializeOwned + Default + Send + 'static,
+    TMRes: prost::Message + Serialize + Send + 'static,
+    H: FnOnce(RpcContext, RpcRequestStream<TMReq>) -> F + Send + 'static,
+    F: Future<Output = RpcResult<St>> + Send + 'static,
+    St: Stream<Item = RpcResult<TMRes>> + Send + 'static,
+{
+    #[cfg(feature = "tracing")]
+    let span = crate::trace::request_span(&req);
+    let tags = ResponseTags::new(&req);
+
+    let (mut parts, _) = req.into_parts();
+    if let Err(e) = check_scopes(&mut parts).await {
+        return tags.apply(ResponseEncoder::new(&parts, false, false).encode_error_response(e));
+    }
+
+    let ws = match WebSocketUpgrade::from_request_parts(&mut parts, &()).await {
+        Ok(ws) => ws.protocols([PROTOCOL_PROTO, PROTOCOL_JSON]),
+        Err(rejection) => return tags.apply(rejection.into_response()),
+    };
+
+    let binary = ws
+        .selected_protocol()
+        .is_some_and(|protocol| protocol == PROTOCOL_PROTO);
+    let encoder = ResponseEncoder::new(&parts, binary, true);
+    protocol_trace!(encoder.method.as_ref(), binary, "upgrading to a WebSocket");
+
+    let res = ws.on_upgrade(move |socket| {
+        let fut = serve(handler, socket, parts, encoder);
+
+        #[cfg(feature = "tracing")]
+        let fut = tracing::Instrument::instrument(fut, span);
+
+        fut
+    });
+
+    tags.apply(res)
+}
+
+/// Runs the call over an upgraded WebSocket, see the module docs for the framing.
+async fn serve<TMReq, TMRes, H, F, St>(
+    handler: H,
+    socket: WebSocket,
+    parts: request::Parts,
+    encoder: ResponseEncoder,
+) where
+    TMReq: prost::Message + DeserializeOwned + Default + Send + 'static,
+    TMRes: prost::Message + Serialize + Send + 'static,
+    H: FnOnce(RpcContext, RpcRequestStream<TMReq>) -> F,
+    F: Future<Output = RpcResult<St>>,
+    St: Stream<Item = RpcResult<TMRes>> + Send + 'static,
+{
+    let (mut sender, receiver) = socket.split();
+    let requests = RpcRequestStream {
+        inner: Box::pin(read_requests(receiver, encoder.binary)),
+    };
+
+    let ctx = RpcContext::from_parts(parts);
+    let config = ctx.config();
+    let handler = async move { handler(ctx, requests).await };
+    let stream = encoder
+        .before_deadline(call_handler(&config, handler))
+        .await
+        .and_then(|res| res)
+        .and_then(|res| res);
+
+    let items = match stream {
+        Ok(stream) => StreamExt::catch_unwind(AssertUnwindSafe(stream))
+            .map(move |item| match item {
+                Ok(item) => RpcStreamItem::from(item),
+                Err(panic) => RpcStreamItem::End(StreamEnd::error(recover_panic(&config, panic))),
+            })
+            .left_stream(),
+        Err(e) => futures::stream::once(future::ready(RpcStreamItem::End(StreamEnd::error(e))))
+            .right_stream(),
+    };
+
+    let mut frames = Box::pin(encoder.encode_frames(items));
+    while let Some(frame) = frames.next().await {
+        if sender.send(Message::Binary(frame)).await.is_err() {
+            // The client is gone, there's no one left to tell.
+            return;
+        }
+    }
+    let _ = sender.send(Message::Close(None)).await;
+}
+
+/// The request messages read off of the WebSocket, until the client half-closes it.
+fn read_requests<M>(
+    mut receiver: SplitStream<WebSocket>,
+    binary: bool,
+) -> impl Stream<Item = RpcResult<M>> + Send + 'static
+where
+    M: prost::Message + DeserializeOwned + Default + Send + 'static,
+{
+    stream! {
+        while let Some(message) = receiver.next().await {
+            let frame = match message {
+                Ok(Message::Binary(frame)) => frame,
+                Ok(Message::Text(_)) => {
+                    yield Err(RpcError::invalid_argument(
+                        "Expected binary WebSocket messages, each carrying an envelope",
+                    ));
+                    break;
+                }
+                Ok(Message::Close(_)) => break,
+                // Pings and pongs are answered by axum.
+                Ok(_) => continue,
+                Err(e) => {
+                    yield Err(RpcError::canceled(format!("WebSocket failed. {}", e)));
+                    break;
+                }
+            };
+
+            // The end-of-stream flag, the client is done sending.
+            if frame.first().is_some_and(|flags| flags & 0x2 != 0) {
+                break;
+            }
+
+            yield decode_envelope(frame).and_then(|bytes| decode_message(bytes, binary));
+        }
+    }
+}
@@ -0,0 +1,333 @@
+//! Conversions between the well-known types (which are generated as `axum_connect::pbjson_types`)
+//! and the types handlers actually work with:
+//!
+//! - `Timestamp` and `Duration` to and from `std::time` always, `chrono` and `time` with the
+//!   features of the same name.
+//! - `Struct`, `Value` and `ListValue` to and from `serde_json::Value`.
+//! - Any generated message to and from `Any`, with `pack_any` and `unpack_any`.
+//!
+//! These are extension traits rather than `From` impls because neither side of the conversion is
+//! defined in this crate. Conversions that can fail (a timestamp out of range, a negative duration
+//! for `std`) fail with `InvalidArgument`, so handlers can `?` them on request fields:
+//!
+//! ```ignore
+//! let deadline = request.deadline.unwrap_or_default().to_system_time()?;
+//! let response = EventResponse { at: Some(Timestamp::from_system_time(SystemTime::now())) };
+//! let attributes = Struct::from_json(serde_json::json!({ "color": "red" }))?;
+//! ```
+
+use std::time::{self, SystemTime, UNIX_EPOCH};
+
+use pbjson_types::{value::Kind, Any, Duration, ListValue, Struct, Timestamp, Value};
+use prost::{Message, Name};
+
+use crate::{
+    error::{RpcError, RpcErrorCode},
+    response::RpcResult,
+};
+
+const NANOS_PER_SECOND: i32 = 1_000_000_000;
+
+fn invalid(message: &str) -> RpcError {
+    RpcError::new(RpcErrorCode::InvalidArgument, message.to_string())
+}
+
+pub trait RpcTimestampExt: Sized {
+    fn from_system_time(time: SystemTime) -> Self;
+
+    fn to_system_time(&self) -> RpcResult<SystemTime>;
+
+    #[cfg(feature = "chrono")]
+    fn from_chrono(time: chrono::DateTime<chrono::Utc>) -> Self;
+
+    #[cfg(feature = "chrono")]
+    fn to_chrono(&self) -> RpcResult<chrono::DateTime<chrono::Utc>>;
+
+    #[cfg(feature = "time")]
+    fn from_offset_date_time(time: ::time::OffsetDateTime) -> Self;
+
+    #[cfg(feature = "time")]
+    fn to_offset_date_time(&self) -> RpcResult<::time::OffsetDateTime>;
+}
+
+impl RpcTimestampExt for Timestamp {
+    fn from_system_time(time: SystemTime) -> Self {
+        let (seconds, nanos) = match time.duration_since(UNIX_EPOCH) {
+            Ok(since) => (since.as_secs() as i64, since.subsec_nanos() as i32),
+            Err(e) => {
+                // Before the epoch. Nanos always count forward, so borrow a second for them.
+                let before = e.duration();
+                match before.subsec_nanos() as i32 {
+                    0 => (-(before.as_secs() as i64), 0),
+                    nanos => (-(before.as_secs() as i64) - 1, NANOS_PER_SECOND - nanos),
+                }
+            }
+        };
+
+        Self { seconds, nanos }
+    }
+
+    fn to_system_time(&self) -> RpcResult<SystemTime> {
+        let nanos = checked_timestamp_nanos(self)?;
+        let offset = time::Duration::from_secs(self.seconds.unsigned_abs());
+        let time = if self.seconds >= 0 {
+            UNIX_EPOCH.checked_add(offset)
+        } else {
+            UNIX_EPOCH.checked_sub(offset)
+        };
+
+        time.and_then(|time| time.checked_add(time::Duration::from_nanos(nanos as u64)))
+            .ok_or_else(|| invalid("Timestamp out of range"))
+    }
+
+    #[cfg(feature = "chrono")]
+    fn from_chrono(time: chrono::DateTime<chrono::Utc>) -> Self {
+        Self {
+            seconds: time.timestamp(),
+            nanos: time.timestamp_subsec_nanos() as i32,
+        }
+    }
+
+    #[cfg(feature = "chrono")]
+    fn to_chrono(&self) -> RpcResult<chrono::DateTime<chrono::Utc>> {
+        let nanos = checked_timestamp_nanos(self)?;
+        chrono::DateTime::from_timestamp(self.seconds, nanos as u32)
+            .ok_or_else(|| invalid("Timestamp out of range"))
+    }
+
+    #[cfg(feature = "time")]
+    fn from_offset_date_time(time: ::time::OffsetDateTime) -> Self {
+        Self {
+            seconds: time.unix_timestamp(),
+            nanos: time.nanosecond() as i32,
+        }
+    }
+
+    #[cfg(feature = "time")]
+    fn to_offset_date_time(&self) -> RpcResult<::time::OffsetDateTime> {
+        let nanos = checked_timestamp_nanos(self)?;
+        ::time::OffsetDateTime::from_unix_timestamp_nanos(
+            self.seconds as i128 * NANOS_PER_SECOND as i128 + nanos as i128,
+        )
+        .map_err(|_| invalid("Timestamp out of range"))
+    }
+}
+
+fn checked_timestamp_nanos(timestamp: &Timestamp) -> RpcResult<i32> {
+    if (0..NANOS_PER_SECOND).contains(&timestamp.nanos) {
+        Ok(timestamp.nanos)
+    } else {
+        Err(invalid("Timestamp nanos must be in [0, 1e9)"))
+    }
+}
+
+pub trait RpcDurationExt: Sized {
+    /// Saturates at `i64::MAX` seconds, which is well past anything protobuf considers valid.
+    fn from_std(duration: time::Duration) -> Self;
+
+    /// Fails for negative durations, which `std` can't represent.
+    fn to_std(&self) -> RpcResult<time::Duration>;
+
+    #[cfg(feature = "chrono")]
+    fn from_chrono(duration: chrono::TimeDelta) -> Self;
+
+    #[cfg(feature = "chrono")]
+    fn to_chrono(&self) -> RpcResult<chrono::TimeDelta>;
+
+    #[cfg(feature = "time")]
+    fn from_time_duration(duration: ::time::Duration) -> Self;
+
+    #[cfg(feature = "time")]
+    fn to_time_duration(&self) -> RpcResult<::time::Duration>;
+}
+
+impl RpcDurationExt for Duration {
+    fn from_std(duration: time::Duration) -> Self {
+        Self {
+            seconds: i64::try_from(duration.as_secs()).unwrap_or(i64::MAX),
+            nanos: duration.subsec_nanos() as i32,
+        }
+    }
+
+    fn to_std(&self) -> RpcResult<time::Duration> {
+        let nanos = checked_duration_nanos(self)?;
+        if self.seconds < 0 || nanos < 0 {
+            return Err(invalid("Duration must not be negative"));
+        }
+
+        Ok(time::Duration::new(self.seconds as u64, nanos as u32))
+    }
+
+    #[cfg(feature = "chrono")]
+    fn from_chrono(duration: chrono::TimeDelta) -> Self {
+        Self {
+            seconds: duration.num_seconds(),
+            nanos: duration.subsec_nanos(),
+        }
+    }
+
+    #[cfg(feature = "chrono")]
+    fn to_chrono(&self) -> RpcResult<chrono::TimeDelta> {
+        let nanos = checked_duration_nanos(self)?;
+        chrono::TimeDelta::try_seconds(self.seconds)
+            .and_then(|seconds| seconds.checked_add(&chrono::TimeDelta::nanoseconds(nanos as i64)))
+            .ok_or_else(|| invalid("Duration out of range"))
+    }
+
+    #[cfg(feature = "time")]
+    fn from_time_duration(duration: ::time::Duration) -> Self {
+        Self {
+            seconds: duration.whole_seconds(),
+            nanos: duration.subsec_nanoseconds(),
+        }
+    }
+
+    #[cfg(feature = "time")]
+    fn to_time_duration(&self) -> RpcResult<::time::Duration> {
+        let nanos = checked_duration_nanos(self)?;
+        Ok(::time::Duration::new(self.seconds, nanos))
+    }
+}
+
+/// Duration nanos must be within a second, with the same sign as the seconds.
+fn checked_duration_nanos(duration: &Duration) -> RpcResult<i32> {
+    let nanos = duration.nanos;
+    let in_range = nanos.abs() < NANOS_PER_SECOND;
+    let same_sign = nanos == 0 || duration.seconds == 0 || (nanos > 0) == (duration.seconds > 0);
+
+    if in_range && same_sign {
+        Ok(nanos)
+    } else {
+        Err(invalid(
+            "Duration nanos must be within a second, with the same sign as the seconds",
+        ))
+    }
+}
+
+pub trait RpcJsonExt: Sized {
+    /// Fails (with `InvalidArgument`) if `json` isn't the right shape: an object for a `Struct`,
+    /// an array for a `ListValue`. Any JSON is a `Value`.
+    fn from_json(json: serde_json::Value) -> RpcResult<Self>;
+
+    /// Numbers that JSON can't represent (NaN and infinities) become `null`, same as serializing
+    /// the message with serde.
+    fn to_json(&self) -> serde_json::Value;
+}
+
+impl RpcJsonExt for Value {
+    fn from_json(json: serde_json::Value) -> RpcResult<Self> {
+        let kind = match json {
+            serde_json::Value::Null => Kind::NullValue(0),
+            serde_json::Value::Bool(value) => Kind::BoolValue(value),
+            serde_json::Value::Number(value) => {
+                Kind::NumberValue(value.as_f64().unwrap_or_default())
+            }
+            serde_json::Value::String(value) => Kind::StringValue(value),
+            serde_json::Value::Array(values) => Kind::ListValue(list_from_json(values)),
+            serde_json::Value::Object(fields) => Kind::StructValue(struct_from_json(fields)),
+        };
+
+        Ok(Self { kind: Some(kind) })
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        match &self.kind {
+            None | Some(Kind::NullValue(_)) => serde_json::Value::Null,
+            Some(Kind::BoolValue(value)) => serde_json::Value::Bool(*value),
+            Some(Kind::NumberValue(value)) => serde_json::Number::from_f64(*value)
+                .map(serde_json::Value::Number)
+                .unwrap_or_default(),
+            Some(Kind::StringValue(value)) => serde_json::Value::String(value.clone()),
+            Some(Kind::ListValue(list)) => list.to_json(),
+            Some(Kind::StructValue(fields)) => fields.to_json(),
+        }
+    }
+}
+
+impl RpcJsonExt for Struct {
+    fn from_json(json: serde_json::Value) -> RpcResult<Self> {
+        match json {
+            serde_json::Value::Object(fields) => Ok(struct_from_json(fields)),
+            _ => Err(invalid("Expected a JSON object for a Struct")),
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::Value::Object(
+            self.fields
+                .iter()
+                .map(|(name, value)| (name.clone(), value.to_json()))
+                .collect(),
+        )
+    }
+}
+
+impl RpcJsonExt for ListValue {
+    fn from_json(json: serde_json::Value) -> RpcResult<Self> {
+        match json {
+            serde_json::Value::Array(values) => Ok(list_from_json(values)),
+            _ => Err(invalid("Expected a JSON array for a ListValue")),
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::Value::Array(self.values.iter().map(RpcJsonExt::to_json).collect())
+    }
+}
+
+fn struct_from_json(fields: serde_json::Map<String, serde_json::Value>) -> Struct {
+    Struct {
+        fields: fields
+            .into_iter()
+            .map(|(name, value)| (name, json_to_value(value)))
+            .collect(),
+    }
+}
+
+fn list_from_json(values: Vec<serde_json::Value>) -> ListValue {
+    ListValue {
+        values: values.into_iter().map(json_to_value).collect(),
+    }
+}
+
+fn json_to_value(json: serde_json::Value) -> Value {
+    // Only `Struct` and `ListValue` can fail, and only at the top level.
+    Value::from_json(json).unwrap_or_default()
+}
+
+/// Packs `message` into an `Any`, under its `type.googleapis.com/` type URL.
+pub fn pack_any<M>(message: &M) -> Any
+where
+    M: Message + Name,
+{
+    Any {
+        type_url: M::type_url(),
+        value: message.encode_to_vec().into(),
+    }
+}
+
+/// Unpacks an `M` from `any`. Fails (with `InvalidArgument`) if `any` holds some other message,
+/// whatever the domain of its type URL.
+pub fn unpack_any<M>(any: &Any) -> RpcResult<M>
+where
+    M: Message + Name + Default,
+{
+    let type_name = any.type_url.rsplit('/').next().unwrap_or_default();
+    if type_name != M::full_name() {
+        return Err(RpcError::new(
+            RpcErrorCode::InvalidArgument,
+            format!(
+                "Expected an Any of {}, got {}",
+                M::full_name(),
+                any.type_url
+            ),
+        ));
+    }
+
+    M::decode(&any.value[..]).map_err(|e| {
+        RpcError::new(
+            RpcErrorCode::InvalidArgument,
+            format!("Failed to decode {}. {}", M::full_name(), e),
+        )
+    })
+}
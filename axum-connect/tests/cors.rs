@@ -0,0 +1,92 @@
+//! CORS preflights on RPC routes: allowed origins get the CORS headers, others an error saying
+//! which origin was rejected.
+
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    http::{header, Method, Request, StatusCode},
+    response::Response,
+    Router,
+};
+use axum_connect::{
+    config::RpcConfig,
+    descriptor::{MethodDescriptor, MethodKind},
+    raw::{raw_unary, RpcRawMessage},
+    router::RpcRouterExt,
+    service::RpcContext,
+};
+use futures::executor::block_on;
+use tower::ServiceExt;
+
+const PING: MethodDescriptor = MethodDescriptor {
+    service: "test.PingService",
+    method: "Ping",
+    path: "/test.PingService/Ping",
+    kind: MethodKind::Unary,
+    deprecated: false,
+    idempotent: true,
+    sunset: None,
+    scopes: &[],
+};
+
+const ALLOWED: &str = "https://app.example.com";
+
+fn router() -> Router {
+    Router::new()
+        .rpc(raw_unary(PING, |_: RpcContext, _: RpcRawMessage| async {
+            Ok(Bytes::new())
+        }))
+        .rpc_config(RpcConfig::new().cors_origins([ALLOWED]))
+}
+
+fn preflight(origin: &str) -> Response {
+    let req = Request::builder()
+        .method(Method::OPTIONS)
+        .uri(PING.path)
+        .header(header::ORIGIN, origin)
+        .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+        .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "content-type")
+        .body(Body::empty())
+        .unwrap();
+
+    // Routers are infallible.
+    block_on(router().oneshot(req)).unwrap()
+}
+
+#[test]
+fn allowed_origin_gets_cors_headers() {
+    let res = preflight(ALLOWED);
+
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    assert_eq!(res.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], ALLOWED);
+}
+
+#[test]
+fn other_origin_is_told_it_was_rejected() {
+    let res = preflight("https://evil.example.com");
+
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    assert!(!res
+        .headers()
+        .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+
+    let body = block_on(to_bytes(res.into_body(), usize::MAX)).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["code"], "permission_denied");
+    assert!(json["message"]
+        .as_str()
+        .unwrap()
+        .contains("https://evil.example.com"));
+}
+
+#[test]
+fn plain_options_request_is_answered_with_the_allowed_methods() {
+    let req = Request::builder()
+        .method(Method::OPTIONS)
+        .uri(PING.path)
+        .body(Body::empty())
+        .unwrap();
+    let res = block_on(router().oneshot(req)).unwrap();
+
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    assert_eq!(res.headers()[header::ALLOW], "POST, OPTIONS");
+}
@@ -0,0 +1,199 @@
+//! Errors, whatever their code, message and details, go out exactly as the Connect spec says:
+//! https://connectrpc.com/docs/protocol/#error-end-stream
+
+use axum::{
+    body::{to_bytes, Body},
+    http::{header, Request, StatusCode},
+    response::Response,
+    routing::post,
+    Router,
+};
+use axum_connect::{
+    error::{RpcError, RpcErrorCode, RpcErrorDetail},
+    handler::{RpcHandlerStream, RpcHandlerUnary},
+    pbjson_types::{Empty, StringValue},
+    prost::Message,
+};
+use base64::{engine::general_purpose, Engine as _};
+use futures::{executor::block_on, stream};
+use proptest::prelude::*;
+use serde_json::Value;
+use tower::ServiceExt;
+
+/// Every code, with its wire name and the HTTP status of unary errors, straight from the spec.
+const CODES: [(RpcErrorCode, &str, u16); 16] = [
+    (RpcErrorCode::Canceled, "canceled", 499),
+    (RpcErrorCode::Unknown, "unknown", 500),
+    (RpcErrorCode::InvalidArgument, "invalid_argument", 400),
+    (RpcErrorCode::DeadlineExceeded, "deadline_exceeded", 504),
+    (RpcErrorCode::NotFound, "not_found", 404),
+    (RpcErrorCode::AlreadyExists, "already_exists", 409),
+    (RpcErrorCode::PermissionDenied, "permission_denied", 403),
+    (RpcErrorCode::ResourceExhausted, "resource_exhausted", 429),
+    (RpcErrorCode::FailedPrecondition, "failed_precondition", 400),
+    (RpcErrorCode::Aborted, "aborted", 409),
+    (RpcErrorCode::OutOfRange, "out_of_range", 400),
+    (RpcErrorCode::Unimplemented, "unimplemented", 501),
+    (RpcErrorCode::Internal, "internal", 500),
+    (RpcErrorCode::Unavailable, "unavailable", 503),
+    (RpcErrorCode::DataLoss, "data_loss", 500),
+    (RpcErrorCode::Unauthenticated, "unauthenticated", 401),
+];
+
+fn code() -> impl Strategy<Value = (RpcErrorCode, &'static str, u16)> {
+    prop::sample::select(CODES.to_vec())
+}
+
+/// Details of made up types, each carrying a `StringValue`.
+fn details() -> impl Strategy<Value = Vec<(String, String)>> {
+    prop::collection::vec(("[a-z]{1,8}(\\.[A-Z][A-Za-z]{0,8}){1,3}", ".*"), 0..4)
+}
+
+fn error(code: RpcErrorCode, message: &str, details: &[(String, String)]) -> RpcError {
+    let mut e = RpcError::new(code, message.to_string());
+    for (proto_type, value) in details {
+        e.details.push(RpcErrorDetail::new(
+            proto_type.clone(),
+            &StringValue {
+                value: value.clone(),
+            },
+        ));
+    }
+
+    e
+}
+
+/// Calls a unary and a server-streaming method that both fail with `e`.
+fn call(e: RpcError, streaming: bool) -> Response {
+    let unary = {
+        let e = e.clone();
+        move |req: Request<Body>| {
+            let handler = move |_: Empty| async move { Err::<Empty, _>(e) };
+            RpcHandlerUnary::<Empty, Empty, _, ()>::call(handler, req, ())
+        }
+    };
+    let server_streaming = move |req: Request<Body>| {
+        let handler = move |_: Empty| async move { Err::<stream::Empty<Empty>, _>(e) };
+        RpcHandlerStream::<Empty, Empty, _, ()>::call(handler, req, ())
+    };
+    let router = Router::new()
+        .route("/test.Test/Unary", post(unary))
+        .route("/test.Test/Stream", post(server_streaming));
+
+    // Streaming requests carry their message in an envelope: flags, then the big-endian size.
+    let (path, content_type, body) = if streaming {
+        let body = [&[0, 0, 0, 0, 2][..], b"{}"].concat();
+        ("/test.Test/Stream", "application/connect+json", body)
+    } else {
+        ("/test.Test/Unary", "application/json", b"{}".to_vec())
+    };
+    let req = Request::post(path)
+        .header(header::CONTENT_TYPE, content_type)
+        .header("connect-protocol-version", "1")
+        .body(Body::from(body))
+        .unwrap();
+
+    // Routers are infallible.
+    block_on(router.oneshot(req)).unwrap()
+}
+
+fn body(res: Response) -> Vec<u8> {
+    block_on(to_bytes(res.into_body(), usize::MAX))
+        .unwrap()
+        .to_vec()
+}
+
+/// Checks the JSON of an error, which is the same for unary bodies and EndStreamResponses.
+fn assert_error_json(
+    json: &Value,
+    code: &str,
+    message: &str,
+    details: &[(String, String)],
+) -> Result<(), TestCaseError> {
+    let json = json.as_object().expect("errors are objects");
+    for key in json.keys() {
+        prop_assert!(
+            ["code", "message", "details"].contains(&key.as_str()),
+            "unexpected key {}",
+            key
+        );
+    }
+
+    prop_assert_eq!(json["code"].as_str(), Some(code));
+    prop_assert_eq!(
+        json.get("message")
+            .and_then(Value::as_str)
+            .unwrap_or_default(),
+        message
+    );
+
+    let sent = json
+        .get("details")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+    prop_assert_eq!(sent.len(), details.len());
+    for (sent, (proto_type, value)) in sent.iter().zip(details) {
+        prop_assert_eq!(sent["type"].as_str(), Some(proto_type.as_str()));
+
+        // Base64 (standard alphabet) with the padding left out.
+        let encoded = sent["value"].as_str().expect("detail values are strings");
+        prop_assert!(!encoded.ends_with('='));
+        let bytes = general_purpose::STANDARD_NO_PAD.decode(encoded).unwrap();
+        prop_assert_eq!(&StringValue::decode(bytes.as_slice()).unwrap().value, value);
+    }
+
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn unary_errors(
+        (code, name, status) in code(),
+        message in ".*",
+        details in details(),
+    ) {
+        let res = call(error(code, &message, &details), false);
+
+        prop_assert_eq!(res.status(), StatusCode::from_u16(status).unwrap());
+        prop_assert_eq!(
+            res.headers()[header::CONTENT_TYPE].to_str().unwrap(),
+            "application/json"
+        );
+
+        let json: Value = serde_json::from_slice(&body(res)).unwrap();
+        assert_error_json(&json, name, &message, &details)?;
+    }
+
+    #[test]
+    fn streaming_errors(
+        (code, name, _) in code(),
+        message in ".*",
+        details in details(),
+    ) {
+        let res = call(error(code, &message, &details), true);
+
+        // Streams always succeed at the HTTP level, the error is in the EndStreamResponse.
+        prop_assert_eq!(res.status(), StatusCode::OK);
+        prop_assert_eq!(
+            res.headers()[header::CONTENT_TYPE].to_str().unwrap(),
+            "application/connect+json"
+        );
+
+        let body = body(res);
+        prop_assert!(body.len() >= 5);
+        prop_assert_eq!(body[0], 0x2, "an end-of-stream envelope");
+        let size = u32::from_be_bytes([body[1], body[2], body[3], body[4]]) as usize;
+        prop_assert_eq!(size, body.len() - 5, "exactly one envelope");
+
+        let json: Value = serde_json::from_slice(&body[5..]).unwrap();
+        assert_error_json(&json["error"], name, &message, &details)?;
+    }
+
+    #[test]
+    fn codes_round_trip((code, name, _) in code()) {
+        prop_assert_eq!(code.as_str(), name);
+        prop_assert_eq!(name.parse::<RpcErrorCode>().unwrap(), code.clone());
+        prop_assert_eq!(RpcErrorCode::try_from(i32::from(code.clone())).unwrap(), code);
+    }
+}
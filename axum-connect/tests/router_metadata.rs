@@ -0,0 +1,109 @@
+//! Method descriptors (and the `RpcConfig` of each side) travel with the routes through
+//! `merge_rpc` and `nest_rpc`, where middleware wrapping the whole router can still see them.
+
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    http::{header, Request},
+    response::Response,
+    Router,
+};
+use axum_connect::{
+    config::RpcConfig,
+    descriptor::{MethodDescriptor, MethodKind},
+    error::RpcError,
+    raw::{raw_unary, RpcRawMessage},
+    router::RpcRouterExt,
+    service::RpcContext,
+};
+use futures::executor::block_on;
+use tower::ServiceExt;
+
+const PING: MethodDescriptor = MethodDescriptor {
+    service: "test.PingService",
+    method: "Ping",
+    path: "/test.PingService/Ping",
+    kind: MethodKind::Unary,
+    deprecated: false,
+    idempotent: true,
+    sunset: None,
+    scopes: &[],
+};
+
+const FAIL: MethodDescriptor = MethodDescriptor {
+    service: "test.PingService",
+    method: "Fail",
+    path: "/test.PingService/Fail",
+    kind: MethodKind::Unary,
+    deprecated: false,
+    idempotent: false,
+    sunset: None,
+    scopes: &[],
+};
+
+/// A router of both methods, with an error hook of its own.
+fn ping_router() -> Router {
+    Router::new()
+        .rpc(raw_unary(PING, |_: RpcContext, _: RpcRawMessage| async {
+            Ok(Bytes::new())
+        }))
+        .rpc(raw_unary(FAIL, |_: RpcContext, _: RpcRawMessage| async {
+            Err(RpcError::internal("boom"))
+        }))
+        .rpc_config(RpcConfig::new().error_hook(|mut e, method| {
+            e.message = format!("{}: {}", method.method, e.message);
+            e
+        }))
+}
+
+fn call(router: Router, path: &str) -> Response {
+    let req = Request::post(path)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header("connect-protocol-version", "1")
+        .body(Body::from("{}"))
+        .unwrap();
+
+    // Routers are infallible.
+    block_on(router.oneshot(req)).unwrap()
+}
+
+fn error_message(res: Response) -> String {
+    let body = block_on(to_bytes(res.into_body(), usize::MAX)).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    json["message"].as_str().unwrap_or_default().to_string()
+}
+
+#[test]
+fn merged_routes_keep_their_descriptor() {
+    let router = Router::new().merge_rpc(ping_router());
+    let res = call(router, PING.path);
+
+    assert!(res.status().is_success());
+    assert_eq!(res.extensions().get::<MethodDescriptor>(), Some(&PING));
+}
+
+#[test]
+fn nested_routes_keep_their_descriptor() {
+    let router = Router::new().nest_rpc("/api", ping_router());
+    let res = call(router, &format!("/api{}", PING.path));
+
+    assert!(res.status().is_success());
+    assert_eq!(res.extensions().get::<MethodDescriptor>(), Some(&PING));
+}
+
+#[test]
+fn merged_routes_keep_their_config() {
+    let router = Router::new().merge_rpc(ping_router());
+    let res = call(router, FAIL.path);
+
+    assert_eq!(res.extensions().get::<MethodDescriptor>(), Some(&FAIL));
+    assert_eq!(error_message(res), "Fail: boom");
+}
+
+#[test]
+fn nested_routes_keep_their_config() {
+    let router = Router::new().nest_rpc("/api", ping_router());
+    let res = call(router, &format!("/api{}", FAIL.path));
+
+    assert_eq!(res.extensions().get::<MethodDescriptor>(), Some(&FAIL));
+    assert_eq!(error_message(res), "Fail: boom");
+}
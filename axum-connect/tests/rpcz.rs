@@ -0,0 +1,89 @@
+//! rpcz lists the RPCs of the router it's mounted on, not those of every router in the process.
+
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    http::{header, Request},
+    Router,
+};
+use axum_connect::{
+    descriptor::{MethodDescriptor, MethodKind, RpcMethods},
+    raw::{raw_unary, RpcRawMessage},
+    router::RpcRouterExt,
+    rpcz::rpcz,
+    service::RpcContext,
+};
+use futures::executor::block_on;
+use tower::ServiceExt;
+
+const PING: MethodDescriptor = MethodDescriptor {
+    service: "test.PingService",
+    method: "Ping",
+    path: "/test.PingService/Ping",
+    kind: MethodKind::Unary,
+    deprecated: false,
+    idempotent: true,
+    sunset: None,
+    scopes: &[],
+};
+
+const PONG: MethodDescriptor = MethodDescriptor {
+    service: "test.PongService",
+    method: "Pong",
+    path: "/test.PongService/Pong",
+    kind: MethodKind::Unary,
+    deprecated: false,
+    idempotent: true,
+    sunset: None,
+    scopes: &[],
+};
+
+/// A router of just `method`, with rpcz mounted, recorded into `methods`.
+fn app(methods: &RpcMethods, method: MethodDescriptor) -> Router {
+    methods.record(|| {
+        Router::new()
+            .rpc(raw_unary(method, |_: RpcContext, _: RpcRawMessage| async {
+                Ok(Bytes::new())
+            }))
+            .rpc(rpcz("/debug/rpcz"))
+    })
+}
+
+fn listed_paths(router: Router) -> Vec<String> {
+    let req = Request::get("/debug/rpcz")
+        .header(header::ACCEPT, "application/json")
+        .body(Body::empty())
+        .unwrap();
+
+    // Routers are infallible.
+    let res = block_on(router.oneshot(req)).unwrap();
+    let body = block_on(to_bytes(res.into_body(), usize::MAX)).unwrap();
+    let report: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+
+    report
+        .iter()
+        .map(|method| method["path"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn lists_only_its_own_router() {
+    let (ping_methods, pong_methods) = (RpcMethods::new(), RpcMethods::new());
+    let ping = app(&ping_methods, PING);
+    let pong = app(&pong_methods, PONG);
+
+    assert_eq!(ping_methods.get(), vec![PING]);
+    assert_eq!(pong_methods.get(), vec![PONG]);
+    assert_eq!(listed_paths(ping), vec![PING.path]);
+    assert_eq!(listed_paths(pong), vec![PONG.path]);
+}
+
+#[test]
+fn lists_nothing_outside_of_a_registry() {
+    let router = Router::new()
+        .rpc(raw_unary(PING, |_: RpcContext, _: RpcRawMessage| async {
+            Ok(Bytes::new())
+        }))
+        .rpc(rpcz("/debug/rpcz"));
+
+    assert!(listed_paths(router).is_empty());
+}
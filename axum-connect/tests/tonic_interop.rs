@@ -0,0 +1,105 @@
+//! One message type, used by tonic and by axum-connect alike. Only builds with
+//! `--no-default-features --features prost-0-13,tonic`, tonic 0.12 being built on prost 0.13.
+#![cfg(all(feature = "tonic", feature = "prost-0-13"))]
+
+extern crate prost_0_13 as prost;
+
+use axum::{
+    body::{to_bytes, Body},
+    http::{header, Request},
+    routing::post,
+    Router,
+};
+use axum_connect::{
+    descriptor::{MethodDescriptor, MethodKind},
+    handler::RpcHandlerUnary,
+    prelude::*,
+    tonic_interop::{call_unary, tonic_unary},
+};
+use futures::executor::block_on;
+use serde::{Deserialize, Serialize};
+use tonic::codec::{Codec, ProstCodec};
+use tower::ServiceExt;
+
+/// What prost-build and pbjson-build would generate, minus the noise.
+#[derive(Clone, PartialEq, prost::Message, Serialize, Deserialize)]
+struct HelloRequest {
+    #[prost(string, tag = "1")]
+    name: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message, Serialize, Deserialize)]
+struct HelloResponse {
+    #[prost(string, tag = "1")]
+    message: String,
+}
+
+const SAY_HELLO: MethodDescriptor = MethodDescriptor {
+    service: "hello.HelloWorldService",
+    method: "SayHello",
+    path: "/hello.HelloWorldService/SayHello",
+    kind: MethodKind::Unary,
+    deprecated: false,
+    idempotent: false,
+    sunset: None,
+    scopes: &[],
+};
+
+/// A tonic service method, as a tonic `impl Greeter` would have it.
+async fn tonic_say_hello(
+    request: tonic::Request<HelloRequest>,
+) -> Result<tonic::Response<HelloResponse>, tonic::Status> {
+    Ok(tonic::Response::new(HelloResponse {
+        message: format!("Hello {}!", request.into_inner().name),
+    }))
+}
+
+/// The same method, as a Connect handler.
+async fn connect_say_hello(request: HelloRequest) -> RpcResult<HelloResponse> {
+    Ok(HelloResponse {
+        message: format!("Hello {}!", request.name),
+    })
+}
+
+/// Only compiles if tonic's own codec takes the messages, i.e. they implement tonic's
+/// `prost::Message`.
+fn tonic_codec<C: Codec>(_: C) {}
+
+#[test]
+fn tonic_service_is_served_over_connect() {
+    tonic_codec(ProstCodec::<HelloResponse, HelloRequest>::default());
+
+    let router = Router::new().route(
+        SAY_HELLO.path,
+        post(|mut request: Request<Body>| async move {
+            request.extensions_mut().insert(SAY_HELLO);
+            tonic_unary(tonic_say_hello).call(request, ()).await
+        }),
+    );
+
+    let req = Request::post(SAY_HELLO.path)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header("connect-protocol-version", "1")
+        .body(Body::from(r#"{"name":"tonic"}"#))
+        .unwrap();
+
+    // Routers are infallible.
+    let res = block_on(router.oneshot(req)).unwrap();
+    assert!(res.status().is_success());
+
+    let body = block_on(to_bytes(res.into_body(), usize::MAX)).unwrap();
+    let res: HelloResponse = serde_json::from_slice(&body).unwrap();
+    assert_eq!(res.message, "Hello tonic!");
+}
+
+#[test]
+fn connect_handler_is_called_from_tonic() {
+    tonic_codec(ProstCodec::<HelloResponse, HelloRequest>::default());
+
+    let request = tonic::Request::new(HelloRequest {
+        name: "connect".to_string(),
+    });
+    let res = block_on(call_unary(connect_say_hello, SAY_HELLO, request, ())).unwrap();
+
+    assert_eq!(res.into_inner().message, "Hello connect!");
+}